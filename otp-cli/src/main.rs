@@ -12,25 +12,186 @@
 #![allow(clippy::cast_precision_loss, clippy::too_many_lines, clippy::cognitive_complexity)]
 //! A command-line interface for the OTP encryption tool.
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use log::{info, error};
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, OnceLock, PoisonError};
 use sha2::{Sha256, Digest};
 use std::io::{Read, Write, Seek, SeekFrom};
 use uuid::Uuid;
 
+use otp_core::archive;
+use otp_core::armor;
+use otp_core::audit;
+use otp_core::backup;
+use otp_core::config;
+use otp_core::fsck;
+use otp_core::crypto;
+use otp_core::envelope;
 use otp_core::state_manager;
+use otp_core::sync;
+use otp_core::thresholds;
+use otp_core::timestamp;
+use otp_core::letters;
 use otp_core::pad_generator;
+use otp_core::pad_map;
+use otp_core::paper;
+use otp_core::sequence;
+
+/// Prints a line of human-readable output. `--output-format json` suppresses it; the command
+/// reports its result with [`report`] instead.
+macro_rules! out {
+    () => {
+        if !json_output() {
+            println!();
+        }
+    };
+    ($($arg:tt)*) => {
+        if !json_output() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// The format chosen with `--output-format`, set once at startup.
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// What a command reported so far, printed as one JSON object when it exits.
+static JSON_REPORT: LazyLock<Mutex<JsonReport>> = LazyLock::new(Mutex::default);
+
+/// The parts of the `--output-format json` document collected while a command runs.
+#[derive(Default)]
+struct JsonReport {
+    command: String,
+    result: serde_json::Map<String, serde_json::Value>,
+    warnings: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Runs `f` on the collected report.
+fn with_report<T>(f: impl FnOnce(&mut JsonReport) -> T) -> T {
+    f(&mut JSON_REPORT.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Whether the result is printed as JSON.
+fn json_output() -> bool {
+    OUTPUT_FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Sets `key` of the JSON result. Does nothing for text output.
+fn report(key: &str, value: impl serde::Serialize) {
+    if json_output() {
+        let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+        with_report(|report| report.result.insert(key.to_string(), value));
+    }
+}
+
+/// Warns on stderr, or in the `warnings` of the JSON document.
+fn warn(message: &str) {
+    if json_output() {
+        with_report(|report| report.warnings.push(message.to_string()));
+    } else {
+        eprintln!("WARNING: {message}");
+    }
+}
+
+/// The process exit codes, one per class of failure. The README lists them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// The command succeeded.
+    Success = 0,
+    /// An error was logged that fits no other class.
+    Failure = 1,
+    /// The command line could not be parsed.
+    Usage = 2,
+    /// The vault, a pad, a contact or a file does not exist.
+    NotFound = 3,
+    /// No pad can provide the bytes, or the chosen pad may not encrypt them.
+    PadUnavailable = 4,
+    /// Tampering, corruption, pad reuse or a replayed message, or an operation refused because it would reuse pad bytes.
+    Integrity = 5,
+    /// Reading or writing the vault or another file failed.
+    Storage = 6,
+    /// An argument, input or message is invalid.
+    InvalidInput = 7,
+}
+
+/// Exits with `code`. A success after a logged error exits with [`Exit::Failure`]. For
+/// `--output-format json`, the collected document is printed first.
+fn exit(code: Exit) -> ! {
+    let report = with_report(std::mem::take);
+    let code = if code == Exit::Success && !report.errors.is_empty() { Exit::Failure } else { code } as i32;
+    if json_output() {
+        let document = serde_json::json!({
+            "ok": code == 0,
+            "command": report.command,
+            "exit_code": code,
+            "result": report.result,
+            "warnings": report.warnings,
+            "errors": report.errors,
+        });
+        println!("{document}");
+    }
+    let _ = std::io::stdout().flush();
+    std::process::exit(code)
+}
+
+/// Records every logged error for the exit code and the JSON document, and hands
+/// text output to `env_logger`.
+struct CliLogger {
+    text: Option<env_logger::Logger>,
+}
+
+impl CliLogger {
+    /// Installs the logger. JSON output keeps stderr free of log lines.
+    fn install(format: OutputFormat) {
+        let text = (format == OutputFormat::Text).then(|| env_logger::Builder::from_default_env().build());
+        let level = text.as_ref().map_or(log::LevelFilter::Error, |logger| logger.filter().max(log::LevelFilter::Error));
+        if log::set_boxed_logger(Box::new(Self { text })).is_ok() {
+            log::set_max_level(level);
+        }
+    }
+}
+
+impl log::Log for CliLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() == log::Level::Error || self.text.as_ref().is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if record.level() == log::Level::Error {
+            with_report(|report| report.errors.push(record.args().to_string()));
+        }
+        if let Some(logger) = &self.text {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = &self.text {
+            logger.flush();
+        }
+    }
+}
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
-#[command(after_help = "EXAMPLES:\n  \n# Initialize a new vault\notp-cli --vault ./my_vault vault init\n\n# Generate a new pad\notp-cli --vault ./my_vault pad generate\n\n# Encrypt a file with automatic pad selection\notp-cli --vault ./my_vault encrypt ./my_file.txt\n\n# Encrypt a file with a specific pad\notp-cli --vault ./my_vault encrypt ./my_file.txt --pad-id <PAD_ID>\n\n# Decrypt using a metadata file\notp-cli --vault ./my_vault decrypt --metadata ./my_file.enc.metadata.json --input ./my_file.enc --output ./my_file.txt\n\n# Decrypt manually without a metadata file\notp-cli --vault ./my_vault decrypt --input ./my_file.enc --output ./my_file.txt --pad-id <PAD_ID> --length <FILE_SIZE>")]
+#[command(after_help = "EXAMPLES:\n  \n# Initialize a new vault\notp-cli --vault ./my_vault vault init\n\n# Generate a new pad\notp-cli --vault ./my_vault pad generate\n\n# Encrypt a file with automatic pad selection\notp-cli --vault ./my_vault encrypt ./my_file.txt\n\n# Encrypt a file with a specific pad\notp-cli --vault ./my_vault encrypt ./my_file.txt --pad-id <PAD_ID>\n\n# Decrypt using a metadata file\notp-cli --vault ./my_vault decrypt --metadata ./my_file.enc.metadata.json --input ./my_file.enc --output ./my_file.txt\n\n# Decrypt manually without a metadata file\notp-cli --vault ./my_vault decrypt --input ./my_file.enc --output ./my_file.txt --pad-id <PAD_ID> --length <FILE_SIZE>\n\n# Encrypt piped text into an armored message and decrypt it again\necho secret | otp-cli --vault ./my_vault encrypt - > message.asc\notp-cli --vault ./my_vault decrypt --input message.asc --output -\n\n# List pads as JSON for scripts\notp-cli --vault ./my_vault pad list --output-format json")]
 struct Cli {
-    /// The path to the OTP vault.
+    /// The path to the OTP vault. Defaults to the configured vault, then `./.otp_vault`.
     #[arg(long, global = true)]
     vault: Option<PathBuf>,
 
+    /// The config file profile to use, e.g. `work`
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Print the result as one JSON object on stdout, including for errors, e.g. `otp-cli pad list --output-format json`.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -47,50 +208,115 @@ enum Commands {
         #[command(subcommand)]
         command: PadCommands,
     },
+    /// Manage the partners pads are shared with
+    Contact {
+        #[command(subcommand)]
+        command: ContactCommands,
+    },
     /// Encrypt a file using a specified pad
-    Encrypt {
-        /// Path to the input file to encrypt
-        #[arg()]
-        input: PathBuf,
+    Encrypt(EncryptArgs),
+    /// Decrypt a file using a specified pad
+    Decrypt(DecryptArgs),
+}
 
-        /// Path to the output file to save the encrypted content. If omitted, uses the input filename with a .enc extension.
-        #[arg(short, long, value_name = "OUTPUT_FILE")]
-        output: Option<PathBuf>,
+/// Arguments of the `encrypt` command.
+#[derive(clap::Args)]
+struct EncryptArgs {
+    /// Path to the input file or directory to encrypt, or `-` to read from stdin
+    #[arg(required_unless_present = "text")]
+    input: Option<PathBuf>,
 
-        /// The ID of the pad to use for encryption. If omitted, a suitable pad will be selected automatically.
-        #[arg(long, value_name = "PAD_ID")]
-        pad_id: Option<String>,
+    /// Encrypt a directory and everything below it as a single archive
+    #[arg(short, long)]
+    recursive: bool,
 
-        /// [ADVANCED] Specify a starting offset in bytes for the pad segment.
-        #[arg(long)]
-        offset: Option<usize>,
-    },
-    /// Decrypt a file using a specified pad
-    Decrypt {
-        /// Path to the input file to decrypt
-        #[arg(short, long)]
-        input: PathBuf,
+    /// Encrypt this text instead of an input file
+    #[arg(long, conflicts_with = "input")]
+    text: Option<String>,
 
-        /// Path to the output file to save the decrypted content
-        #[arg(short, long)]
-        output: PathBuf,
+    /// Path to the output file to save the encrypted content, or `-` for stdout. If omitted, uses the input filename with a .enc extension, or stdout for piped input and --text.
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    output: Option<PathBuf>,
 
-        /// Path to the ciphertext metadata file. If omitted, --pad-id and --length must be provided.
-        #[arg(long, value_name = "METADATA_FILE")]
-        metadata: Option<PathBuf>,
+    /// The ID of the pad to use for encryption. If omitted, a suitable pad will be selected automatically.
+    #[arg(long, value_name = "PAD_ID")]
+    pad_id: Option<String>,
 
-        /// The ID of the pad to use for decryption. Required if --metadata is not used.
-        #[arg(long, value_name = "PAD_ID", required_if_eq("metadata", "None"))]
-        pad_id: Option<String>,
+    /// Encrypt for this contact, selecting only pads shared with them.
+    #[arg(long, value_name = "CONTACT")]
+    to: Option<String>,
 
-        /// The length of the pad segment to use. Required if --metadata is not used.
-        #[arg(long, value_name = "LENGTH", required_if_eq("metadata", "None"))]
-        length: Option<usize>,
-        
-        /// The starting offset in bytes for the pad segment. Defaults to 0 if not provided.
-        #[arg(long, value_name = "OFFSET", default_value_t = 0)]
-        offset: usize,
-    },
+    /// [ADVANCED] Specify a starting offset in bytes for the pad segment.
+    #[arg(long)]
+    offset: Option<usize>,
+
+    /// The cipher mode. `letters` and `digits` produce hand-decryptable 5-character groups.
+    #[arg(long, value_enum, default_value_t = CipherMode::Bytes)]
+    mode: CipherMode,
+
+    /// The encoding of the written ciphertext. Defaults to `raw` for files and `armor` for stdout.
+    #[arg(long, value_enum)]
+    encoding: Option<CiphertextEncoding>,
+
+    /// Upper bound in bytes for piped input, used to reserve pad space up front. If omitted, the largest free gap of a pad is reserved.
+    #[arg(long, value_name = "BYTES")]
+    max_size: Option<usize>,
+
+    /// Do not wrap the payload in the encrypted inner header, so the ciphertext is exactly as long as the input.
+    #[arg(long)]
+    no_header: bool,
+
+    /// Path to save the decryption metadata to. Defaults to the output path with a .metadata.json extension.
+    #[arg(long, value_name = "METADATA_FILE")]
+    metadata_out: Option<PathBuf>,
+}
+
+/// Arguments of the `decrypt` command.
+#[derive(clap::Args)]
+struct DecryptArgs {
+    /// Path to the input file to decrypt, or `-` to read from stdin
+    #[arg(short, long)]
+    input: PathBuf,
+
+    /// Path to the output file to save the decrypted content, or `-` for stdout. Archives are extracted into this directory. If omitted or a directory, the original file name is restored.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// List the contents of an encrypted archive instead of extracting it
+    #[arg(long)]
+    list: bool,
+
+    /// Treat the plaintext as an archive. Ignored if --metadata is used or the input is armored.
+    #[arg(long)]
+    archive: bool,
+
+    /// Path to the ciphertext metadata file. If omitted, --pad-id and --length must be provided unless the input is armored.
+    #[arg(long, value_name = "METADATA_FILE")]
+    metadata: Option<PathBuf>,
+
+    /// The ID of the pad to use for decryption. Required if --metadata is not used.
+    #[arg(long, value_name = "PAD_ID", required_if_eq("metadata", "None"))]
+    pad_id: Option<String>,
+
+    /// The length of the pad segment to use. Required if --metadata is not used. For a message with an inner header, the size of the original file works too.
+    #[arg(long, value_name = "LENGTH", required_if_eq("metadata", "None"))]
+    length: Option<usize>,
+
+    /// The starting offset in bytes for the pad segment. Defaults to 0 if not provided.
+    #[arg(long, value_name = "OFFSET", default_value_t = 0)]
+    offset: usize,
+
+    /// The cipher mode used for encryption. Ignored if --metadata is used.
+    #[arg(long, value_enum, default_value_t = CipherMode::Bytes)]
+    mode: CipherMode,
+
+    /// The encoding of the ciphertext. Ignored if --metadata is used; armored input is detected automatically.
+    #[arg(long, value_enum)]
+    encoding: Option<CiphertextEncoding>,
+
+    /// Decrypt a message even if it was received before, e.g. to read an old message again.
+    #[arg(long)]
+    allow_replay: bool,
 }
 
 #[derive(Subcommand)]
@@ -99,28 +325,470 @@ enum VaultCommands {
     Init,
     /// Show the status of the vault
     Status,
+    /// Exchange pad usage with a partner holding the same pads
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommands,
+    },
+    /// Show the audit log of vault operations and verify its hash chain
+    #[command(after_help = "EXAMPLES:\n  \n# Everything that happened to one pad\notp-cli --vault ./my_vault vault log --pad <PAD_ID>\n\n# All encryptions in July 2025\notp-cli --vault ./my_vault vault log --op encrypt --since 2025-07-01 --until 2025-07-31")]
+    Log {
+        /// Only show entries about this pad
+        #[arg(long, value_name = "PAD_ID")]
+        pad: Option<String>,
+        /// Only show entries of this operation, e.g. `encrypt` or `export`
+        #[arg(long, value_parser = parse_operation)]
+        op: Option<audit::Operation>,
+        /// Only show entries from this day on, as YYYY-MM-DD (UTC)
+        #[arg(long, value_parser = parse_day)]
+        since: Option<u64>,
+        /// Only show entries up to and including this day, as YYYY-MM-DD (UTC)
+        #[arg(long, value_parser = parse_day)]
+        until: Option<u64>,
+    },
+    /// Check the vault state against the pad files on disk
+    #[command(after_help = "EXAMPLES:\n  \n# Report problems without changing anything\notp-cli --vault ./my_vault vault fsck\n\n# Fix what is safe to fix and quarantine the rest\notp-cli --vault ./my_vault vault fsck --repair")]
+    Fsck {
+        /// Fix safe problems and quarantine pads and files that cannot be fixed
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Show or set when to warn about low pad material, and whether to generate local pads
+    #[command(after_help = "EXAMPLES:\n  \n# Warn below 10 MB left or when no 1 MB message fits anymore\notp-cli --vault ./my_vault vault thresholds --warn-remaining 10MB --warn-gap 1MB\n\n# Keep local pads for self-encryption topped up with 16 MB pads\notp-cli --vault ./my_vault vault thresholds --auto-generate 16MB")]
+    Thresholds {
+        /// Warn when fewer bytes than this are left, e.g. `10MB`
+        #[arg(long, value_parser = thresholds::parse_size)]
+        warn_remaining: Option<usize>,
+        /// Warn when the largest message that fits is smaller than this, e.g. `1MB`
+        #[arg(long, value_parser = thresholds::parse_size)]
+        warn_gap: Option<usize>,
+        /// Generate local pads of this size whenever the local pads cross a threshold
+        #[arg(long, value_parser = thresholds::parse_size)]
+        auto_generate: Option<usize>,
+        /// Remove all thresholds and the replenishment policy
+        #[arg(long, conflicts_with_all = ["warn_remaining", "warn_gap", "auto_generate"])]
+        clear: bool,
+    },
+    /// Take a consistent snapshot of the state, pads and audit log
+    #[command(after_help = "EXAMPLES:\n  \n# Back up the vault, even while otp-web is running\notp-cli --vault ./my_vault vault backup --out ./backups/2025-07-17\n\n# Restore it later\notp-cli --vault ./my_vault vault restore ./backups/2025-07-17")]
+    Backup {
+        /// The new or empty directory to write the snapshot to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Replace the vault's state and pads with a snapshot taken by 'vault backup'
+    Restore {
+        /// The snapshot directory
+        snapshot: PathBuf,
+        /// Restore even if the vault has used pad bytes or pads the snapshot does not know about
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+#[command(after_help = "EXAMPLES:\n  \n# Tell your partner which pad bytes you used since the last sync\notp-cli --vault ./my_vault vault sync export --out usage.json\n\n# Merge the partner's usage into your vault\notp-cli --vault ./my_vault vault sync import usage.json")]
+enum SyncCommands {
+    /// Write authenticated receipts of the pad ranges used since the last export
+    Export {
+        /// Path to write the sync bundle to, or `-` for stdout
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Only export usage of this pad
+        #[arg(long, value_name = "PAD_ID")]
+        pad_id: Option<String>,
+        /// Export all used ranges, including those already sent or received
+        #[arg(long)]
+        all: bool,
+    },
+    /// Verify a partner's sync bundle and merge its ranges into the vault
+    Import {
+        /// Path to the partner's sync bundle, or `-` for stdin
+        input: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
-#[command(after_help = "EXAMPLES:\n  \n# Generate a single 10MB pad\notp-cli --vault ./my_vault pad generate --size 10\n\n# Generate 5 pads of 1MB each\notp-cli --vault ./my_vault pad generate --count 5")]
+#[command(after_help = "EXAMPLES:\n  \n# Generate a single 10MB pad\notp-cli --vault ./my_vault pad generate --size 10\n\n# Generate 5 pads of 1MB each\notp-cli --vault ./my_vault pad generate --count 5\n\n# Print a pad for an air-gapped partner\notp-cli --vault ./my_vault pad print <PAD_ID> --output pad.txt\n\n# Import a typed-in or scanned pad\notp-cli --vault ./my_vault pad import --from-text pad.txt\n\n# Import a pad from photos or scans of the codes printed by 'pad qr'\notp-cli --vault ./my_vault pad import --from-qr-images scans/*.png\n\n# Share a pad so each partner consumes it from a different end\notp-cli --vault ./my_vault pad print <PAD_ID> --pair --output pad.txt\n\n# Share a pad among three people, then import it as one of them\notp-cli --vault ./my_vault pad group <PAD_ID> --members alice,bob,carol --member alice --layout stripes\notp-cli --vault ./bobs_vault pad import --from-text pad.txt --member bob")]
 enum PadCommands {
     /// Generate a new one-time pad file
     Generate {
-        /// The size of the pad in megabytes (MB)
-        #[arg(short, long, default_value_t = 1)]
-        size: usize,
+        /// The size of the pad in megabytes (MB). Defaults to the configured size, then 1.
+        #[arg(short, long)]
+        size: Option<usize>,
         /// The number of pads to generate
         #[arg(short, long, default_value_t = 1)]
         count: u32,
+        /// Keep the pads in this vault only, e.g. for encrypting your own backups
+        #[arg(long)]
+        local: bool,
+        /// Stop encrypting with the pads from this day on, as `YYYY-MM-DD` (UTC)
+        #[arg(long, value_name = "DAY", value_parser = parse_day)]
+        expires: Option<u64>,
+        /// Label the pads; repeat for several labels
+        #[arg(long = "label", value_name = "LABEL", value_parser = parse_label)]
+        labels: Vec<String>,
+        /// A note to keep with the pads
+        #[arg(long)]
+        note: Option<String>,
+        /// Hand out the pads' bytes front to back from a watermark instead of tracking each message
+        #[arg(long)]
+        watermark: bool,
+    },
+    /// List the pads in the vault
+    #[command(after_help = "EXAMPLES:\n  \n# The usable pads of a project, fullest first\notp-cli --vault ./my_vault pad list --label project-x --sort remaining --available-only")]
+    List {
+        /// Only list pads with this label
+        #[arg(long)]
+        label: Option<String>,
+        /// The order of the listing
+        #[arg(long, value_enum, default_value_t = PadSortArg::Id)]
+        sort: PadSortArg,
+        /// Only list pads this vault can still encrypt with
+        #[arg(long)]
+        available_only: bool,
+    },
+    /// Add or remove a pad's labels
+    #[command(group(clap::ArgGroup::new("change").required(true).multiple(true).args(["add", "remove"])))]
+    Label {
+        /// The ID of the pad
+        pad_id: String,
+        /// A label to add; repeat for several
+        #[arg(long, value_name = "LABEL", value_parser = parse_label)]
+        add: Vec<String>,
+        /// A label to remove; repeat for several
+        #[arg(long, value_name = "LABEL")]
+        remove: Vec<String>,
+    },
+    /// Set or clear a pad's note
+    Note {
+        /// The ID of the pad
+        pad_id: String,
+        /// The note. Omit it to clear the note.
+        text: Option<String>,
+    },
+    /// Show a pad and the history of its used segments
+    Show {
+        /// The ID of the pad to show
+        pad_id: String,
+    },
+    /// Draw where a pad's free bytes are and list its free ranges
+    Map {
+        /// The ID of the pad to map
+        pad_id: String,
+        /// The number of cells in the usage bar
+        #[arg(long, default_value_t = 64)]
+        width: usize,
+        /// Draw the bar with ASCII characters instead of Unicode blocks
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Record a pad range as consumed without encrypting, e.g. when it was used by hand
+    #[command(after_help = "EXAMPLES:\n  \n# Record bytes a partner used for a handwritten message\notp-cli --vault ./my_vault pad burn <PAD_ID> --range 1000..1200 --reason \"handwritten note from Bob\"")]
+    Burn {
+        /// The ID of the pad
+        pad_id: String,
+        /// The byte range to burn, as `start..end`
+        #[arg(long, value_parser = parse_range)]
+        range: Range<usize>,
+        /// Why the range is burned
+        #[arg(long)]
+        reason: String,
+    },
+    /// Stop encrypting with a pad, now or from a given day on; old messages still decrypt
+    Retire {
+        /// The ID of the pad to retire
+        pad_id: String,
+        /// Why the pad is retired
+        #[arg(long)]
+        reason: Option<String>,
+        /// Set the pad to expire on this day, as `YYYY-MM-DD` (UTC), instead of retiring it now
+        #[arg(long, value_name = "DAY", value_parser = parse_day)]
+        on: Option<u64>,
+    },
+    /// Mark a pad as compromised because a copy may have been exposed
+    Revoke {
+        /// The ID of the pad to revoke
+        pad_id: String,
+        /// What happened, e.g. `laptop stolen`
+        #[arg(long)]
+        reason: String,
     },
-    /// List all pads in the vault
-    List,
     /// Delete a pad from the vault
     Delete {
         /// The ID of the pad to delete
         #[arg(long)]
         pad_id: String,
     },
+    /// Render pad bytes as numbered, checksummed lines for printing on paper
+    Print {
+        /// The ID of the pad to print
+        pad_id: String,
+        /// The byte range to print, as `start..end`. Defaults to the whole pad. A partial printout cannot be imported.
+        #[arg(long, value_parser = parse_range)]
+        range: Option<Range<usize>>,
+        /// The text encoding of the pad bytes
+        #[arg(long, value_enum, default_value_t = PrintEncoding::Hex)]
+        encoding: PrintEncoding,
+        /// The output document format
+        #[arg(long, value_enum, default_value_t = PrintFormat::Text)]
+        format: PrintFormat,
+        /// Path to write the document to. If omitted, the document is written to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Print the hand-cipher key groups for `letters` or `digits` mode instead of raw bytes
+        #[arg(long, value_enum)]
+        key_sheet: Option<CipherMode>,
+        /// Pair the pad: this vault becomes the initiator and the printout tells the partner to act as responder
+        #[arg(long)]
+        pair: bool,
+    },
+    /// Render pad bytes as a numbered sequence of QR-code SVG images
+    Qr {
+        /// The ID of the pad to render
+        pad_id: String,
+        /// The byte range to render, as `start..end`. Defaults to the whole pad. Partial codes cannot be imported.
+        #[arg(long, value_parser = parse_range)]
+        range: Option<Range<usize>>,
+        /// The text encoding of the pad bytes inside each code
+        #[arg(long, value_enum, default_value_t = PrintEncoding::Base32)]
+        encoding: PrintEncoding,
+        /// The number of pad lines carried by each QR code
+        #[arg(long, default_value_t = 8)]
+        lines_per_code: usize,
+        /// Directory to write the numbered SVG files into
+        #[arg(long)]
+        out_dir: PathBuf,
+        /// Pair the pad: this vault becomes the initiator and the codes tell the partner to act as responder
+        #[arg(long)]
+        pair: bool,
+    },
+    /// Set which part of a shared pad this vault may consume
+    #[command(group(clap::ArgGroup::new("policy").required(true).args(["role", "range", "clear"])))]
+    Pair {
+        /// The ID of the pad to configure
+        pad_id: String,
+        /// Consume from the front as initiator or from the back as responder
+        #[arg(long, value_enum)]
+        role: Option<PairRole>,
+        /// Only consume bytes within this range, as `start..end`
+        #[arg(long, value_parser = parse_range)]
+        range: Option<Range<usize>>,
+        /// Remove the policy and allow the whole pad again
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Switch how a pad hands out its bytes
+    #[command(after_help = "EXAMPLES:\n  \n# Consume a pad front to back, keeping only a watermark\notp-cli --vault ./my_vault pad mode <PAD_ID> watermark")]
+    Mode {
+        /// The ID of the pad to switch
+        pad_id: String,
+        /// `watermark` hands out bytes front to back and never below the last used byte;
+        /// `free-ranges` may use any unused range and records every message
+        #[arg(value_enum)]
+        mode: AllocationModeArg,
+    },
+    /// Import a pad from printed text or QR-code images
+    #[command(group(clap::ArgGroup::new("source").required(true).multiple(true).args(["from_text", "from_qr_images"])))]
+    Import {
+        /// Text files holding the printed pages or the decoded QR-code payloads
+        #[arg(long, num_args = 1..)]
+        from_text: Vec<PathBuf>,
+        /// PNG or JPEG photos or scans of the QR codes printed by `pad qr`, in any order
+        #[arg(long, num_args = 1..)]
+        from_qr_images: Vec<PathBuf>,
+        /// Your member name, required when importing a group pad
+        #[arg(long)]
+        member: Option<String>,
+    },
+    /// Share a pad among a group, giving each member a separate part to consume
+    #[command(group(clap::ArgGroup::new("membership").required(true).args(["members", "clear"])))]
+    Group {
+        /// The ID of the pad to configure
+        pad_id: String,
+        /// All member names in a fixed order, comma-separated
+        #[arg(long, value_delimiter = ',', requires = "member")]
+        members: Vec<String>,
+        /// The member name of this vault
+        #[arg(long)]
+        member: Option<String>,
+        /// How the pad is divided among the members
+        #[arg(long, value_enum, default_value_t = GroupLayoutArg::Ranges)]
+        layout: GroupLayoutArg,
+        /// The stripe width in bytes for the `stripes` layout
+        #[arg(long, default_value_t = 65536)]
+        stripe_width: usize,
+        /// Stop sharing the pad as a group
+        #[arg(long)]
+        clear: bool,
+    },
+}
+
+/// The order of `pad list`.
+#[derive(Clone, Copy, ValueEnum)]
+enum PadSortArg {
+    /// By pad ID.
+    Id,
+    /// Most bytes left first.
+    Remaining,
+    /// Largest pad first.
+    Size,
+    /// Oldest first, by when the pad was generated or imported.
+    Added,
+}
+
+impl From<PadSortArg> for state_manager::PadSort {
+    fn from(sort: PadSortArg) -> Self {
+        match sort {
+            PadSortArg::Id => Self::Id,
+            PadSortArg::Remaining => Self::Remaining,
+            PadSortArg::Size => Self::Size,
+            PadSortArg::Added => Self::Added,
+        }
+    }
+}
+
+/// How a group pad is divided among its members.
+#[derive(Clone, Copy, ValueEnum)]
+enum GroupLayoutArg {
+    /// One contiguous range per member.
+    Ranges,
+    /// Interleaved stripes taken in turn; each message must fit into one stripe.
+    Stripes,
+}
+
+#[derive(Subcommand)]
+#[command(after_help = "EXAMPLES:\n  \n# Record that a pad is shared with Alice, who consumes it from the back\notp-cli --vault ./my_vault contact add alice --pad <PAD_ID> --role initiator --notes \"Signal: +1 555 0100\"\n\n# Encrypt for Alice with one of her pads\notp-cli --vault ./my_vault encrypt ./my_file.txt --to alice")]
+enum ContactCommands {
+    /// Add a contact, or link more pads to an existing one
+    Add {
+        /// The contact's name
+        name: String,
+        /// A pad shared with the contact; repeat for several pads
+        #[arg(long = "pad", value_name = "PAD_ID")]
+        pads: Vec<String>,
+        /// Free-form notes about the contact
+        #[arg(long)]
+        notes: Option<String>,
+        /// The side this vault takes on the shared pads
+        #[arg(long, value_enum)]
+        role: Option<PairRole>,
+    },
+    /// List all contacts
+    List,
+    /// Show a contact and the state of the pads shared with them
+    Show {
+        /// The contact's name
+        name: String,
+    },
+}
+
+/// The side a vault takes on a pad shared between two partners.
+#[derive(Clone, Copy, ValueEnum)]
+enum PairRole {
+    /// Consumes the pad from the front.
+    Initiator,
+    /// Consumes the pad from the back.
+    Responder,
+}
+
+impl From<PairRole> for state_manager::Direction {
+    fn from(role: PairRole) -> Self {
+        match role {
+            PairRole::Initiator => Self::Front,
+            PairRole::Responder => Self::Back,
+        }
+    }
+}
+
+/// How a pad hands out its bytes.
+#[derive(Clone, Copy, ValueEnum)]
+enum AllocationModeArg {
+    /// Any unused range, with a record of every message.
+    FreeRanges,
+    /// Front to back from a watermark.
+    Watermark,
+}
+
+impl From<AllocationModeArg> for state_manager::AllocationMode {
+    fn from(mode: AllocationModeArg) -> Self {
+        match mode {
+            AllocationModeArg::FreeRanges => Self::FreeRanges,
+            AllocationModeArg::Watermark => Self::Watermark,
+        }
+    }
+}
+
+/// The text encoding used when printing pad bytes.
+#[derive(Clone, Copy, ValueEnum)]
+enum PrintEncoding {
+    Hex,
+    Base32,
+}
+
+impl From<PrintEncoding> for paper::Encoding {
+    fn from(encoding: PrintEncoding) -> Self {
+        match encoding {
+            PrintEncoding::Hex => Self::Hex,
+            PrintEncoding::Base32 => Self::Base32,
+        }
+    }
+}
+
+/// How plaintext is combined with pad material.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CipherMode {
+    /// Byte-wise XOR of arbitrary data.
+    #[default]
+    Bytes,
+    /// Letters A-Z with the reciprocal DIANA table.
+    Letters,
+    /// Digits 0-9 via the straddling checkerboard.
+    Digits,
+}
+
+impl CipherMode {
+    /// The hand-cipher table for the letter and digit modes.
+    const fn table(self) -> Option<letters::Table> {
+        match self {
+            Self::Bytes => None,
+            Self::Letters => Some(letters::Table::Diana),
+            Self::Digits => Some(letters::Table::Checkerboard),
+        }
+    }
+}
+
+/// How ciphertext is written out.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CiphertextEncoding {
+    /// Unencoded binary.
+    #[default]
+    Raw,
+    /// Lowercase hexadecimal.
+    Hex,
+    /// Standard base64.
+    Base64,
+    /// Base64 wrapped in an armor block that also carries the decryption metadata.
+    Armor,
+}
+
+/// How `otp-cli` prints its results.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object with `ok`, `command`, `exit_code`, `result`, `warnings` and `errors`.
+    Json,
+}
+
+/// The document format produced by `pad print`.
+#[derive(Clone, Copy, ValueEnum)]
+enum PrintFormat {
+    Text,
+    Svg,
 }
 
 /// Metadata stored alongside the ciphertext to enable correct decryption.
@@ -130,6 +798,12 @@ struct CiphertextMetadata {
     start_byte: usize,
     length: usize,
     ciphertext_hash: String,
+    #[serde(default)]
+    mode: CipherMode,
+    #[serde(default)]
+    encoding: CiphertextEncoding,
+    #[serde(default)]
+    archive: bool,
 }
 
 /// Information needed for decryption, whether from metadata or command line arguments.
@@ -137,39 +811,122 @@ struct DecryptionInfo {
     pad_id: String,
     start_byte: usize,
     length: usize,
+    mode: CipherMode,
+    archive: bool,
+}
+
+impl From<CiphertextMetadata> for DecryptionInfo {
+    fn from(meta: CiphertextMetadata) -> Self {
+        Self {
+            pad_id: meta.pad_id,
+            start_byte: meta.start_byte,
+            length: meta.length,
+            mode: meta.mode,
+            archive: meta.archive,
+        }
+    }
 }
 
 fn main() {
-    env_logger::init();
-    let cli = Cli::parse();
-    
-    let vault_path = get_vault_path(&cli);
+    let cli = parse_args();
+    let flags = config::Settings { vault: cli.vault.clone(), ..config::Settings::default() };
+    let config = config::load(cli.profile.as_deref(), flags).unwrap_or_else(|e| {
+        error!("Invalid configuration: {e}");
+        exit(Exit::InvalidInput);
+    });
+
+    let vault_path = get_vault_path(&cli, &config);
 
     match &cli.command {
         Commands::Vault { command } => handle_vault_command(command, &vault_path),
-        Commands::Pad { command } => handle_pad_command(command, &vault_path),
-        Commands::Encrypt { input, output, pad_id, offset } => handle_encrypt_command(input, output.as_ref(), pad_id.as_deref(), offset.unwrap_or(0), &vault_path),
-        Commands::Decrypt { input, output, metadata, pad_id, length, offset } => handle_decrypt_command(input, output, metadata.as_ref(), pad_id.as_deref(), length.unwrap_or(0), *offset, &vault_path),
+        Commands::Pad { command } => handle_pad_command(command, &vault_path, &config),
+        Commands::Contact { command } => handle_contact_command(command, &vault_path),
+        Commands::Encrypt(args) => handle_encrypt_command(args, &vault_path, &config),
+        Commands::Decrypt(args) => handle_decrypt_command(args, &vault_path),
+    }
+    if uses_pad_material(&cli.command) {
+        let mut state = state_manager::load_state(&vault_path).unwrap_or_default();
+        if let Some(checked) = check_pad_material(&vault_path, &mut state) {
+            for warning in &checked.warnings {
+                warn(&format!("{warning}."));
+            }
+        }
     }
+    exit(Exit::Success);
 }
 
-fn get_vault_path(cli: &Cli) -> PathBuf {
-    if matches!(&cli.command, Commands::Vault { command: VaultCommands::Init }) {
-        cli.vault.clone().unwrap_or_else(|| {
-            error!("The --vault path is required for 'vault init'");
-            std::process::exit(1);
-        })
-    } else {
-        let path = cli.vault.clone().unwrap_or_else(|| {
-            error!("A --vault path is required for this command.");
-            std::process::exit(1);
-        });
-        if !path.exists() {
-            error!("Vault path '{}' does not exist. Please create it with 'vault init'.", path.display());
-            std::process::exit(1);
+/// Whether `command` can use up pad material, or changes the thresholds, so that the
+/// thresholds are checked and local pads replenished after it.
+const fn uses_pad_material(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Encrypt(_)
+            | Commands::Decrypt(_)
+            | Commands::Vault { command: VaultCommands::Thresholds { .. } | VaultCommands::Sync { command: SyncCommands::Import { .. } } }
+            | Commands::Pad { command: PadCommands::Burn { .. } | PadCommands::Mode { .. } }
+    )
+}
+
+/// Parses the command line and sets up output and logging. A usage error exits with
+/// code 2, as a JSON document if the arguments ask for one.
+fn parse_args() -> Cli {
+    let matches = Cli::command().try_get_matches().unwrap_or_else(|e| {
+        if e.use_stderr() && requests_json(std::env::args().skip(1)) {
+            let _ = OUTPUT_FORMAT.set(OutputFormat::Json);
+            with_report(|report| report.errors.push(e.render().to_string().trim().to_string()));
+            exit(Exit::Usage);
         }
-        path
+        e.exit()
+    });
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let _ = OUTPUT_FORMAT.set(cli.output_format);
+    CliLogger::install(cli.output_format);
+    let mut names = Vec::new();
+    let mut current = &matches;
+    while let Some((name, sub)) = current.subcommand() {
+        names.push(name);
+        current = sub;
+    }
+    with_report(|report| report.command = names.join(" "));
+    cli
+}
+
+/// Whether the arguments ask for `--output-format json`.
+fn requests_json(args: impl Iterator<Item = String>) -> bool {
+    let args: Vec<String> = args.collect();
+    args.iter().any(|arg| arg == "--output-format=json") || args.windows(2).any(|pair| pair[0] == "--output-format" && pair[1] == "json")
+}
+
+/// Replenishes local pads if the vault asks for it and checks the low-material thresholds.
+fn check_pad_material(vault_path: &Path, state: &mut state_manager::VaultState) -> Option<thresholds::Report> {
+    if state.thresholds.is_empty() {
+        return None;
+    }
+    match thresholds::after_operation(vault_path, state, audit::Interface::Cli) {
+        Ok(checked) => {
+            for pad_id in &checked.generated {
+                if !json_output() {
+                    eprintln!("Generated local pad '{pad_id}' because pad material ran low.");
+                }
+            }
+            report("generated_pads", &checked.generated);
+            Some(checked)
+        }
+        Err(e) => {
+            error!("Failed to replenish pads: {e}");
+            None
+        }
+    }
+}
+
+/// Returns the vault to work on, from `--vault`, the environment or the config file.
+fn get_vault_path(cli: &Cli, config: &config::Config) -> PathBuf {
+    let path = config.vault.clone();
+    if !matches!(&cli.command, Commands::Vault { command: VaultCommands::Init | VaultCommands::Restore { .. } }) && !path.exists() {
+        error!("Vault path '{}' does not exist. Please create it with 'vault init'.", path.display());
+        exit(Exit::NotFound);
     }
+    path
 }
 
 fn handle_vault_command(command: &VaultCommands, vault_path: &Path) {
@@ -178,23 +935,24 @@ fn handle_vault_command(command: &VaultCommands, vault_path: &Path) {
             info!("Initializing new vault at '{}'", vault_path.display());
             if let Err(e) = fs::create_dir_all(vault_path.join("pads/available")) {
                 error!("Failed to create pads directory: {e}");
-                std::process::exit(1);
+                exit(Exit::Storage);
             }
             if let Err(e) = fs::create_dir_all(vault_path.join("pads/used")) {
                 error!("Failed to create used pads directory: {e}");
-                std::process::exit(1);
+                exit(Exit::Storage);
             }
             let initial_state = state_manager::VaultState::default();
             if let Err(e) = state_manager::save_state(vault_path, &initial_state) {
                 error!("Failed to save initial state: {e}");
-                std::process::exit(1);
+                exit(Exit::Storage);
             }
             info!("Vault initialized successfully.");
+            report("vault", vault_path);
         }
         VaultCommands::Status => {
             let state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
                 error!("Failed to load vault state: {e}");
-                std::process::exit(1);
+                exit(Exit::Storage);
             });
             let available_pads = state.pads.values().filter(|p| !p.is_fully_used).count();
             let used_pads = state.pads.len() - available_pads;
@@ -206,67 +964,597 @@ fn handle_vault_command(command: &VaultCommands, vault_path: &Path) {
             let total_used_bytes: usize = state.pads.values().map(state_manager::Pad::total_used_bytes).sum();
             let total_used_mb = total_used_bytes as f64 / (1024.0 * 1024.0);
 
-            println!("Vault Status for: {}", vault_path.display());
-            println!("{:-<40}", "");
-            println!("Total Pads: {total_pads}");
-            println!("  - Available: {available_pads}");
-            println!("  - Fully Used: {used_pads}");
-            println!();
-            println!("Total Storage: {total_storage_mb:.2} MB");
-            println!("  - Used: {total_used_mb:.2} MB");
-            println!("  - Remaining: {:.2} MB", total_storage_mb - total_used_mb);
-        }
-    }
-}
-
-fn handle_pad_command(command: &PadCommands, vault_path: &Path) {
-    let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
-        error!("Failed to load vault state: {e}");
-        std::process::exit(1);
-    });
-    match command {
-        PadCommands::Generate { size, count } => {
-            info!("Generating {count} new pad(s) of {size} MB each...");
-            for _ in 0..*count {
-                let pad_id = Uuid::new_v4().to_string();
-                let file_name = format!("{pad_id}.pad");
-                let pad_path = vault_path.join("pads/available").join(&file_name);
-                let size_in_bytes = size * 1024 * 1024;
+            out!("Vault Status for: {}", vault_path.display());
+            out!("{:-<40}", "");
+            out!("Total Pads: {total_pads}");
+            out!("  - Available: {available_pads}");
+            out!("  - Fully Used: {used_pads}");
+            out!();
+            out!("Total Storage: {total_storage_mb:.2} MB");
+            out!("  - Used: {total_used_mb:.2} MB");
+            out!("  - Remaining: {:.2} MB", total_storage_mb - total_used_mb);
 
-                let pad_path_str = pad_path.to_str().unwrap_or_else(|| {
-                    error!("Pad path contains invalid UTF-8");
-                    std::process::exit(1);
-                });
-                
-                if let Err(e) = pad_generator::generate_pad(pad_path_str, size_in_bytes) {
-                    error!("Failed to generate pad file for ID {pad_id}: {e}");
-                } else {
-                    state.add_pad(pad_id.clone(), file_name, size_in_bytes);
-                    println!("{pad_id}");
-                }
+            let material = thresholds::Material::of(&state, |_| true);
+            out!();
+            out!("Pad Material:");
+            out!("  - Usable: {}", thresholds::format_size(material.remaining));
+            out!("  - Largest Message: {}", thresholds::format_size(material.largest_gap));
+            let warnings = state.thresholds.check(material);
+            for warning in &warnings {
+                out!("WARNING: {warning}.");
             }
-            if let Err(e) = state_manager::save_state(vault_path, &state) {
-                error!("Failed to save state after generating pads: {e}");
-            } else {
-                info!("Successfully generated and registered {count} pad(s).");
+            report("vault", vault_path);
+            report("pads", serde_json::json!({ "total": total_pads, "available": available_pads, "fully_used": used_pads }));
+            report("storage", serde_json::json!({ "total_bytes": total_storage_bytes, "used_bytes": total_used_bytes }));
+            report("material", material);
+            report("thresholds", &state.thresholds);
+            report("material_warnings", &warnings);
+        }
+        VaultCommands::Thresholds { warn_remaining, warn_gap, auto_generate, clear } => {
+            let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+                error!("Failed to load vault state: {e}");
+                exit(Exit::Storage);
+            });
+            let settings = &mut state.thresholds;
+            if *clear {
+                *settings = thresholds::Thresholds::default();
+            }
+            settings.min_remaining = warn_remaining.or(settings.min_remaining);
+            settings.min_largest_gap = warn_gap.or(settings.min_largest_gap);
+            settings.auto_generate = auto_generate.or(settings.auto_generate);
+            if (*clear || warn_remaining.is_some() || warn_gap.is_some() || auto_generate.is_some())
+                && let Err(e) = state_manager::save_state(vault_path, &state)
+            {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
             }
+            let show = |size: Option<usize>| size.map_or_else(|| "-".to_string(), thresholds::format_size);
+            out!("Warn below remaining: {}", show(state.thresholds.min_remaining));
+            out!("Warn below largest message: {}", show(state.thresholds.min_largest_gap));
+            out!("Auto-generate local pads: {}", show(state.thresholds.auto_generate));
+            report("thresholds", &state.thresholds);
         }
-        PadCommands::List => {
+        VaultCommands::Sync { command } => handle_sync_command(command, vault_path),
+        VaultCommands::Fsck { repair } => {
+            let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+                error!("Failed to load vault state: {e}");
+                exit(Exit::Storage);
+            });
+            let problems = fsck::check(vault_path, &state).unwrap_or_else(|e| {
+                error!("Failed to check the vault: {e}");
+                exit(Exit::Storage);
+            });
+            report("problems", problems.iter().map(|problem| serde_json::json!({ "problem": problem.to_string(), "repairable": problem.is_repairable() })).collect::<Vec<_>>());
+            if problems.is_empty() {
+                out!("No problems found in vault '{}'.", vault_path.display());
+                return;
+            }
+            out!("Found {} problem(s) in vault '{}':", problems.len(), vault_path.display());
+            for problem in &problems {
+                let remedy = if problem.is_repairable() { "repairable" } else { "needs quarantine" };
+                out!("  - {problem} ({remedy})");
+            }
+            if !repair {
+                out!("Run 'vault fsck --repair' to fix them.");
+                exit(Exit::Integrity);
+            }
+            let result = fsck::repair(vault_path, &mut state, &problems);
+            // Whatever was applied before a failure must still be saved.
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            let actions = result.unwrap_or_else(|e| {
+                error!("Failed to repair the vault: {e}");
+                exit(Exit::Storage);
+            });
+            for action in &actions {
+                out!("{action}.");
+            }
+            report("repairs", actions.iter().map(ToString::to_string).collect::<Vec<_>>());
+            audit_log(vault_path, audit_record(audit::Operation::Repair).detail(format!("{} problem(s)", problems.len())));
+        }
+        VaultCommands::Backup { out } => {
+            let manifest = backup::create(vault_path, out).unwrap_or_else(|e| {
+                error!("Failed to back up the vault: {e}");
+                exit(Exit::Storage);
+            });
+            let bytes: u64 = manifest.files.iter().map(|file| file.size).sum();
+            audit_log(vault_path, audit_record(audit::Operation::Export).file(out).detail("backup"));
+            out!("Backed up {} file(s), {bytes} bytes, to '{}'.", manifest.files.len(), out.display());
+            report("snapshot", out);
+            report("manifest", &manifest);
+        }
+        VaultCommands::Restore { snapshot, force } => {
+            let manifest = backup::verify(snapshot).unwrap_or_else(|e| {
+                error!("Cannot restore '{}': {e}", snapshot.display());
+                exit(Exit::Integrity);
+            });
+            let saved = backup::snapshot_state(snapshot).unwrap_or_else(|e| {
+                error!("Failed to read the snapshot's state: {e}");
+                exit(Exit::Storage);
+            });
+            let current = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+                error!("Failed to load vault state: {e}");
+                exit(Exit::Storage);
+            });
+            let stale = backup::stale_pads(&saved, &current);
+            if !stale.is_empty() {
+                let pads = stale.iter().map(|id| format!("'{id}'")).collect::<Vec<_>>().join(", ");
+                if !*force {
+                    error!("The snapshot from {} is older than the vault's usage of pad(s) {pads}.", timestamp::format_utc(manifest.created_at));
+                    error!("Restoring it would make those bytes available again and lead to pad reuse. Pass --force to restore anyway.");
+                    exit(Exit::Integrity);
+                }
+                warn(&format!("restoring stale usage of pad(s) {pads}. Do not encrypt with them until their usage is synced."));
+            }
+            let added = backup::added_pads(&saved, &current);
+            if !added.is_empty() {
+                let pads = added.iter().map(|id| format!("'{id}'")).collect::<Vec<_>>().join(", ");
+                if !*force {
+                    error!("The snapshot from {} does not have pad(s) {pads}, which were added to the vault since.", timestamp::format_utc(manifest.created_at));
+                    error!("Restoring it would delete them. Take a new backup, or pass --force to restore anyway.");
+                    exit(Exit::Integrity);
+                }
+                warn(&format!("deleting pad(s) {pads}, which the snapshot does not have."));
+            }
+            if let Err(e) = fs::create_dir_all(vault_path).and_then(|()| backup::restore(snapshot, &manifest, vault_path)) {
+                error!("Failed to restore the vault: {e}");
+                exit(Exit::Storage);
+            }
+            audit_log(vault_path, audit_record(audit::Operation::Import).file(snapshot).detail("restore from backup"));
+            out!("Restored {} file(s) from the snapshot taken {}.", manifest.files.len(), timestamp::format_utc(manifest.created_at));
+            report("snapshot", snapshot);
+            report("manifest", &manifest);
+            report("stale_pads", &stale);
+            report("deleted_pads", &added);
+        }
+        VaultCommands::Log { pad, op, since, until } => {
+            let entries = audit::read(vault_path).unwrap_or_else(|e| {
+                error!("Failed to read the audit log: {e}");
+                exit(Exit::Storage);
+            });
+            let filter = audit::Filter { pad_id: pad.clone(), op: *op, since: *since, until: until.map(|day| day + 86_400) };
+            let selected: Vec<_> = entries.iter().filter(|entry| filter.matches(entry)).collect();
+            for entry in &selected {
+                out!("{}", entry.record);
+            }
+            report("entries", &selected);
+            if let Err(index) = audit::verify(&entries) {
+                error!("The audit log's hash chain is broken at entry {}: entries were changed, removed or reordered.", index + 1);
+                exit(Exit::Integrity);
+            }
+        }
+    }
+}
+
+/// Derives the receipt MAC key of a pad from its file.
+fn sync_key(vault_path: &Path, pad: &state_manager::Pad) -> Result<[u8; 32], String> {
+    fs::read(pad_file_path(vault_path, pad))
+        .map(|bytes| sync::mac_key(&bytes))
+        .map_err(|e| format!("Failed to read pad file for '{}': {e}", pad.id))
+}
+
+fn handle_sync_command(command: &SyncCommands, vault_path: &Path) {
+    let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+        error!("Failed to load vault state: {e}");
+        exit(Exit::Storage);
+    });
+    match command {
+        SyncCommands::Export { out, pad_id, all } => {
+            if let Some(pad_id) = pad_id {
+                find_pad(&state, pad_id);
+            }
+            let mut pad_ids: Vec<String> = state.pads.keys().filter(|id| pad_id.as_ref().is_none_or(|p| p == *id)).cloned().collect();
+            pad_ids.sort();
+
+            let mut receipts = Vec::new();
+            for id in &pad_ids {
+                let pad = find_pad(&state, id);
+                let segments = if *all {
+                    sync::used_segments(pad)
+                } else {
+                    sync::pending_segments(pad)
+                };
+                if segments.is_empty() {
+                    continue;
+                }
+                let key = sync_key(vault_path, pad).unwrap_or_else(|e| {
+                    error!("{e}");
+                    exit(Exit::Storage);
+                });
+                receipts.push(sync::UsageReceipt::new(id, &segments, &key));
+            }
+
+            let bundle = sync::SyncBundle::new(receipts);
+            let bundle_str = serde_json::to_string_pretty(&bundle).unwrap_or_else(|e| {
+                error!("Failed to serialize sync bundle: {e}");
+                exit(Exit::Storage);
+            });
+            let to_stdout = is_stdio(out);
+            write_output((!to_stdout).then_some(out.as_path()), format!("{bundle_str}\n").as_bytes());
+
+            for receipt in &bundle.receipts {
+                if let Some(pad) = state.pads.get_mut(&receipt.pad_id) {
+                    sync::mark_synced(pad, &receipt.byte_ranges());
+                }
+                let record = audit_record(audit::Operation::Export).pad(&receipt.pad_id).detail(format!("usage sync of {} range(s)", receipt.ranges.len()));
+                audit_log(vault_path, if to_stdout { record } else { record.file(out) });
+            }
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            let bytes: usize = bundle.receipts.iter().map(sync::UsageReceipt::used_bytes).sum();
+            let message = format!("Exported {bytes} used bytes across {} pad(s).", bundle.receipts.len());
+            if !to_stdout {
+                out!("{message}");
+            } else if !json_output() {
+                eprintln!("{message}");
+            }
+            report("pads", bundle.receipts.iter().map(|receipt| &receipt.pad_id).collect::<Vec<_>>());
+            report("bytes", bytes);
+            if !to_stdout {
+                report("file", out);
+            }
+        }
+        SyncCommands::Import { input } => {
+            let data = Input::from_args(Some(input), None).read_all();
+            let bundle: sync::SyncBundle = serde_json::from_slice(&data).unwrap_or_else(|e| {
+                error!("Failed to parse sync bundle: {e}");
+                exit(Exit::InvalidInput);
+            });
+            if bundle.version > sync::BUNDLE_VERSION {
+                error!("Sync bundle version {} is newer than this tool supports.", bundle.version);
+                exit(Exit::InvalidInput);
+            }
+
+            let mut failed = false;
+            let mut conflicts = Vec::new();
+            let mut merged = Vec::new();
+            let mut skipped = Vec::new();
+            for receipt in &bundle.receipts {
+                let Some(pad) = state.pads.get(&receipt.pad_id) else {
+                    out!("Skipping pad '{}': not in this vault.", receipt.pad_id);
+                    skipped.push(receipt.pad_id.clone());
+                    continue;
+                };
+                let verified = sync_key(vault_path, pad).is_ok_and(|key| receipt.verify(&key));
+                if !verified {
+                    error!("Receipt for pad '{}' failed authentication and was ignored.", receipt.pad_id);
+                    failed = true;
+                    continue;
+                }
+                let Some(pad) = state.pads.get_mut(&receipt.pad_id) else {
+                    continue;
+                };
+                let was_available = !pad.is_fully_used;
+                match sync::merge(pad, &receipt.ranges) {
+                    Ok(outcome) => {
+                        let added: usize = outcome.added.iter().map(ExactSizeIterator::len).sum();
+                        out!("Pad '{}': marked {added} new bytes as used.", receipt.pad_id);
+                        merged.push(serde_json::json!({ "pad_id": receipt.pad_id, "added_bytes": added, "conflicts": outcome.conflicts.len() }));
+                        audit_log(
+                            vault_path,
+                            audit_record(audit::Operation::Import)
+                                .pad(&receipt.pad_id)
+                                .detail(format!("usage sync: {added} new byte(s), {} conflict(s)", outcome.conflicts.len())),
+                        );
+                        if !outcome.conflicts.is_empty() {
+                            pad.quarantined = Some("pad reuse reported by a sync import".to_string());
+                        }
+                        warn_skipped(vault_path, &receipt.pad_id, &outcome.skipped);
+                        conflicts.extend(outcome.conflicts.into_iter().map(|range| (receipt.pad_id.clone(), range)));
+                    }
+                    Err(e) => {
+                        error!("Receipt for pad '{}' is invalid: {e}", receipt.pad_id);
+                        failed = true;
+                    }
+                }
+                if was_available && pad.is_fully_used {
+                    move_pad_to_used(vault_path, &pad.file_name);
+                    out!("Pad '{}' is now fully consumed. Moving to 'used' directory.", receipt.pad_id);
+                }
+            }
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            report("pads", merged);
+            report("skipped", skipped);
+
+            if !conflicts.is_empty() {
+                print_reuse_alert("Both you and your partner used the same pad bytes for different messages.", &conflicts);
+            }
+            if failed || !conflicts.is_empty() {
+                exit(Exit::Integrity);
+            }
+        }
+    }
+}
+
+fn handle_pad_command(command: &PadCommands, vault_path: &Path, config: &config::Config) {
+    let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+        error!("Failed to load vault state: {e}");
+        exit(Exit::Storage);
+    });
+    match command {
+        PadCommands::Generate { size, count, local, expires, labels, note, watermark } => {
+            let size = size.unwrap_or(config.pad_size);
+            info!("Generating {count} new pad(s) of {size} MB each...");
+            let mut generated = Vec::new();
+            let mut failed = false;
+            for _ in 0..*count {
+                let pad_id = Uuid::new_v4().to_string();
+                let file_name = format!("{pad_id}.pad");
+                let pad_path = vault_path.join("pads/available").join(&file_name);
+                let size_in_bytes = size * 1024 * 1024;
+
+                let pad_path_str = pad_path.to_str().unwrap_or_else(|| {
+                    error!("Pad path contains invalid UTF-8");
+                    exit(Exit::Storage);
+                });
+                
+                if let Err(e) = pad_generator::generate_pad(pad_path_str, size_in_bytes) {
+                    error!("Failed to generate pad file for ID {pad_id}: {e}");
+                    failed = true;
+                } else {
+                    state.add_pad(pad_id.clone(), file_name, size_in_bytes);
+                    if let Some(pad) = state.pads.get_mut(&pad_id) {
+                        pad.local = *local;
+                        pad.expires_at = *expires;
+                        pad.note.clone_from(note);
+                        pad.labels.clone_from(labels);
+                        pad.labels.sort();
+                        pad.labels.dedup();
+                        if *watermark {
+                            pad.next_offset = Some(0);
+                        }
+                    }
+                    audit_log(vault_path, audit_record(audit::Operation::Generate).pad(&pad_id).range(0..size_in_bytes));
+                    out!("{pad_id}");
+                    generated.push(pad_id);
+                }
+            }
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state after generating pads: {e}");
+                exit(Exit::Storage);
+            }
+            report("pad_ids", &generated);
+            report("pads", generated.iter().map(|id| pad_json(find_pad(&state, id))).collect::<Vec<_>>());
+            if failed {
+                exit(Exit::Storage);
+            }
+            info!("Successfully generated and registered {count} pad(s).");
+        }
+        PadCommands::List { label, sort, available_only } => {
+            let query = state_manager::PadQuery { label: label.clone(), available_only: *available_only, sort: (*sort).into() };
+            let pads = state.query(&query);
+            report("pads", pads.iter().map(|pad| pad_json(pad)).collect::<Vec<_>>());
             if state.pads.is_empty() {
-                println!("No pads found in vault '{}'", vault_path.display());
+                out!("No pads found in vault '{}'", vault_path.display());
+                return;
+            }
+            if pads.is_empty() {
+                out!("No pads in vault '{}' match the filter.", vault_path.display());
                 return;
             }
 
-            println!("Pads in vault '{}':", vault_path.display());
-            println!("{:<38} {:<10} {:<15} {:<15}", "ID", "Size (MB)", "Used (Bytes)", "Remaining (Bytes)");
-            println!("{:-<80}", "");
+            out!("Pads in vault '{}':", vault_path.display());
+            out!("{:<38} {:<10} {:<15} {:<18} {:<18} {:<10} {:<11} Labels", "ID", "Size (MB)", "Used (Bytes)", "Remaining (Bytes)", "Largest Gap", "Direction", "Status");
+            out!("{:-<134}", "");
 
-            for (id, pad) in &state.pads {
+            for pad in &pads {
+                let id = &pad.id;
                 let total_used = pad.total_used_bytes();
-                let remaining = pad.size - total_used;
+                let remaining = pad.remaining_bytes();
+                let largest_gap = pad_map::PadMap::of(pad).largest_gap;
                 let size_mb = pad.size as f64 / (1024.0 * 1024.0);
-                println!("{id:<38} {size_mb:<10.2} {total_used:<15} {remaining:<15}");
+                let direction = pad.group.as_ref().map_or_else(
+                    || pad.direction.map_or_else(|| if pad.local { "local" } else { "-" }.to_string(), |d| d.to_string()),
+                    |m| format!("group:{}", m.member),
+                );
+                let status = pad.status().to_string();
+                let labels = pad.labels.join(",");
+                out!("{id:<38} {size_mb:<10.2} {total_used:<15} {remaining:<18} {largest_gap:<18} {direction:<10} {status:<11} {labels}");
+            }
+            let now = timestamp::now();
+            for pad in &pads {
+                if let Some(reason) = pad.encrypt_refusal(now) {
+                    out!("Pad '{}' is {} because {reason}.", pad.id, pad.status_at(now));
+                }
+            }
+        }
+        PadCommands::Show { pad_id } => {
+            let pad = find_pad(&state, pad_id);
+            let used = pad.total_used_bytes();
+            report("pad", pad_json(pad));
+            report("contact", state.contact_for_pad(pad_id).map(|contact| &contact.name));
+            out!("ID:          {}", pad.id);
+            out!("File:        {}", pad_file_path(vault_path, pad).display());
+            out!("Size:        {} bytes", pad.size);
+            out!("Used:        {used} bytes");
+            out!("Remaining:   {} bytes", pad.remaining_bytes());
+            out!("Direction:   {}", pad.direction.map_or_else(|| "-".to_string(), |d| d.to_string()));
+            out!("Allocation:  {}", pad.next_offset.map_or_else(|| pad.allocation_mode().to_string(), |watermark| format!("watermark at byte {watermark}")));
+            out!("Group:       {}", pad.group.as_ref().map_or_else(|| "-".to_string(), |m| format!("{} as {}", m.group, m.member)));
+            out!("Contact:     {}", state.contact_for_pad(pad_id).map_or("-", |contact| contact.name.as_str()));
+            out!("Labels:      {}", if pad.labels.is_empty() { "-".to_string() } else { pad.labels.join(", ") });
+            out!("Note:        {}", pad.note.as_deref().unwrap_or("-"));
+            if let Some(created) = pad.created_at {
+                out!("Created:     {}", timestamp::format_utc(created));
+            }
+            if let Some(imported) = pad.imported_at {
+                out!("Imported:    {}", timestamp::format_utc(imported));
+            }
+            out!("Status:      {}", pad.status());
+            out!("Expires:     {}", pad.expires_at.map_or_else(|| "-".to_string(), timestamp::format_utc));
+            if let Some(reason) = pad.encrypt_refusal(timestamp::now()) {
+                out!("Not usable:  {reason}");
             }
+            out!("History:");
+            if used == 0 {
+                out!("  No bytes used yet.");
+            } else if let Some(watermark) = pad.next_offset {
+                out!("  Bytes 0..{watermark} lie below the watermark and count as used.");
+            }
+            let mut history: Vec<_> = pad.used_segments.iter().collect();
+            history.sort_by_key(|segment| (segment.used_at, segment.start));
+            for segment in history {
+                let when = segment.used_at.map_or_else(|| "unknown time".to_string(), timestamp::format_utc);
+                let operation = segment.operation.map_or_else(|| "-".to_string(), |op| op.to_string());
+                let details: Vec<String> = [
+                    segment.seq.map(|seq| format!("message {seq}")),
+                    segment.counterparty.as_ref().map(|counterparty| format!("with {counterparty}")),
+                    segment.digest.as_ref().map(|digest| format!("sha256 {}", &digest[..digest.len().min(16)])),
+                    segment.reason.as_ref().map(|reason| format!("({reason})")),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                out!("  {:>10}..{:<10} {when:<20} {operation:<11} {}", segment.start, segment.end, details.join(" "));
+            }
+        }
+        PadCommands::Map { pad_id, width, ascii } => {
+            let map = pad_map::PadMap::of(find_pad(&state, pad_id));
+            let style = if *ascii { pad_map::BarStyle::Ascii } else { pad_map::BarStyle::Unicode };
+            out!(
+                "Pad '{pad_id}': {} used, {} free in {} range(s)",
+                thresholds::format_size(map.used_bytes),
+                thresholds::format_size(map.free_bytes),
+                map.free_ranges.len()
+            );
+            out!("[{}]", map.bar(*width, style));
+            out!("{}", style.legend());
+            if let Some(largest) = map.largest_free_range() {
+                out!("Largest gap: {} bytes at {}..{}", largest.len(), largest.start, largest.end);
+            }
+            if map.usable_ranges != map.free_ranges {
+                let usable: usize = map.usable_ranges.iter().map(ExactSizeIterator::len).sum();
+                out!("Usable by this vault: {usable} bytes in {} range(s)", map.usable_ranges.len());
+            }
+            out!("Free ranges:");
+            for range in &map.free_ranges {
+                out!("  {:>10}..{:<10} {} bytes", range.start, range.end, range.len());
+            }
+            report("map", &map);
+        }
+        PadCommands::Label { pad_id, add, remove } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found.");
+                exit(Exit::NotFound);
+            };
+            for label in add {
+                if let Err(e) = pad.add_label(label) {
+                    error!("{e}");
+                    exit(Exit::InvalidInput);
+                }
+            }
+            for label in remove {
+                if !pad.remove_label(label) {
+                    out!("Pad '{pad_id}' had no label '{label}'.");
+                }
+            }
+            let labels = if pad.labels.is_empty() { "none".to_string() } else { pad.labels.join(", ") };
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            out!("Labels of pad '{pad_id}': {labels}");
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Note { pad_id, text } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found.");
+                exit(Exit::NotFound);
+            };
+            pad.note = text.clone().filter(|text| !text.trim().is_empty());
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            if text.is_some() {
+                out!("Updated the note of pad '{pad_id}'.");
+            } else {
+                out!("Cleared the note of pad '{pad_id}'.");
+            }
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Burn { pad_id, range, reason } => {
+            let pad = find_pad(&state, pad_id);
+            if range.end > pad.size {
+                error!("Range {}..{} lies outside pad '{pad_id}', which has {} bytes.", range.start, range.end, pad.size);
+                exit(Exit::InvalidInput);
+            }
+            // Only bytes not recorded yet are burned, so no segment is ever counted twice.
+            let fresh = pad.unused_parts(range.start, range.end);
+            if fresh.is_empty() {
+                error!("Range {}..{} of pad '{pad_id}' is already used; nothing to burn.", range.start, range.end);
+                exit(Exit::PadUnavailable);
+            }
+            let burned: usize = fresh.iter().map(ExactSizeIterator::len).sum();
+            let mut became_full = false;
+            for part in fresh {
+                let segment = state_manager::UsedSegment::consumed(part.clone(), state_manager::SegmentOperation::Burn).reason(reason.as_str());
+                became_full |= record_used_segment(&mut state, vault_path, pad_id, segment).unwrap_or_else(|e| {
+                    error!("Failed to save state: {e}");
+                    exit(Exit::Storage);
+                });
+                audit_log(vault_path, audit_record(audit::Operation::Burn).pad(pad_id).range(part).detail(reason.as_str()));
+            }
+            out!("Burned {burned} byte(s) of pad '{pad_id}' in {}..{}.", range.start, range.end);
+            if burned < range.len() {
+                out!("The other {} byte(s) of the range were already used.", range.len() - burned);
+            }
+            if became_full {
+                out!("Pad '{pad_id}' is now fully used and was moved to pads/used.");
+            }
+            report("burned_bytes", burned);
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Retire { pad_id, reason, on } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found.");
+                exit(Exit::NotFound);
+            };
+            let mut detail = Vec::new();
+            if let Some(day) = on {
+                pad.expires_at = Some(*day);
+                detail.push(format!("expires at {}", timestamp::format_utc(*day)));
+            } else {
+                if pad.retirement.as_ref().is_some_and(|retirement| retirement.compromised) {
+                    error!("Pad '{pad_id}' is already revoked as compromised.");
+                    exit(Exit::InvalidInput);
+                }
+                pad.retirement = Some(state_manager::Retirement { at: timestamp::now(), compromised: false, reason: reason.clone() });
+            }
+            detail.extend(reason.clone());
+            let record = audit_record(audit::Operation::Retire).pad(pad_id);
+            let record = if detail.is_empty() { record } else { record.detail(detail.join("; ")) };
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            audit_log(vault_path, record);
+            match on {
+                Some(day) => out!("Pad '{pad_id}' will not encrypt from {} on.", timestamp::format_utc(*day)),
+                None => out!("Retired pad '{pad_id}'. It can still decrypt old messages."),
+            }
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Revoke { pad_id, reason } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found.");
+                exit(Exit::NotFound);
+            };
+            pad.retirement = Some(state_manager::Retirement { at: timestamp::now(), compromised: true, reason: Some(reason.clone()) });
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            audit_log(vault_path, audit_record(audit::Operation::Revoke).pad(pad_id).detail(reason));
+            out!("Revoked pad '{pad_id}' as compromised. Tell everyone sharing it to revoke their copy too.");
+            report("pad", pad_json(find_pad(&state, pad_id)));
         }
         PadCommands::Delete { pad_id } => {
             if let Some(pad_to_delete) = state.pads.get(pad_id) {
@@ -276,301 +1564,1565 @@ fn handle_pad_command(command: &PadCommands, vault_path: &Path) {
                 if fs::remove_file(&pad_path).is_err() {
                      if pad_path.exists() {
                         error!("Failed to delete pad file '{}'", pad_path.display());
-                        return;
+                        exit(Exit::Storage);
                      }
-                        println!("Pad file not found at '{}', but removing from state.", pad_path.display());
+                    warn(&format!("pad file not found at '{}', but removing it from the state.", pad_path.display()));
                 }
                 state.pads.remove(pad_id);
+                for contact in state.contacts.values_mut() {
+                    contact.pad_ids.retain(|id| id != pad_id);
+                }
                 if let Err(e) = state_manager::save_state(vault_path, &state) {
                     error!("Failed to save state after deleting pad: {e}");
+                    exit(Exit::Storage);
+                }
+                audit_log(vault_path, audit_record(audit::Operation::Delete).pad(pad_id));
+                out!("Successfully deleted pad '{pad_id}'");
+                report("deleted", pad_id);
+            } else {
+                error!("Pad with ID '{pad_id}' not found in the vault.");
+                exit(Exit::NotFound);
+            }
+        }
+        PadCommands::Print { pad_id, range, encoding, format, output, key_sheet, pair } => {
+            let sharing = sharing_for_export(&mut state, vault_path, pad_id, *pair);
+            let pad = find_pad(&state, pad_id);
+            let range = range.clone().unwrap_or(0..pad.size);
+            let data = read_pad_range(vault_path, pad, &range);
+            let lines = match key_sheet.map(CipherMode::table) {
+                None => paper::render_section(&pad.id, pad.size, range.start, &data, (*encoding).into(), &sharing),
+                Some(Some(table)) => letters::render_key_sheet(table, &data, range.start),
+                Some(None) => {
+                    error!("Key sheets are only available for the 'letters' and 'digits' modes.");
+                    exit(Exit::InvalidInput);
+                }
+            };
+            let document = match format {
+                PrintFormat::Text => lines.join("\n") + "\n",
+                PrintFormat::Svg => render_svg_page(&lines),
+            };
+            let record = audit_record(audit::Operation::Export).pad(&pad.id).range(range.clone());
+            let record = if key_sheet.is_some() { record.detail("key sheet") } else { record.detail("printout") };
+            audit_log(vault_path, with_file(record, output.as_deref()));
+            if let Some(output_path) = output {
+                if let Err(e) = fs::write(output_path, document) {
+                    error!("Failed to write printout: {e}");
+                    exit(Exit::Storage);
+                }
+                out!("Printed {} bytes of pad '{}' to '{}'", data.len(), pad.id, output_path.display());
+                report("file", output_path);
+            } else {
+                write_output(None, document.as_bytes());
+            }
+            report("pad_id", &pad.id);
+            report("range", &range);
+        }
+        PadCommands::Qr { pad_id, range, encoding, lines_per_code, out_dir, pair } => {
+            let sharing = sharing_for_export(&mut state, vault_path, pad_id, *pair);
+            let pad = find_pad(&state, pad_id);
+            let range = range.clone().unwrap_or(0..pad.size);
+            let data = read_pad_range(vault_path, pad, &range);
+            let frames = paper::render_qr_frames(&pad.id, pad.size, range.start, &data, (*encoding).into(), *lines_per_code, &sharing);
+            if let Err(e) = fs::create_dir_all(out_dir) {
+                error!("Failed to create output directory: {e}");
+                exit(Exit::Storage);
+            }
+            let mut files = Vec::new();
+            for (index, frame) in frames.iter().enumerate() {
+                let code = qrcode::QrCode::new(frame.as_bytes()).unwrap_or_else(|e| {
+                    error!("Failed to encode QR code {}: {e}. Try a smaller --lines-per-code.", index + 1);
+                    exit(Exit::InvalidInput);
+                });
+                let svg = code.render::<qrcode::render::svg::Color<'_>>().min_dimensions(400, 400).build();
+                let frame_path = out_dir.join(format!("{}-{:04}-of-{:04}.svg", pad.id, index + 1, frames.len()));
+                if let Err(e) = fs::write(&frame_path, svg) {
+                    error!("Failed to write QR code '{}': {e}", frame_path.display());
+                    exit(Exit::Storage);
+                }
+                files.push(frame_path);
+            }
+            audit_log(vault_path, audit_record(audit::Operation::Export).pad(&pad.id).range(range).file(out_dir).detail("qr codes"));
+            out!("Wrote {} QR code(s) for pad '{}' to '{}'", frames.len(), pad.id, out_dir.display());
+            report("pad_id", &pad.id);
+            report("files", &files);
+        }
+        PadCommands::Import { from_text, from_qr_images, member } => {
+            let mut sections = Vec::new();
+            let mut has_errors = false;
+            let sources = from_text.iter().map(|path| (path, false)).chain(from_qr_images.iter().map(|path| (path, true)));
+            for (path, is_image) in sources {
+                let contents = fs::read(path).unwrap_or_else(|e| {
+                    error!("Failed to read '{}': {e}", path.display());
+                    exit(Exit::Storage);
+                });
+                let decoded = if is_image {
+                    decode_qr_image(&contents)
                 } else {
-                    println!("Successfully deleted pad '{pad_id}'");
+                    String::from_utf8(contents).map_err(|_| "the file is not UTF-8 text".to_string())
+                };
+                let text = match decoded {
+                    Ok(text) => text,
+                    Err(e) => {
+                        has_errors = true;
+                        error!("{}: {e}", path.display());
+                        continue;
+                    }
+                };
+                match paper::parse_sections(&text) {
+                    Ok(parsed) => sections.extend(parsed),
+                    Err(errors) => {
+                        has_errors = true;
+                        for line_error in errors {
+                            error!("{}: {line_error}", path.display());
+                        }
+                    }
                 }
+            }
+            if has_errors {
+                error!("The pad text contains errors. Correct the lines or images above and try again.");
+                exit(Exit::InvalidInput);
+            }
+            let (pad_id, data) = paper::assemble_pad(&sections).unwrap_or_else(|e| {
+                error!("Failed to assemble pad: {e}");
+                exit(Exit::InvalidInput);
+            });
+            if !pad_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                error!("Pad ID '{pad_id}' contains characters that are not allowed in a file name.");
+                exit(Exit::InvalidInput);
+            }
+            if state.pads.contains_key(&pad_id) {
+                error!("Pad with ID '{pad_id}' already exists in the vault.");
+                exit(Exit::InvalidInput);
+            }
+            let sharing = sections.first().map(|section| section.sharing.clone()).unwrap_or_default();
+            let membership = sharing.group.map(|group| {
+                let Some(member) = member.clone().filter(|m| group.members.contains(m)) else {
+                    error!("Pad '{pad_id}' is shared by the group {}. Pass your name with --member.", group.members.join(", "));
+                    exit(Exit::InvalidInput);
+                };
+                state_manager::GroupMembership { group, member }
+            });
+            let file_name = format!("{pad_id}.pad");
+            if let Err(e) = fs::write(vault_path.join("pads/available").join(&file_name), &data) {
+                error!("Failed to write pad file: {e}");
+                exit(Exit::Storage);
+            }
+            state.import_pad(pad_id.clone(), file_name, data.len());
+            let peer = sharing.peer;
+            if let Some(pad) = state.pads.get_mut(&pad_id) {
+                pad.direction = peer;
+                pad.group.clone_from(&membership);
+            }
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state after importing pad: {e}");
+                exit(Exit::Storage);
+            }
+            if let Some(direction) = peer {
+                info!("Pad '{pad_id}' is paired; this vault consumes it from the {direction}.");
+            }
+            if let Some(membership) = &membership {
+                info!("Pad '{pad_id}' is a group pad; this vault consumes the part of '{}'.", membership.member);
+            }
+            let source = from_text.iter().chain(from_qr_images).map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ");
+            audit_log(vault_path, audit_record(audit::Operation::Import).pad(&pad_id).range(0..data.len()).detail(format!("printout from {source}")));
+            out!("{pad_id}");
+            report("pad_id", &pad_id);
+            report("pad", pad_json(find_pad(&state, &pad_id)));
+        }
+        PadCommands::Group { pad_id, members, member, layout, stripe_width, clear } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found in vault.");
+                exit(Exit::NotFound);
+            };
+            pad.group = if *clear {
+                None
             } else {
-                println!("Pad with ID '{pad_id}' not found in the vault.");
+                refuse_policy_on_watermark(pad);
+                let layout = match layout {
+                    GroupLayoutArg::Ranges => state_manager::GroupLayout::Ranges,
+                    GroupLayoutArg::Stripes => state_manager::GroupLayout::Stripes { width: *stripe_width },
+                };
+                let group = state_manager::Group::new(layout, members.clone()).unwrap_or_else(|e| {
+                    error!("Invalid group: {e}");
+                    exit(Exit::InvalidInput);
+                });
+                let Some(member) = member.clone().filter(|m| group.members.contains(m)) else {
+                    error!("--member must be one of the listed members.");
+                    exit(Exit::InvalidInput);
+                };
+                Some(state_manager::GroupMembership { group, member })
+            };
+            let summary = pad.group.as_ref().map_or_else(
+                || "no longer shared as a group".to_string(),
+                |m| format!("shared by {}; this vault uses {} byte(s) as '{}'", m.group, pad.allowed_ranges().iter().map(ExactSizeIterator::len).sum::<usize>(), m.member),
+            );
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            out!("Pad '{pad_id}' is {summary}.");
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Pair { pad_id, role, range, clear } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found in vault.");
+                exit(Exit::NotFound);
+            };
+            if !*clear {
+                refuse_policy_on_watermark(pad);
+            }
+            pad.direction = match (role, range) {
+                _ if *clear => None,
+                (Some(role), _) => Some((*role).into()),
+                (None, Some(range)) if range.end <= pad.size => Some(state_manager::Direction::Range { start: range.start, end: range.end }),
+                (None, _) => {
+                    error!("The range must lie within the pad's {} bytes.", pad.size);
+                    exit(Exit::InvalidInput);
+                }
+            };
+            let summary = pad.direction.map_or_else(|| "the whole pad".to_string(), |d| match d {
+                state_manager::Direction::Range { .. } => format!("bytes {d}"),
+                _ => format!("from the {d}"),
+            });
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            out!("Pad '{pad_id}' will be consumed {summary}.");
+            report("pad", pad_json(find_pad(&state, pad_id)));
+        }
+        PadCommands::Mode { pad_id, mode } => {
+            let Some(pad) = state.pads.get_mut(pad_id) else {
+                error!("Pad with ID '{pad_id}' not found in vault.");
+                exit(Exit::NotFound);
+            };
+            let forfeited = pad.convert((*mode).into()).unwrap_or_else(|e| {
+                error!("Cannot switch the pad: {e}.");
+                exit(Exit::InvalidInput);
+            });
+            let watermark = pad.next_offset;
+            // Giving up the last gaps can leave nothing to use
+            let became_full = !pad.is_fully_used && pad.is_fully_used();
+            pad.is_fully_used |= became_full;
+            let file_name = pad.file_name.clone();
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            if became_full {
+                move_pad_to_used(vault_path, &file_name);
             }
+            match watermark {
+                Some(watermark) => out!("Pad '{pad_id}' now hands out its bytes from a watermark at byte {watermark}."),
+                None => out!("Pad '{pad_id}' now hands out any free range."),
+            }
+            if forfeited > 0 {
+                let watermark = watermark.unwrap_or_default();
+                audit_log(vault_path, audit_record(audit::Operation::Burn).pad(pad_id).range(0..watermark).detail(format!("switched to a watermark, giving up {forfeited} free byte(s)")));
+                out!("The {forfeited} free byte(s) below the watermark will never be used.");
+            }
+            report("forfeited_bytes", forfeited);
+            report("pad", pad_json(find_pad(&state, pad_id)));
         }
     }
 }
 
-fn handle_encrypt_command(input: &Path, output: Option<&PathBuf>, pad_id: Option<&str>, offset: usize, vault_path: &Path) {
+/// Exits unless `pad` may take a direction or group policy, which watermark pads cannot:
+/// the partners' messages would push the watermark past this vault's bytes.
+fn refuse_policy_on_watermark(pad: &state_manager::Pad) {
+    if pad.allocation_mode() == state_manager::AllocationMode::Watermark {
+        error!("Pad '{}' hands out its bytes from a watermark. Switch it with 'pad mode {} free-ranges' before sharing it with a policy.", pad.id, pad.id);
+        exit(Exit::InvalidInput);
+    }
+}
+
+/// Returns the sharing terms to hand to the partner or group with an exported pad.
+///
+/// With `pair`, an unpaired pad is first made the initiator's, consuming from the front.
+fn sharing_for_export(state: &mut state_manager::VaultState, vault_path: &Path, pad_id: &str, pair: bool) -> paper::Sharing {
+    let Some(pad) = state.pads.get_mut(pad_id) else {
+        error!("Pad with ID '{pad_id}' not found in vault.");
+        exit(Exit::NotFound);
+    };
+    if pair && pad.direction.is_none() {
+        refuse_policy_on_watermark(pad);
+        pad.direction = Some(state_manager::Direction::Front);
+        if let Err(e) = state_manager::save_state(vault_path, state) {
+            error!("Failed to save state: {e}");
+            exit(Exit::Storage);
+        }
+        info!("Pad '{pad_id}' is now paired; this vault consumes it from the front.");
+    }
+    let pad = find_pad(state, pad_id);
+    if pair && matches!(pad.direction, Some(state_manager::Direction::Range { .. })) {
+        warn(&format!("pad '{pad_id}' uses an assigned range; give the partner theirs with 'pad pair --range'."));
+    }
+    paper::Sharing {
+        peer: pad.direction.and_then(state_manager::Direction::peer),
+        group: pad.group.as_ref().map(|membership| membership.group.clone()),
+    }
+}
+
+/// A pad as `--output-format json` reports it: the stored fields plus its status, free bytes and
+/// largest free range.
+fn pad_json(pad: &state_manager::Pad) -> serde_json::Value {
+    let mut value = serde_json::to_value(pad).unwrap_or_default();
+    if let Some(fields) = value.as_object_mut() {
+        fields.insert("status".to_string(), pad.status().to_string().into());
+        fields.insert("remaining_bytes".to_string(), pad.remaining_bytes().into());
+        fields.insert("largest_gap".to_string(), pad_map::PadMap::of(pad).largest_gap.into());
+    }
+    value
+}
+
+/// Parses a `start..end` byte range argument.
+fn parse_range(value: &str) -> Result<Range<usize>, String> {
+    let (start, end) = value
+        .split_once("..")
+        .ok_or_else(|| format!("'{value}' is not a range of the form start..end"))?;
+    let start = start.trim().parse::<usize>().map_err(|e| format!("invalid range start: {e}"))?;
+    let end = end.trim().parse::<usize>().map_err(|e| format!("invalid range end: {e}"))?;
+    if start >= end {
+        return Err(format!("range {start}..{end} is empty"));
+    }
+    Ok(start..end)
+}
+
+fn handle_contact_command(command: &ContactCommands, vault_path: &Path) {
     let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
         error!("Failed to load vault state: {e}");
-        std::process::exit(1);
+        exit(Exit::Storage);
     });
-    let Ok(Ok(input_file_size)) = fs::metadata(input).map(|m| usize::try_from(m.len())) else {
-        error!("Failed to get input file metadata");
-        std::process::exit(1);
+    match command {
+        ContactCommands::Add { name, pads, notes, role } => {
+            for pad_id in pads {
+                find_pad(&state, pad_id);
+                if let Some(owner) = state.contact_for_pad(pad_id).filter(|owner| owner.name != *name) {
+                    error!("Pad '{pad_id}' is already shared with contact '{}'.", owner.name);
+                    exit(Exit::InvalidInput);
+                }
+            }
+            // Linked pads without a direction inherit the contact's, which a watermark pad cannot take.
+            let direction = role.map(state_manager::Direction::from).or_else(|| state.contacts.get(name).and_then(|contact| contact.direction));
+            if direction.is_some() {
+                let linked = state.contacts.get(name).map(|contact| contact.pad_ids.clone()).unwrap_or_default();
+                for pad in pads.iter().chain(&linked).filter_map(|pad_id| state.pads.get(pad_id)) {
+                    refuse_policy_on_watermark(pad);
+                }
+            }
+            let existed = state.contacts.contains_key(name);
+            let contact = state.contacts.entry(name.clone()).or_insert_with(|| state_manager::Contact { name: name.clone(), ..Default::default() });
+            if notes.is_some() {
+                contact.notes.clone_from(notes);
+            }
+            if let Some(role) = role {
+                contact.direction = Some((*role).into());
+            }
+            for pad_id in pads {
+                if !contact.pad_ids.contains(pad_id) {
+                    contact.pad_ids.push(pad_id.clone());
+                }
+            }
+            // An explicit role applies to every shared pad; otherwise only unpaired pads inherit it.
+            let contact = contact.clone();
+            for pad in state.pads.values_mut().filter(|pad| contact.pad_ids.contains(&pad.id)) {
+                if role.is_some() || pad.direction.is_none() {
+                    pad.direction = contact.direction;
+                }
+            }
+            if let Err(e) = state_manager::save_state(vault_path, &state) {
+                error!("Failed to save state: {e}");
+                exit(Exit::Storage);
+            }
+            let verb = if existed { "Updated" } else { "Added" };
+            out!("{verb} contact '{name}' with {} pad(s).", contact.pad_ids.len());
+            report("contact", &contact);
+            report("created", !existed);
+        }
+        ContactCommands::List => {
+            let mut contacts: Vec<_> = state.contacts.values().collect();
+            contacts.sort_by(|a, b| a.name.cmp(&b.name));
+            let remaining = |contact: &state_manager::Contact| -> usize {
+                contact.pad_ids.iter().filter_map(|id| state.pads.get(id)).map(state_manager::Pad::remaining_bytes).sum()
+            };
+            report(
+                "contacts",
+                contacts.iter().map(|contact| serde_json::json!({ "contact": contact, "remaining_bytes": remaining(contact) })).collect::<Vec<_>>(),
+            );
+            if contacts.is_empty() {
+                out!("No contacts in the vault.");
+                return;
+            }
+            out!("{:<20} {:<6} {:<18} Notes", "Name", "Pads", "Remaining (Bytes)");
+            out!("{:-<80}", "");
+            for contact in contacts {
+                out!("{:<20} {:<6} {:<18} {}", contact.name, contact.pad_ids.len(), remaining(contact), contact.notes.as_deref().unwrap_or(""));
+            }
+        }
+        ContactCommands::Show { name } => {
+            let Some(contact) = state.contacts.get(name) else {
+                error!("Contact '{name}' not found in the vault.");
+                exit(Exit::NotFound);
+            };
+            report("contact", contact);
+            report("pads", contact.pad_ids.iter().filter_map(|id| state.pads.get(id)).map(pad_json).collect::<Vec<_>>());
+            out!("Name:      {}", contact.name);
+            out!("Notes:     {}", contact.notes.as_deref().unwrap_or("-"));
+            out!("Direction: {}", contact.direction.map_or_else(|| "-".to_string(), |d| d.to_string()));
+            out!("Pads:");
+            for pad_id in &contact.pad_ids {
+                match state.pads.get(pad_id) {
+                    Some(pad) => {
+                        let direction = pad.direction.map_or_else(|| "-".to_string(), |d| d.to_string());
+                        out!("  {pad_id:<38} {:>12} bytes left  direction {direction}", pad.remaining_bytes());
+                    }
+                    None => out!("  {pad_id:<38} (no longer in the vault)"),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the pads to choose from when encrypting for contact `name`.
+///
+/// An explicit `pad_id` must be one of them.
+fn contact_pad_ids(state: &state_manager::VaultState, name: &str, pad_id: Option<&str>) -> Vec<String> {
+    let Some(contact) = state.contacts.get(name) else {
+        error!("Contact '{name}' not found in the vault.");
+        exit(Exit::NotFound);
     };
+    if let Some(pad_id) = pad_id
+        && !contact.pad_ids.iter().any(|id| id == pad_id)
+    {
+        error!("Pad '{pad_id}' is not shared with contact '{name}'.");
+        exit(Exit::InvalidInput);
+    }
+    if contact.pad_ids.is_empty() {
+        error!("No pads are shared with contact '{name}'. Link one with 'contact add {name} --pad <PAD_ID>'.");
+        exit(Exit::PadUnavailable);
+    }
+    contact.pad_ids.clone()
+}
+
+/// Names the recipient of a message on `pad_id`: the contact it was addressed to, or the
+/// contact the pad is shared with.
+fn counterparty_for(state: &state_manager::VaultState, to: Option<&str>, pad_id: &str) -> Option<String> {
+    to.map(str::to_string).or_else(|| state.contact_for_pad(pad_id).map(|contact| contact.name.clone()))
+}
 
-    let output_path = output.cloned().unwrap_or_else(|| {
-        let mut new_path = input.as_os_str().to_owned();
-        new_path.push(".enc");
-        PathBuf::from(new_path)
+/// Looks up a pad by ID, exiting with an error if it is not in the vault.
+fn find_pad<'a>(state: &'a state_manager::VaultState, pad_id: &str) -> &'a state_manager::Pad {
+    state.pads.get(pad_id).unwrap_or_else(|| {
+        error!("Pad with ID '{pad_id}' not found in the vault.");
+        exit(Exit::NotFound);
+    })
+}
+
+/// Returns the location of a pad's file, which depends on whether it is fully used.
+fn pad_file_path(vault_path: &Path, pad: &state_manager::Pad) -> PathBuf {
+    let pad_dir = if pad.is_fully_used { "used" } else { "available" };
+    vault_path.join("pads").join(pad_dir).join(&pad.file_name)
+}
+
+/// Reads a byte range of a pad file, exiting with an error if the range is out of bounds.
+fn read_pad_range(vault_path: &Path, pad: &state_manager::Pad, range: &Range<usize>) -> Vec<u8> {
+    if range.end > pad.size {
+        error!("Range {}..{} exceeds the pad size of {} bytes.", range.start, range.end, pad.size);
+        exit(Exit::InvalidInput);
+    }
+    let mut pad_file = fs::File::open(pad_file_path(vault_path, pad)).unwrap_or_else(|e| {
+        error!("Failed to open pad file: {e}");
+        exit(Exit::Storage);
     });
+    if let Err(e) = pad_file.seek(SeekFrom::Start(range.start as u64)) {
+        error!("Failed to seek in pad file: {e}");
+        exit(Exit::Storage);
+    }
+    let mut data = vec![0u8; range.len()];
+    if let Err(e) = pad_file.read_exact(&mut data) {
+        error!("Failed to read pad segment: {e}");
+        exit(Exit::Storage);
+    }
+    data
+}
 
-    let pad_id_to_use = pad_id.map_or_else(
-        || {
-            state
-                .pads
-                .values()
-                .find(|p| p.find_available_segment(input_file_size).is_some())
-                .map_or_else(
-                    || {
-                        error!("Could not find an available pad with enough contiguous space ({input_file_size} bytes).");
-                        error!("Please generate a new pad with 'pad generate'.");
-                        std::process::exit(1);
-                    },
-                    |pad| {
-                        println!("Automatically selected pad '{}'", pad.id);
-                        pad.id.clone()
-                    },
-                )
-        },
-        String::from,
+/// Images wider or taller than this many pixels are refused before they are decoded.
+#[cfg(feature = "qr-images")]
+const MAX_QR_IMAGE_SIDE: u32 = 8192;
+
+/// Reads every QR code in a PNG or JPEG image and returns their texts, one per line.
+#[cfg(feature = "qr-images")]
+fn decode_qr_image(bytes: &[u8]) -> Result<String, String> {
+    let mut reader = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("cannot read the image: {e}"))?;
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_QR_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_QR_IMAGE_SIDE);
+    reader.limits(limits);
+    let luma = reader.decode().map_err(|e| format!("cannot read the image: {e}"))?.into_luma8();
+    let width = luma.width() as usize;
+    let pixels = luma.as_raw();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(width, luma.height() as usize, |x, y| pixels[y * width + x]);
+    let grids = prepared.detect_grids();
+    if grids.is_empty() {
+        return Err("no QR code found".to_string());
+    }
+    let texts = grids
+        .iter()
+        .map(|grid| grid.decode().map(|(_, text)| text).map_err(|e| format!("cannot decode a QR code: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(texts.join("\n"))
+}
+
+/// Stands in for the QR decoder in builds without the `qr-images` feature.
+#[cfg(not(feature = "qr-images"))]
+fn decode_qr_image(_bytes: &[u8]) -> Result<String, String> {
+    Err("this build of otp-cli cannot read images; rebuild it with the `qr-images` feature".to_string())
+}
+
+/// Lays out printed pad lines as a single monospace SVG page.
+fn render_svg_page(lines: &[String]) -> String {
+    const LINE_HEIGHT: usize = 16;
+    const MARGIN: usize = 24;
+    let width = lines.iter().map(String::len).max().unwrap_or(0) * 8 + 2 * MARGIN;
+    let height = lines.len() * LINE_HEIGHT + 2 * MARGIN;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n<g font-family=\"monospace\" font-size=\"13\">\n"
     );
+    svg.extend(lines.iter().enumerate().map(|(index, line)| {
+        let y = MARGIN + (index + 1) * LINE_HEIGHT;
+        format!("<text x=\"{MARGIN}\" y=\"{y}\">{}</text>\n", escape_xml(line))
+    }));
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
 
-    if let Some(pad) = state.pads.get_mut(&pad_id_to_use) {
-        if pad.is_fully_used {
-            error!("Cannot encrypt with pad '{}' because it is fully used.", pad.id);
-            return;
+/// Escapes text for use as SVG element content or attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
         }
+    }
+    escaped
+}
 
-        let start_byte = offset;
+/// A source of plaintext or ciphertext.
+enum Input {
+    /// A file on disk.
+    File(PathBuf),
+    /// Standard input, selected with `-`.
+    Stdin,
+    /// Literal text given on the command line.
+    Text(String),
+    /// A directory tree packed into an archive stream.
+    Archive(PathBuf, archive::Source),
+}
 
-        info!("Encrypting '{}' with pad '{}' starting at byte {start_byte}.", input.display(), pad_id_to_use);
+impl Input {
+    fn from_args(path: Option<&PathBuf>, text: Option<&String>) -> Self {
+        match (path, text) {
+            (_, Some(text)) => Self::Text(text.clone()),
+            (Some(path), None) if !is_stdio(path) => Self::File(path.clone()),
+            _ => Self::Stdin,
+        }
+    }
 
-        let pad_path = vault_path.join("pads/available").join(&pad.file_name);
-        
-        let mut pad_file = fs::File::open(&pad_path).unwrap_or_else(|e| {
-            error!("Failed to open pad file: {e}");
-            std::process::exit(1);
+    /// Scans a directory for `encrypt --recursive`, reporting entries that cannot be archived.
+    fn archive(path: &Path) -> Self {
+        let (source, skipped) = archive::Source::scan(path).unwrap_or_else(|e| {
+            error!("Failed to read directory '{}': {e}", path.display());
+            exit(Exit::Storage);
         });
-        if let Err(e) = pad_file.seek(SeekFrom::Start(start_byte as u64)) {
-            error!("Failed to seek in pad file: {e}");
-            std::process::exit(1);
+        for skipped_path in skipped {
+            warn(&format!("skipping '{}': only regular files and directories are archived.", skipped_path.display()));
         }
-        
-        let mut pad_segment = vec![0u8; input_file_size];
-        if let Err(e) = pad_file.read_exact(&mut pad_segment) {
-            error!("Failed to read pad segment: {e}");
-            std::process::exit(1);
+        Self::Archive(path.components().collect(), source)
+    }
+
+    /// The input size if it is known before reading; piped input has no known size.
+    fn known_size(&self) -> Option<usize> {
+        match self {
+            Self::File(path) => {
+                let Ok(Ok(size)) = fs::metadata(path).map(|m| usize::try_from(m.len())) else {
+                    error!("Failed to get input file metadata");
+                    exit(Exit::Storage);
+                };
+                Some(size)
+            }
+            Self::Stdin => None,
+            Self::Text(text) => Some(text.len()),
+            Self::Archive(_, source) => Some(usize::try_from(source.archive_len()).unwrap_or(usize::MAX)),
         }
+    }
 
-        let input_file = fs::File::open(input).unwrap_or_else(|e| {
-            error!("Failed to open input file: {e}");
-            std::process::exit(1);
-        });
-        let mut output_file = fs::File::create(&output_path).unwrap_or_else(|e| {
-            error!("Failed to create output file: {e}");
-            std::process::exit(1);
-        });
-        
-        let mut hasher = Sha256::new();
-        let mut reader = std::io::BufReader::new(input_file);
-        let mut buffer = [0; 8192];
-        let mut total_bytes_processed = 0;
-
-        loop {
-            let bytes_read = reader.read(&mut buffer).unwrap_or_else(|e| {
-                error!("Failed to read from input: {e}");
-                std::process::exit(1);
-            });
-            if bytes_read == 0 { break; }
+    fn open(&self) -> Box<dyn Read + '_> {
+        match self {
+            Self::File(path) => Box::new(std::io::BufReader::new(fs::File::open(path).unwrap_or_else(|e| {
+                error!("Failed to open input file: {e}");
+                exit(Exit::Storage);
+            }))),
+            Self::Stdin => Box::new(std::io::stdin().lock()),
+            Self::Text(text) => Box::new(text.as_bytes()),
+            Self::Archive(_, source) => Box::new(source.reader()),
+        }
+    }
 
-            let input_chunk = &buffer[..bytes_read];
-            let pad_chunk = &pad_segment[total_bytes_processed..total_bytes_processed + bytes_read];
-            
-            let mut processed_chunk = Vec::with_capacity(bytes_read);
-            for (i, &byte) in input_chunk.iter().enumerate() {
-                processed_chunk.push(byte ^ pad_chunk[i]);
+    /// The inner header describing this input.
+    fn header(&self) -> envelope::FileHeader {
+        match self {
+            Self::File(path) | Self::Archive(path, _) => {
+                let mut header = envelope::FileHeader::from_path(path).unwrap_or_else(|e| {
+                    error!("Failed to get input file metadata: {e}");
+                    exit(Exit::Storage);
+                });
+                header.size = self.known_size().map(|size| size as u64);
+                header
             }
+            Self::Stdin => envelope::FileHeader::default(),
+            Self::Text(text) => envelope::FileHeader { size: Some(text.len() as u64), ..Default::default() },
+        }
+    }
 
-            if let Err(e) = output_file.write_all(&processed_chunk) {
-                error!("Failed to write to output: {e}");
-                std::process::exit(1);
-            }
-            hasher.update(&processed_chunk);
-            total_bytes_processed += bytes_read;
+    fn read_all(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Err(e) = self.open().read_to_end(&mut data) {
+            error!("Failed to read from input: {e}");
+            exit(Exit::Storage);
         }
-        
-        let ciphertext_hash = format!("{:x}", hasher.finalize());
+        data
+    }
 
-        let metadata = CiphertextMetadata {
-            pad_id: pad_id_to_use.clone(),
-            start_byte,
-            length: input_file_size,
-            ciphertext_hash,
-        };
+    fn describe(&self) -> String {
+        match self {
+            Self::File(path) => format!("file '{}'", path.display()),
+            Self::Stdin => "standard input".to_string(),
+            Self::Text(_) => "text".to_string(),
+            Self::Archive(path, source) => format!("directory '{}' ({} entries)", path.display(), source.entries().len()),
+        }
+    }
+}
 
-        let metadata_path = format!("{}.metadata.json", output_path.display());
-        let metadata_str = serde_json::to_string_pretty(&metadata).unwrap_or_else(|e| {
-            error!("Failed to serialize metadata: {e}");
-            std::process::exit(1);
-        });
-        if let Err(e) = fs::write(&metadata_path, metadata_str) {
-            error!("Failed to write metadata file: {e}");
-            std::process::exit(1);
+/// Whether a path argument is `-`, meaning stdin or stdout.
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Writes command output to a file, or to stdout if no path is given.
+fn write_output(path: Option<&Path>, data: &[u8]) {
+    // The JSON document owns stdout; the data travels inside it.
+    if path.is_none() && json_output() {
+        match std::str::from_utf8(data) {
+            Ok(text) => report("output", text),
+            Err(_) => report("output_base64", data_encoding::BASE64.encode(data)),
+        }
+        return;
+    }
+    let result = path.map_or_else(|| std::io::stdout().lock().write_all(data).and_then(|()| std::io::stdout().flush()), |path| fs::write(path, data));
+    if let Err(e) = result {
+        error!("Failed to write to output: {e}");
+        // Never leave a partly written file behind.
+        if let Some(path) = path {
+            let _ = fs::remove_file(path);
         }
+        exit(Exit::Storage);
+    }
+}
 
-        pad.used_segments.push(state_manager::UsedSegment {
-            start: start_byte,
-            end: start_byte + input_file_size,
-        });
+/// Encodes raw ciphertext for output. Armored output embeds the decryption metadata.
+fn encode_ciphertext(metadata: &CiphertextMetadata, ciphertext: &[u8]) -> Vec<u8> {
+    match metadata.encoding {
+        CiphertextEncoding::Raw => ciphertext.to_vec(),
+        CiphertextEncoding::Hex => (data_encoding::HEXLOWER.encode(ciphertext) + "\n").into_bytes(),
+        CiphertextEncoding::Base64 => (data_encoding::BASE64.encode(ciphertext) + "\n").into_bytes(),
+        CiphertextEncoding::Armor => {
+            let mode = serde_json::to_value(metadata.mode).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+            armor::armor(
+                &[
+                    ("Pad-Id", metadata.pad_id.clone()),
+                    ("Start-Byte", metadata.start_byte.to_string()),
+                    ("Length", metadata.length.to_string()),
+                    ("Mode", mode),
+                    ("Content", if metadata.archive { "archive" } else { "data" }.to_string()),
+                    ("Ciphertext-Hash", metadata.ciphertext_hash.clone()),
+                ],
+                ciphertext,
+            )
+            .into_bytes()
+        }
+    }
+}
 
-        let total_used_bytes = pad.total_used_bytes();
-        let usage_percent = (total_used_bytes as f64 / pad.size as f64) * 100.0;
-        pad.is_fully_used = pad.total_used_bytes() >= pad.size;
-        
-        let is_full = pad.is_fully_used;
-        let file_name_clone = pad.file_name.clone();
-        
-        if let Err(e) = state_manager::save_state(vault_path, &state) {
-            error!("Failed to save state after encryption: {e}");
-        }
-
-        println!("Pad '{pad_id_to_use}' is now {usage_percent:.2}% used.");
-        if is_full {
-            println!("Pad '{pad_id_to_use}' is now fully consumed. Moving to 'used' directory.");
-            let old_pad_path = vault_path.join("pads/available").join(&file_name_clone);
-            let used_pad_path = vault_path.join("pads/used").join(&file_name_clone);
-            if old_pad_path.exists() {
-                if let Err(e) = fs::rename(old_pad_path, used_pad_path) {
-                    error!("Failed to move used pad: {e}");
-                }
+/// Decodes hex or base64 ciphertext back to raw bytes.
+fn decode_ciphertext(encoding: CiphertextEncoding, data: &[u8]) -> Vec<u8> {
+    let compact: Vec<u8> = data.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    let decoded = match encoding {
+        CiphertextEncoding::Raw | CiphertextEncoding::Armor => return data.to_vec(),
+        CiphertextEncoding::Hex => data_encoding::HEXLOWER_PERMISSIVE.decode(&compact),
+        CiphertextEncoding::Base64 => data_encoding::BASE64.decode(&compact),
+    };
+    decoded.unwrap_or_else(|e| {
+        error!("Failed to decode ciphertext: {e}");
+        exit(Exit::InvalidInput);
+    })
+}
+
+/// Rebuilds decryption metadata from the headers of an armored message.
+fn metadata_from_armor(headers: &[(String, String)]) -> CiphertextMetadata {
+    let header = |name: &str| {
+        headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map_or_else(
+            || {
+                error!("Armored message is missing the '{name}' header.");
+                exit(Exit::InvalidInput);
+            },
+            |(_, value)| value.clone(),
+        )
+    };
+    let number = |name: &str| {
+        header(name).parse::<usize>().unwrap_or_else(|e| {
+            error!("Armored message has an invalid '{name}' header: {e}");
+            exit(Exit::InvalidInput);
+        })
+    };
+    let mode = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Mode"))
+        .and_then(|(_, value)| serde_json::from_value(serde_json::Value::String(value.clone())).ok())
+        .unwrap_or_default();
+    CiphertextMetadata {
+        pad_id: header("Pad-Id"),
+        start_byte: number("Start-Byte"),
+        length: number("Length"),
+        ciphertext_hash: header("Ciphertext-Hash"),
+        mode,
+        encoding: CiphertextEncoding::Armor,
+        archive: headers.iter().any(|(key, value)| key.eq_ignore_ascii_case("Content") && value == "archive"),
+    }
+}
+
+/// Returns the `length` pad bytes from `start`, exiting if the range ends past `usize::MAX`.
+fn pad_range(start: usize, length: usize) -> Range<usize> {
+    let Some(end) = start.checked_add(length) else {
+        error!("A range of {length} bytes from offset {start} ends beyond any pad.");
+        exit(Exit::InvalidInput);
+    };
+    start..end
+}
+
+/// Chooses the pad and the pad byte range for a new message.
+///
+/// With a known `length` the range is exactly that long. For input of unknown length the
+/// whole free gap is reserved and the message may use any prefix of it. Returns the pad
+/// ID, the reserved range and whether the pad was selected automatically.
+fn reserve_segment(
+    state: &state_manager::VaultState,
+    pad_id: Option<&str>,
+    candidates: Option<&[String]>,
+    offset: Option<usize>,
+    length: Option<usize>,
+) -> Result<(String, Range<usize>, bool), String> {
+    let fits = |pad: &state_manager::Pad| -> Option<Range<usize>> {
+        if pad.is_fully_used || candidates.is_some_and(|ids| !ids.contains(&pad.id)) {
+            return None;
+        }
+        match (offset, length) {
+            (Some(start), Some(length)) => {
+                let range = pad_range(start, length);
+                (pad.is_range_available(range.start, range.end) && pad.is_range_allowed(range.start, range.end)).then_some(range)
             }
+            (None, Some(length)) => pad.find_available_segment(length).map(|start| start..start + length),
+            (Some(start), None) => pad.allocatable_ranges().into_iter().find(|r| r.contains(&start)).map(|r| start..r.end),
+            // Input of unknown length fills a gap from its start, which a pad used from the back must not do.
+            (None, None) if pad.direction == Some(state_manager::Direction::Back) => None,
+            (None, None) => pad.largest_free_range(),
         }
-        println!("Successfully encrypted file '{}' to '{}'", input.display(), output_path.display());
-        println!("Decryption metadata saved to '{metadata_path}'");
+    };
 
+    if let Some(pad_id) = pad_id {
+        let pad = state.pads.get(pad_id).ok_or_else(|| format!("Pad with ID '{pad_id}' not found."))?;
+        if pad.is_fully_used {
+            return Err(format!("Cannot encrypt with pad '{pad_id}' because it is fully used."));
+        }
+        if let Some(reason) = pad.encrypt_refusal(timestamp::now()) {
+            return Err(format!("Cannot encrypt with pad '{pad_id}' because {reason}."));
+        }
+        if offset.is_none() && length.is_none() && pad.direction == Some(state_manager::Direction::Back) {
+            return Err(format!("Pad '{pad_id}' is consumed from the back; pass --max-size for input of unknown length."));
+        }
+        return fits(pad)
+            .map(|range| (pad_id.to_string(), range, false))
+            .ok_or_else(|| format!("Pad '{pad_id}' has no free segment at the requested position and size that its direction policy allows."));
+    }
+
+    let candidate = if length.is_some() {
+        state.pads.values().find_map(|pad| fits(pad).map(|range| (pad, range)))
     } else {
-        error!("Pad with ID '{pad_id_to_use}' not found.");
+        state.pads.values().filter_map(|pad| fits(pad).map(|range| (pad, range))).max_by_key(|(_, range)| range.len())
+    };
+    let scope = if candidates.is_some() { " shared with the recipient" } else { "" };
+    candidate.map(|(pad, range)| (pad.id.clone(), range, true)).ok_or_else(|| {
+        length.map_or_else(
+            || format!("Could not find an available pad{scope} with free space."),
+            |length| format!("Could not find an available pad{scope} with enough contiguous space ({length} bytes)."),
+        )
+    })
+}
+
+/// XORs everything from `reader` with pad bytes from `pad_file`, writing the result to `writer`.
+///
+/// Returns the number of bytes written and the SHA-256 of the output. If the input
+/// exceeds `capacity` the error is returned together with the count of bytes that were
+/// already written, because those pad bytes are spent even though the message is not.
+fn xor_stream(reader: &mut dyn Read, pad_file: &mut fs::File, writer: &mut dyn Write, capacity: usize) -> Result<(usize, String), (usize, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+    let mut pad_buffer = [0; 8192];
+    let mut total_bytes_processed = 0;
+    loop {
+        let bytes_read = reader.read(&mut buffer).map_err(|e| (total_bytes_processed, format!("Failed to read from input: {e}")))?;
+        if bytes_read == 0 {
+            break;
+        }
+        if total_bytes_processed + bytes_read > capacity {
+            return Err((total_bytes_processed, format!("Input exceeds the {capacity} bytes of pad space reserved for it.")));
+        }
+        let pad_chunk = &mut pad_buffer[..bytes_read];
+        pad_file.read_exact(pad_chunk).map_err(|e| (total_bytes_processed, format!("Failed to read pad segment: {e}")))?;
+
+        let processed_chunk = crypto::xor(&buffer[..bytes_read], pad_chunk);
+        writer.write_all(&processed_chunk).map_err(|e| (total_bytes_processed, format!("Failed to write to output: {e}")))?;
+        hasher.update(&processed_chunk);
+        total_bytes_processed += bytes_read;
+    }
+    writer.flush().map_err(|e| (total_bytes_processed, format!("Failed to write to output: {e}")))?;
+    Ok((total_bytes_processed, format!("{:x}", hasher.finalize())))
+}
+
+/// Records a consumed segment, saves the state and moves the pad to `used` once it is full.
+/// Free bytes a watermark skips to get there are warned about and logged.
+///
+/// Returns whether the pad became fully consumed, or the error if the state could not be saved.
+fn record_used_segment(state: &mut state_manager::VaultState, vault_path: &Path, pad_id: &str, segment: state_manager::UsedSegment) -> std::io::Result<bool> {
+    let Some(pad) = state.pads.get_mut(pad_id) else {
+        return Ok(false);
+    };
+    let skipped = if segment.start < segment.end { pad.record_use(segment) } else { 0..0 };
+    let was_available = !pad.is_fully_used;
+    pad.is_fully_used = pad.total_used_bytes() >= pad.size;
+    let became_full = pad.is_fully_used && was_available;
+    let file_name = pad.file_name.clone();
+
+    state_manager::save_state(vault_path, state)?;
+    if became_full {
+        move_pad_to_used(vault_path, &file_name);
+    }
+    warn_skipped(vault_path, pad_id, &[skipped]);
+    Ok(became_full)
+}
+
+/// Warns about and logs the free bytes of a watermark pad that recording a use above the
+/// watermark gave up.
+fn warn_skipped(vault_path: &Path, pad_id: &str, skipped: &[Range<usize>]) {
+    for range in skipped.iter().filter(|range| !range.is_empty()) {
+        warn(&format!(
+            "the watermark of pad '{pad_id}' moved past the {} free byte(s) in {}..{}, which will never be used.",
+            range.len(),
+            range.start,
+            range.end
+        ));
+        audit_log(vault_path, audit_record(audit::Operation::Burn).pad(pad_id).range(range.clone()).detail(format!("watermark skipped {} free byte(s)", range.len())));
+    }
+}
+
+/// Starts an audit record of an operation performed through the command line.
+fn audit_record(op: audit::Operation) -> audit::Record {
+    audit::Record::new(audit::Interface::Cli, op)
+}
+
+/// Names the written file in `record`, if there is one.
+fn with_file(record: audit::Record, path: Option<&Path>) -> audit::Record {
+    match path {
+        Some(path) => record.file(path),
+        None => record,
+    }
+}
+
+/// Appends to the vault's audit log. A failure is reported but does not undo the operation.
+fn audit_log(vault_path: &Path, record: audit::Record) {
+    if let Err(e) = audit::append(vault_path, record) {
+        error!("Failed to write the audit log: {e}");
+    }
+}
+
+/// Parses an audit operation name such as `encrypt`.
+fn parse_operation(value: &str) -> Result<audit::Operation, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| "expected one of generate, import, export, encrypt, decrypt, burn, retire, revoke, delete, clear, repair".to_string())
+}
+
+/// Parses a pad label argument.
+fn parse_label(value: &str) -> Result<String, String> {
+    state_manager::validate_label(value).map(|()| value.to_string())
+}
+
+/// Parses a `YYYY-MM-DD` day argument into seconds since the Unix epoch.
+fn parse_day(value: &str) -> Result<u64, String> {
+    timestamp::parse_date(value).ok_or_else(|| format!("'{value}' is not a date in the form YYYY-MM-DD between 1970 and {}", timestamp::MAX_YEAR))
+}
+
+/// Warns that a pad about to encrypt will soon stop doing so.
+fn warn_if_expiring(pad: &state_manager::Pad) {
+    if let Some(expiry) = pad.expires_at.filter(|_| pad.status() == state_manager::PadStatus::Expiring) {
+        warn(&format!("pad '{}' expires at {}; exchange a replacement soon.", pad.id, timestamp::format_utc(expiry)));
+    }
+}
+
+/// Prints the banner for pad bytes that served two different messages.
+fn print_reuse_alert(explanation: &str, collisions: &[(String, Range<usize>)]) {
+    if json_output() {
+        warn(&format!("PAD REUSE DETECTED: {explanation} The affected pads are quarantined."));
+        report("reuse", collisions.iter().map(|(pad_id, range)| serde_json::json!({ "pad_id": pad_id, "start": range.start, "end": range.end })).collect::<Vec<_>>());
+        return;
+    }
+    eprintln!("{:!<60}", "");
+    eprintln!("WARNING: PAD REUSE DETECTED");
+    eprintln!("{explanation}");
+    eprintln!("Messages encrypted with these ranges are no longer secure:");
+    for (pad_id, range) in collisions {
+        eprintln!("  pad '{pad_id}' bytes {}..{} ({} bytes)", range.start, range.end, range.len());
+    }
+    eprintln!("The affected pads are quarantined and will not be used to encrypt again.");
+    eprintln!("{:!<60}", "");
+}
+
+/// Moves a fully consumed pad file from `available` to `used`.
+fn move_pad_to_used(vault_path: &Path, file_name: &str) {
+    let old_pad_path = vault_path.join("pads/available").join(file_name);
+    let used_pad_path = vault_path.join("pads/used").join(file_name);
+    if old_pad_path.exists()
+        && let Err(e) = fs::rename(old_pad_path, used_pad_path)
+    {
+        error!("Failed to move used pad: {e}");
     }
 }
 
-fn handle_decrypt_command(input: &Path, output: &Path, metadata: Option<&PathBuf>, pad_id: Option<&str>, length: usize, offset: usize, vault_path: &Path) {
+fn handle_encrypt_command(args: &EncryptArgs, vault_path: &Path, config: &config::Config) {
     let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
         error!("Failed to load vault state: {e}");
-        std::process::exit(1);
+        exit(Exit::Storage);
     });
+    let mut input = Input::from_args(args.input.as_ref(), args.text.as_ref());
+    if let Input::File(path) = &input
+        && path.is_dir()
+    {
+        if !args.recursive {
+            error!("'{}' is a directory. Use --recursive to encrypt it as an archive.", path.display());
+            exit(Exit::InvalidInput);
+        }
+        if args.mode != CipherMode::Bytes {
+            error!("Directories can only be encrypted in bytes mode.");
+            exit(Exit::InvalidInput);
+        }
+        input = Input::archive(path);
+    }
 
-    let dec_info = if let Some(meta_path) = metadata {
-        let metadata_str = fs::read_to_string(meta_path).unwrap_or_else(|e| {
-            error!("Failed to read metadata file: {e}");
-            std::process::exit(1);
+    let output_path = match (&args.output, &input) {
+        (Some(path), _) if is_stdio(path) => None,
+        (Some(path), _) => Some(path.clone()),
+        (None, Input::File(path) | Input::Archive(path, _)) => {
+            let mut new_path = path.as_os_str().to_owned();
+            new_path.push(".enc");
+            Some(PathBuf::from(new_path))
+        }
+        (None, _) => None,
+    };
+    let configured = config.encoding.as_deref().map(|name| {
+        CiphertextEncoding::from_str(name, true).unwrap_or_else(|_| {
+            error!("Invalid configured encoding '{name}'; expected raw, hex, base64 or armor.");
+            exit(Exit::InvalidInput);
+        })
+    });
+    let encoding = args.encoding.or(configured).unwrap_or_else(|| if output_path.is_some() { CiphertextEncoding::Raw } else { CiphertextEncoding::Armor });
+    let metadata_path = args.metadata_out.clone().or_else(|| {
+        (encoding != CiphertextEncoding::Armor).then(|| output_path.as_ref().map(|p| PathBuf::from(format!("{}.metadata.json", p.display())))).flatten()
+    });
+    if metadata_path.is_none() && encoding != CiphertextEncoding::Armor {
+        error!("Writing ciphertext to stdout requires --metadata-out or --encoding armor.");
+        exit(Exit::InvalidInput);
+    }
+    if let Some(pad_id) = &args.pad_id {
+        find_pad(&state, pad_id);
+    }
+    let candidates = args.to.as_deref().map(|name| contact_pad_ids(&state, name, args.pad_id.as_deref()));
+    // Status messages must not mix with ciphertext written to stdout.
+    let say = |message: String| if output_path.is_some() { out!("{message}") } else if !json_output() { eprintln!("{message}") };
+
+    let (pad_id, start_byte, consumed, ciphertext, ciphertext_hash, seq) = if let Some(table) = args.mode.table() {
+        let plaintext = String::from_utf8(input.read_all()).unwrap_or_else(|e| {
+            error!("Failed to read input as text: {e}");
+            exit(Exit::InvalidInput);
+        });
+        let symbols = letters::encode_plaintext(table, &plaintext).unwrap_or_else(|e| {
+            error!("Cannot encrypt input in this mode: {e}");
+            exit(Exit::InvalidInput);
+        });
+        let (pad_id, reserved, auto) = reserve_segment(&state, args.pad_id.as_deref(), candidates.as_deref(), args.offset, Some(letters::pad_budget(symbols.len()))).unwrap_or_else(|e| {
+            error!("{e}");
+            exit(Exit::PadUnavailable);
         });
-        let meta: CiphertextMetadata = serde_json::from_str(&metadata_str).unwrap_or_else(|e| {
-            error!("Failed to parse metadata file: {e}");
-            std::process::exit(1);
+        if auto {
+            say(format!("Automatically selected pad '{pad_id}'"));
+        }
+        report("auto_selected", auto);
+        warn_if_expiring(find_pad(&state, &pad_id));
+        // Read the whole reservation, then keep only the bytes rejection sampling consumed.
+        let pad_bytes = read_pad_range(vault_path, find_pad(&state, &pad_id), &reserved);
+        let Some((key, consumed)) = letters::key_stream(table, &pad_bytes, symbols.len()) else {
+            error!("Not enough pad material after byte {} for {} symbols.", reserved.start, symbols.len());
+            exit(Exit::PadUnavailable);
+        };
+        let ciphertext = (letters::format_groups(table, &letters::encrypt(table, &symbols, &key)) + "\n").into_bytes();
+        let hash = format!("{:x}", Sha256::digest(&ciphertext));
+        (pad_id, reserved.start, consumed, Some(ciphertext), hash, None)
+    } else {
+        // The original name and attributes travel encrypted in front of the payload, together
+        // with this vault's sequence number. Space is reserved for the widest possible number.
+        let sender = state.vault_id().to_string();
+        let mut header = (!args.no_header).then(|| envelope::FileHeader { sender: Some(sender), seq: Some(u64::MAX), ..input.header() });
+        let wrapped_size = input
+            .known_size()
+            .or(args.max_size)
+            .map(|size| header.as_ref().map_or(size, |header| envelope::wrapped_len(header, size)));
+        let reservation = reserve_segment(&state, args.pad_id.as_deref(), candidates.as_deref(), args.offset, wrapped_size).or_else(|e| {
+            // Input that fits only without the header is sent the way it was before the header existed
+            let size = input.known_size().filter(|_| header.is_some()).ok_or_else(|| e.clone())?;
+            let reservation = reserve_segment(&state, args.pad_id.as_deref(), candidates.as_deref(), args.offset, Some(size)).map_err(|_| e)?;
+            warn("the pad has no room for the inner header; the message is sent without its file name, attributes and sequence number.");
+            header = None;
+            Ok(reservation)
+        });
+        let (pad_id, mut reserved, auto) = reservation.unwrap_or_else(|e: String| {
+            error!("{e}");
+            error!("Please generate a new pad with 'pad generate'.");
+            exit(Exit::PadUnavailable);
         });
+        if auto {
+            say(format!("Automatically selected pad '{pad_id}'"));
+        }
+        report("auto_selected", auto);
+        warn_if_expiring(find_pad(&state, &pad_id));
+        if let Some(header) = header.as_mut() {
+            let pad = find_pad(&state, &pad_id);
+            header.seq = Some(pad.sent_sequence + 1);
+            // Give back the unused part of the reservation, keeping it at the end the policy consumes from.
+            if let Some(exact) = input.known_size().or(args.max_size).map(|size| envelope::wrapped_len(header, size)) {
+                reserved = if args.offset.is_none() && pad.direction == Some(state_manager::Direction::Back) {
+                    reserved.end - exact..reserved.end
+                } else {
+                    reserved.start..reserved.start + exact
+                };
+            }
+        }
+        info!("Encrypting {} with pad '{pad_id}' starting at byte {}.", input.describe(), reserved.start);
 
-        let mut hasher = Sha256::new();
-        let mut ciphertext_file = fs::File::open(input).unwrap_or_else(|e| {
-            error!("Failed to open ciphertext file: {e}");
-            std::process::exit(1);
+        let mut pad_file = fs::File::open(pad_file_path(vault_path, find_pad(&state, &pad_id))).unwrap_or_else(|e| {
+            error!("Failed to open pad file: {e}");
+            exit(Exit::Storage);
         });
-        if let Err(e) = std::io::copy(&mut ciphertext_file, &mut hasher) {
-            error!("Failed to hash ciphertext: {e}");
-            std::process::exit(1);
+        if let Err(e) = pad_file.seek(SeekFrom::Start(reserved.start as u64)) {
+            error!("Failed to seek in pad file: {e}");
+            exit(Exit::Storage);
         }
-        let calculated_hash = format!("{:x}", hasher.finalize());
 
-        if calculated_hash != meta.ciphertext_hash {
-            error!("Ciphertext hash does not match metadata hash. The file may be corrupt or tampered with. Aborting.");
-            return;
+        // Raw output is streamed straight to its destination; text encodings need the whole
+        // ciphertext, and so does raw output that has to travel inside the JSON document.
+        let streamed = encoding == CiphertextEncoding::Raw && !(json_output() && output_path.is_none());
+        let mut buffer = Vec::new();
+        let mut writer: Box<dyn Write> = match (&output_path, streamed) {
+            (Some(path), true) => Box::new(std::io::BufWriter::new(fs::File::create(path).unwrap_or_else(|e| {
+                error!("Failed to create output file: {e}");
+                exit(Exit::Storage);
+            }))),
+            (None, true) => Box::new(std::io::stdout().lock()),
+            (_, false) => Box::new(&mut buffer),
+        };
+        let mut reader: Box<dyn Read> = match &header {
+            Some(header) => Box::new(envelope::Wrap::new(header, input.open())),
+            None => input.open(),
+        };
+        let result = xor_stream(&mut reader, &mut pad_file, &mut writer, reserved.len());
+        drop(reader);
+        drop(writer);
+        match result {
+            Ok((consumed, hash)) => {
+                let seq = header.and_then(|h| h.seq);
+                if let (Some(seq), Some(pad)) = (seq, state.pads.get_mut(&pad_id)) {
+                    pad.sent_sequence = seq;
+                }
+                (pad_id, reserved.start, consumed, (!streamed).then_some(buffer), hash, seq)
+            }
+            Err((written, message)) => {
+                error!("{message}");
+                if streamed && output_path.is_none() && written > 0 {
+                    // Ciphertext already left through stdout; never hand these pad bytes out again.
+                    let segment = state_manager::UsedSegment::consumed(reserved.start..reserved.start + written, state_manager::SegmentOperation::Encrypt)
+                        .seq(header.as_ref().and_then(|h| h.seq))
+                        .counterparty(counterparty_for(&state, args.to.as_deref(), &pad_id));
+                    if let Err(e) = record_used_segment(&mut state, vault_path, &pad_id, segment) {
+                        error!("Failed to save state: {e}. Pad '{pad_id}' bytes {}..{} were sent but not recorded; do not use them again.", reserved.start, reserved.start + written);
+                    }
+                    audit_log(vault_path, audit_record(audit::Operation::Encrypt).pad(&pad_id).range(reserved.start..reserved.start + written).detail("aborted"));
+                } else if let Some(path) = output_path.as_ref().filter(|_| streamed) {
+                    let _ = fs::remove_file(path);
+                }
+                exit(Exit::Storage);
+            }
         }
-        DecryptionInfo {
-            pad_id: meta.pad_id,
-            start_byte: meta.start_byte,
-            length: meta.length,
+    };
+
+    let metadata = CiphertextMetadata {
+        pad_id: pad_id.clone(),
+        start_byte,
+        length: consumed,
+        ciphertext_hash,
+        mode: args.mode,
+        encoding,
+        archive: matches!(input, Input::Archive(..)),
+    };
+    // The pad bytes are recorded before buffered ciphertext is written, and streamed
+    // ciphertext is removed if they cannot be, so no message outlives a failed save.
+    let segment = state_manager::UsedSegment::consumed(start_byte..start_byte + consumed, state_manager::SegmentOperation::Encrypt)
+        .digest(metadata.ciphertext_hash.clone())
+        .seq(seq)
+        .counterparty(counterparty_for(&state, args.to.as_deref(), &pad_id));
+    let is_full = record_used_segment(&mut state, vault_path, &pad_id, segment).unwrap_or_else(|e| {
+        error!("Failed to save state: {e}");
+        match (&ciphertext, &output_path) {
+            (None, Some(path)) => {
+                let _ = fs::remove_file(path);
+                info!("Removed the unrecorded ciphertext '{}'.", path.display());
+            }
+            (None, None) => error!("The ciphertext written to standard output was not recorded; discard it."),
+            (Some(_), _) => {}
         }
-    } else {
-        DecryptionInfo {
-            pad_id: pad_id.unwrap_or_default().to_string(),
-            start_byte: offset,
-            length,
+        exit(Exit::Storage);
+    });
+    if let Some(ciphertext) = ciphertext {
+        write_output(output_path.as_deref(), &encode_ciphertext(&metadata, &ciphertext));
+    }
+    if let Some(metadata_path) = &metadata_path {
+        let written = serde_json::to_string_pretty(&metadata).map_err(std::io::Error::from).and_then(|text| fs::write(metadata_path, text));
+        if let Err(e) = written {
+            error!("Failed to write metadata file: {e}");
+            // Without its metadata the ciphertext cannot be decrypted; its pad bytes stay used.
+            for path in [Some(metadata_path), output_path.as_ref()].into_iter().flatten() {
+                let _ = fs::remove_file(path);
+            }
+            exit(Exit::Storage);
         }
+    }
+    let record = audit_record(audit::Operation::Encrypt).pad(&pad_id).range(start_byte..start_byte + consumed);
+    let record = match &args.to {
+        Some(name) => record.detail(format!("to {name}")),
+        None => record,
     };
-    
-    if let Some(pad) = state.pads.get_mut(&dec_info.pad_id) {
-        let pad_dir = if pad.is_fully_used { "used" } else { "available" };
-        let pad_path = vault_path.join("pads").join(pad_dir).join(&pad.file_name);
+    audit_log(vault_path, with_file(record, output_path.as_deref()));
+    let pad = find_pad(&state, &pad_id);
+    let usage_percent = (pad.total_used_bytes() as f64 / pad.size as f64) * 100.0;
+    say(format!("Pad '{pad_id}' is now {usage_percent:.2}% used."));
+    if is_full {
+        say(format!("Pad '{pad_id}' is now fully consumed. Moving to 'used' directory."));
+    }
+    let destination = output_path.as_ref().map_or_else(|| "standard output".to_string(), |p| format!("'{}'", p.display()));
+    say(format!("Successfully encrypted {} to {destination}", input.describe()));
+    if args.mode != CipherMode::Bytes {
+        let mode_name = args.mode.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default();
+        say(format!("Key: pad '{pad_id}' from byte {start_byte}. For hand decryption print it with:"));
+        say(format!("  otp-cli pad print {pad_id} --range {start_byte}..{} --key-sheet {mode_name}", start_byte + consumed));
+    }
+    if let Some(metadata_path) = &metadata_path {
+        say(format!("Decryption metadata saved to '{}'", metadata_path.display()));
+    }
+    report("pad_id", &pad_id);
+    report("start_byte", start_byte);
+    report("length", consumed);
+    report("seq", seq);
+    report("mode", args.mode);
+    report("encoding", encoding);
+    report("file", &output_path);
+    report("metadata_file", &metadata_path);
+    report("pad_fully_used", is_full);
+}
+
+/// Classifies a message's sequence number against the pad's receive window for its
+/// sender and records it there.
+///
+/// Returns `None` for messages without a sequence number and for this vault's own messages.
+fn track_sequence(state: &mut state_manager::VaultState, pad_id: &str, header: &envelope::FileHeader) -> Option<(u64, sequence::Delivery)> {
+    let (Some(sender), Some(seq)) = (header.sender.as_deref(), header.seq) else {
+        return None;
+    };
+    if sender == state.vault_id {
+        return None;
+    }
+    let window = state.pads.get_mut(pad_id)?.received_sequences.entry(sender.to_string()).or_default();
+    let delivery = window.check(seq);
+    window.accept(seq);
+    Some((seq, delivery))
+}
 
-        if !pad_path.exists() {
-            error!("Pad file '{}' not found in vault. It may have been moved or deleted.", pad.file_name);
-            return;
+/// Decrypts grouped letter or digit ciphertext with the key drawn from `pad_segment`.
+fn decrypt_letters(ciphertext: &[u8], table: letters::Table, pad_segment: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(ciphertext);
+    let symbols = letters::parse_groups(table, &text).unwrap_or_else(|e| {
+        error!("Failed to parse ciphertext groups: {e}");
+        exit(Exit::InvalidInput);
+    });
+    let Some((key, _)) = letters::key_stream(table, pad_segment, symbols.len()) else {
+        error!("The pad segment is too short for {} ciphertext symbols.", symbols.len());
+        exit(Exit::InvalidInput);
+    };
+    (letters::decode_plaintext(table, &letters::decrypt(table, &symbols, &key)) + "\n").into_bytes()
+}
+
+/// Works out where decrypted output goes; `None` means standard output.
+///
+/// Without `--output`, or with `--output` naming an existing directory, the file name
+/// from the inner header is used. An implicitly named file is never overwritten.
+fn resolve_decrypt_output(args: &DecryptArgs, header: Option<&envelope::FileHeader>, is_archive: bool) -> Option<PathBuf> {
+    let name = header.and_then(envelope::FileHeader::safe_file_name);
+    let named = |dir: &Path| {
+        let Some(name) = name else {
+            error!("The message does not carry a file name. Pass the output file with --output.");
+            exit(Exit::InvalidInput);
+        };
+        let path = dir.join(name);
+        if path.exists() {
+            error!("'{}' already exists. Pass --output to choose another name.", path.display());
+            exit(Exit::InvalidInput);
         }
+        path
+    };
+    match args.output.as_deref() {
+        Some(path) if is_stdio(path) => None,
+        Some(path) if path.is_dir() && !is_archive => Some(named(path)),
+        Some(path) => Some(path.to_path_buf()),
+        None if args.list => None,
+        None => Some(named(Path::new(""))),
+    }
+}
 
-        let mut pad_file = fs::File::open(&pad_path).unwrap_or_else(|e| {
-            error!("Failed to open pad file: {e}");
-            std::process::exit(1);
-        });
-        if let Err(e) = pad_file.seek(SeekFrom::Start(dec_info.start_byte as u64)) {
-            error!("Failed to seek in pad file: {e}");
-            std::process::exit(1);
+/// The hidden sibling of `path` a decrypted file is written to before it is renamed.
+fn staging_file(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".otp-partial");
+    path.with_file_name(name)
+}
+
+/// Removes a staged file or directory. Failing to is not worth more than the error that
+/// led here.
+fn discard_staged(stage: &Path) {
+    let _ = if stage.is_dir() { fs::remove_dir_all(stage) } else { fs::remove_file(stage) };
+}
+
+/// Finds a file below `from` whose counterpart below `to` already exists.
+fn first_existing(from: &Path, to: &Path) -> std::io::Result<Option<PathBuf>> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() && target.is_dir() {
+            if let Some(path) = first_existing(&entry.path(), &target)? {
+                return Ok(Some(path));
+            }
+        } else if target.symlink_metadata().is_ok() {
+            return Ok(Some(target));
         }
-        let mut pad_segment = vec![0u8; dec_info.length];
-        if let Err(e) = pad_file.read_exact(&mut pad_segment) {
-            error!("Failed to read pad segment: {e}");
-            std::process::exit(1);
+    }
+    Ok(None)
+}
+
+/// Moves everything below `from` into `to`, merging directories that exist in both.
+fn move_into(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() && target.is_dir() {
+            move_into(&entry.path(), &target)?;
+        } else {
+            fs::rename(entry.path(), target)?;
         }
+    }
+    Ok(())
+}
 
-        let input_file = fs::File::open(input).unwrap_or_else(|e| {
-            error!("Failed to re-open input file: {e}");
-            std::process::exit(1);
-        });
-        let mut output_file = fs::File::create(output).unwrap_or_else(|e| {
-            error!("Failed to create output file: {e}");
-            std::process::exit(1);
-        });
+fn handle_decrypt_command(args: &DecryptArgs, vault_path: &Path) {
+    let mut state = state_manager::load_state(vault_path).unwrap_or_else(|e| {
+        error!("Failed to load vault state: {e}");
+        exit(Exit::Storage);
+    });
+    let input = Input::from_args(Some(&args.input), None);
+    let to_stdout = args.list || args.output.as_deref().is_some_and(is_stdio);
+    let say = |message: String| if !to_stdout { out!("{message}") } else if !json_output() { eprintln!("{message}") };
+    let alert = |message: String| if json_output() { warn(&message) } else { say(format!("WARNING: {message}")) };
+    let raw_input = input.read_all();
 
-        let mut reader = std::io::BufReader::new(input_file);
-        let mut buffer = [0; 8192];
-        let mut total_bytes_processed = 0;
-        loop {
-            let bytes_read = reader.read(&mut buffer).unwrap_or_else(|e| {
-                error!("Failed to read from input: {e}");
-                std::process::exit(1);
+    let armored = args.encoding == Some(CiphertextEncoding::Armor) || (args.encoding.is_none() && armor::is_armored(&raw_input));
+    let (mut dec_info, ciphertext, expected_hash) = match &args.metadata {
+        Some(meta_path) => {
+            let metadata_str = fs::read_to_string(meta_path).unwrap_or_else(|e| {
+                error!("Failed to read metadata file: {e}");
+                exit(Exit::Storage);
             });
-            if bytes_read == 0 { break; }
-
-            let input_chunk = &buffer[..bytes_read];
-            let pad_chunk = &pad_segment[total_bytes_processed..total_bytes_processed + bytes_read];
-            
-            let mut processed_chunk = Vec::with_capacity(bytes_read);
-            for (i, &byte) in input_chunk.iter().enumerate() {
-                processed_chunk.push(byte ^ pad_chunk[i]);
-            }
-            
-            if let Err(e) = output_file.write_all(&processed_chunk) {
-                error!("Failed to write to output: {e}");
-                std::process::exit(1);
-            }
-            total_bytes_processed += bytes_read;
-        }
-
-        let new_segment = state_manager::UsedSegment { start: dec_info.start_byte, end: dec_info.start_byte + dec_info.length };
-        if !pad.used_segments.iter().any(|s| s.start == new_segment.start && s.end == new_segment.end) {
-            pad.used_segments.push(new_segment);
-            let was_available = !pad.is_fully_used;
-            pad.is_fully_used = pad.total_used_bytes() >= pad.size;
-            if pad.is_fully_used && was_available {
-                let file_name_clone = pad.file_name.clone();
-                if let Err(e) = state_manager::save_state(vault_path, &state) {
-                    error!("Failed to save state after decryption: {e}");
-                }
-                info!("Pad '{}' is now fully consumed on receiver side. Moving to 'used' directory.", dec_info.pad_id);
-                let old_pad_path = vault_path.join("pads/available").join(&file_name_clone);
-                let used_pad_path = vault_path.join("pads/used").join(&file_name_clone);
-                if old_pad_path.exists() {
-                    if let Err(e) = fs::rename(old_pad_path, used_pad_path) {
-                        error!("Failed to move used pad: {e}");
-                    }
-                }
+            let meta: CiphertextMetadata = serde_json::from_str(&metadata_str).unwrap_or_else(|e| {
+                error!("Failed to parse metadata file: {e}");
+                exit(Exit::InvalidInput);
+            });
+            let ciphertext = if meta.encoding == CiphertextEncoding::Armor {
+                armor::dearmor(&String::from_utf8_lossy(&raw_input)).map_or_else(
+                    |e| {
+                        error!("Failed to read armored message: {e}");
+                        exit(Exit::InvalidInput);
+                    },
+                    |(_, body)| body,
+                )
+            } else {
+                decode_ciphertext(meta.encoding, &raw_input)
+            };
+            let hash = meta.ciphertext_hash.clone();
+            (DecryptionInfo::from(meta), ciphertext, Some(hash))
+        }
+        None if armored => {
+            let (headers, ciphertext) = armor::dearmor(&String::from_utf8_lossy(&raw_input)).unwrap_or_else(|e| {
+                error!("Failed to read armored message: {e}");
+                exit(Exit::InvalidInput);
+            });
+            let meta = metadata_from_armor(&headers);
+            let hash = meta.ciphertext_hash.clone();
+            (DecryptionInfo::from(meta), ciphertext, Some(hash))
+        }
+        None => {
+            let info = DecryptionInfo {
+                pad_id: args.pad_id.clone().unwrap_or_default(),
+                start_byte: args.offset,
+                length: args.length.unwrap_or(0),
+                mode: args.mode,
+                archive: args.archive,
+            };
+            (info, decode_ciphertext(args.encoding.unwrap_or_default(), &raw_input), None)
+        }
+    };
+
+    let digest = format!("{:x}", Sha256::digest(&ciphertext));
+    if let Some(expected_hash) = expected_hash
+        && digest != expected_hash
+    {
+        error!("Ciphertext hash does not match metadata hash. The file may be corrupt or tampered with. Aborting.");
+        exit(Exit::Integrity);
+    }
+
+    let Some(pad) = state.pads.get(&dec_info.pad_id) else {
+        error!("Pad with ID '{}' not found in vault.", dec_info.pad_id);
+        exit(Exit::NotFound);
+    };
+    let contact = state.contact_for_pad(&dec_info.pad_id).map(|contact| contact.name.clone());
+    if let Some(name) = &contact {
+        say(format!("From: {name}"));
+    }
+    if let Some(reason) = pad.encrypt_refusal(timestamp::now()) {
+        let consequence = if pad.status() == state_manager::PadStatus::Compromised {
+            "others may have read this message, and anyone holding the pad could have written it"
+        } else {
+            "only messages sent before then should use it"
+        };
+        alert(format!("pad '{}' is {} because {reason}; {consequence}.", pad.id, pad.status()));
+    }
+    if !pad_file_path(vault_path, pad).exists() {
+        error!("Pad file '{}' not found in vault. It may have been moved or deleted.", pad.file_name);
+        exit(Exit::NotFound);
+    }
+    // Manual decryption documents `--length <FILE_SIZE>`. The inner header makes the
+    // ciphertext longer than the file, so the message then spans the whole ciphertext.
+    let manual = args.metadata.is_none() && !armored && dec_info.mode == CipherMode::Bytes;
+    let file_size = (manual && ciphertext.len() > dec_info.length).then_some(dec_info.length);
+    if file_size.is_some() {
+        dec_info.length = ciphertext.len();
+    }
+    let segment = pad_range(dec_info.start_byte, dec_info.length);
+    let pad_segment = read_pad_range(vault_path, pad, &segment);
+
+    if dec_info.mode == CipherMode::Bytes && ciphertext.len() > pad_segment.len() {
+        error!("Ciphertext is longer than the {} byte pad segment.", pad_segment.len());
+        exit(Exit::InvalidInput);
+    }
+    let plaintext = dec_info.mode.table().map_or_else(
+        || crypto::xor(&ciphertext, &pad_segment[..ciphertext.len()]),
+        |table| decrypt_letters(&ciphertext, table, &pad_segment),
+    );
+    let (header, payload) = match envelope::unwrap(&plaintext) {
+        Ok(Some((header, payload))) => (Some(header), payload),
+        Ok(None) => (None, plaintext.as_slice()),
+        Err(e) => {
+            error!("Failed to verify the decrypted payload: {e}");
+            exit(Exit::Integrity);
+        }
+    };
+    if let Some(file_size) = file_size
+        && header.as_ref().and_then(|header| header.size) != u64::try_from(file_size).ok()
+    {
+        error!("Ciphertext is longer than the {file_size} byte pad segment.");
+        exit(Exit::InvalidInput);
+    }
+    if header.is_some() {
+        info!("Plaintext SHA-256 verified.");
+    }
+    if let Some((seq, delivery)) = header.as_ref().and_then(|header| track_sequence(&mut state, &dec_info.pad_id, header)) {
+        match delivery {
+            sequence::Delivery::InOrder => {}
+            sequence::Delivery::Gap { missing } => alert(format!(
+                "message {seq} on pad '{}' arrived before message(s) {}..={}, which may be delayed or lost.",
+                dec_info.pad_id,
+                missing.start,
+                missing.end - 1
+            )),
+            sequence::Delivery::OutOfOrder => alert(format!("message {seq} on pad '{}' arrived out of order.", dec_info.pad_id)),
+            sequence::Delivery::Replay | sequence::Delivery::Stale if args.allow_replay => {
+                alert(format!("message {seq} on pad '{}' was received before; decrypting it again as requested.", dec_info.pad_id));
+            }
+            sequence::Delivery::Replay | sequence::Delivery::Stale => {
+                let reason = if delivery == sequence::Delivery::Replay { "was already received" } else { "is too old to tell whether it was received" };
+                error!("REPLAY DETECTED: message {seq} on pad '{}' {reason}. Someone may be resending an old message.", dec_info.pad_id);
+                error!("Refusing to decrypt it. Pass --allow-replay to read a message you received before.");
+                exit(Exit::Integrity);
             }
         }
-        println!("Successfully decrypted file '{}' to '{}'", input.display(), output.display());
+    }
+    // Our own messages carry our vault ID; everything else is checked for two-time-pad use.
+    let sender = header.as_ref().and_then(|header| header.sender.as_deref());
+    let counterparty = if sender == Some(state.vault_id.as_str()) { None } else { contact.or_else(|| sender.map(str::to_string)) };
+    let collisions = if sender == Some(state.vault_id.as_str()) {
+        Vec::new()
     } else {
-        error!("Pad with ID '{}' not found in vault.", dec_info.pad_id);
+        state.pads[&dec_info.pad_id].incoming_collisions(segment.start, segment.end, sender.is_some())
+    };
+    let collided = !collisions.is_empty();
+    if collided {
+        let collisions: Vec<_> = collisions.into_iter().map(|range| (dec_info.pad_id.clone(), range)).collect();
+        print_reuse_alert("This message was encrypted with pad bytes that already served another message.", &collisions);
+        if let Some(pad) = state.pads.get_mut(&dec_info.pad_id) {
+            pad.quarantined = Some("an incoming message reused pad bytes".to_string());
+        }
+    }
+    let output_path = resolve_decrypt_output(args, header.as_ref(), dec_info.archive);
+
+    // The plaintext goes to a staging file or directory first and only takes its name once
+    // the pad bytes are recorded, so a failed save leaves no message the vault does not
+    // know it read.
+    let mut staged = None;
+    let mut extracted = None;
+    if dec_info.archive {
+        if args.list {
+            let entries = archive::parse(payload).unwrap_or_else(|e| {
+                error!("Failed to read archive: {e}");
+                exit(Exit::InvalidInput);
+            });
+            for (entry, _) in &entries {
+                out!("{}", archive::describe(entry));
+            }
+            report("entries", entries.iter().map(|(entry, _)| archive::describe(entry)).collect::<Vec<_>>());
+        } else {
+            let Some(destination) = output_path.as_deref() else {
+                error!("An archive must be extracted into a directory, not standard output.");
+                exit(Exit::InvalidInput);
+            };
+            let partial = destination.join(format!(".otp-extract-{}", std::process::id()));
+            let result = archive::extract(payload, &partial).and_then(|entries| {
+                first_existing(&partial, destination)?.map_or(Ok(entries), |path| {
+                    Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("'{}' already exists", path.display())))
+                })
+            });
+            let entries = result.unwrap_or_else(|e| {
+                discard_staged(&partial);
+                error!("Failed to extract archive: {e}");
+                exit(Exit::Storage);
+            });
+            extracted = Some(entries.len());
+            staged = Some(partial);
+        }
+    } else if args.list {
+        error!("The message is not an archive; there is nothing to list.");
+        exit(Exit::InvalidInput);
+    } else if let Some(path) = &output_path {
+        let partial = staging_file(path);
+        write_output(Some(&partial), payload);
+        if let Some(header) = &header
+            && let Err(e) = header.apply_to(&partial)
+        {
+            error!("Failed to restore file attributes on '{}': {e}", path.display());
+        }
+        staged = Some(partial);
+    }
+
+    // Only bytes not recorded yet are added, so a message read twice or colliding with
+    // another one is not counted twice. Recording also saves the updated receive window.
+    let mut fresh = state.pads[&dec_info.pad_id].unused_parts(segment.start, segment.end);
+    if fresh.is_empty() {
+        fresh.push(0..0);
+    }
+    let mut became_full = false;
+    for part in fresh {
+        let segment = state_manager::UsedSegment::consumed(part, state_manager::SegmentOperation::Decrypt)
+            .digest(digest.clone())
+            .seq(header.as_ref().and_then(|h| h.seq))
+            .counterparty(counterparty.clone());
+        became_full |= record_used_segment(&mut state, vault_path, &dec_info.pad_id, segment).unwrap_or_else(|e| {
+            if let Some(partial) = &staged {
+                discard_staged(partial);
+            }
+            error!("Failed to save state: {e}");
+            exit(Exit::Storage);
+        });
+    }
+    match (&staged, output_path.as_deref()) {
+        (Some(partial), Some(path)) => {
+            let moved = if dec_info.archive { move_into(partial, path).and_then(|()| fs::remove_dir_all(partial)) } else { fs::rename(partial, path) };
+            if let Err(e) = moved {
+                error!("Failed to move the decrypted output from '{}' to '{}': {e}", partial.display(), path.display());
+                exit(Exit::Storage);
+            }
+        }
+        (None, None) if !args.list => write_output(None, payload),
+        _ => {}
+    }
+    if let Some(count) = extracted {
+        say(format!("Extracted {count} entries."));
+        report("extracted_entries", count);
+    }
+    let record = audit_record(audit::Operation::Decrypt).pad(&dec_info.pad_id).range(segment);
+    let record = if collided { record.detail("pad reuse detected") } else { record };
+    audit_log(vault_path, with_file(record, output_path.as_deref()));
+    if became_full {
+        info!("Pad '{}' is now fully consumed on receiver side. Moving to 'used' directory.", dec_info.pad_id);
+    }
+    report("pad_id", &dec_info.pad_id);
+    report("start_byte", dec_info.start_byte);
+    report("length", dec_info.length);
+    report("seq", header.as_ref().and_then(|h| h.seq));
+    report("from", &counterparty);
+    report("file", &output_path);
+    if collided {
+        error!("Decrypted the message, but the pad bytes it used were compromised.");
+        exit(Exit::Integrity);
+    }
+    if args.list {
+        return;
     }
+    let destination = output_path.map_or_else(|| "standard output".to_string(), |p| format!("'{}'", p.display()));
+    say(format!("Successfully decrypted {} to {destination}", input.describe()));
 }
@@ -0,0 +1,79 @@
+// File:    allocation.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Benchmarks pad allocation on pads with long usage histories.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Benchmarks of pad allocation.
+//!
+//! Each pad has consumed `n` messages of 100 bytes. On a pad used front to back the
+//! messages form one contiguous run; on a fragmented pad each is followed by a 28-byte
+//! gap. `sorted_history` measures what allocation used to cost: sorting the history.
+
+use criterion::{BatchSize, BenchmarkId, Criterion};
+use otp_core::state_manager::{Pad, UsedSegment};
+use std::hint::black_box;
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+fn pad(messages: usize, stride: usize) -> Pad {
+    let mut pad = Pad { id: "bench".to_string(), size: messages * stride + 4096, ..Pad::default() };
+    for message in 0..messages {
+        pad.record_use(UsedSegment::from(message * stride..message * stride + 100));
+    }
+    pad
+}
+
+fn allocation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_available_segment");
+    for messages in SIZES {
+        let front_to_back = pad(messages, 100);
+        group.bench_with_input(BenchmarkId::new("front_to_back", messages), &front_to_back, |b, pad| {
+            b.iter(|| pad.find_available_segment(black_box(1024)));
+        });
+        let fragmented = pad(messages, 128);
+        group.bench_with_input(BenchmarkId::new("fragmented", messages), &fragmented, |b, pad| {
+            b.iter(|| pad.find_available_segment(black_box(16)));
+        });
+        group.bench_with_input(BenchmarkId::new("sorted_history", messages), &fragmented, |b, pad| {
+            b.iter(|| {
+                let mut history = pad.used_segments.clone();
+                history.sort_by_key(|s| s.start);
+                black_box(history)
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bookkeeping(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bookkeeping");
+    for messages in SIZES {
+        let fragmented = pad(messages, 128);
+        group.bench_with_input(BenchmarkId::new("total_used_bytes", messages), &fragmented, |b, pad| {
+            b.iter(|| black_box(pad).total_used_bytes());
+        });
+        group.bench_with_input(BenchmarkId::new("record_use", messages), &fragmented, |b, pad| {
+            b.iter_batched(
+                || pad.clone(),
+                |mut pad| {
+                    pad.record_use(UsedSegment::from(black_box(100..128)));
+                    pad
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn main() {
+    let mut criterion = Criterion::default().configure_from_args();
+    allocation(&mut criterion);
+    bookkeeping(&mut criterion);
+    criterion.final_summary();
+}
@@ -0,0 +1,365 @@
+// File:    archive.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: A minimal archive format for encrypting whole directory trees with one pad allocation.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Directory archives.
+//!
+//! An archive is the magic bytes followed by one record per directory or file and an end
+//! marker. All integers are big-endian:
+//!
+//! ```text
+//! "OTPARCH1"
+//! kind: u8 (0 = directory, 1 = file, 0xff = end)
+//! path_len: u16, path: UTF-8 relative path with '/' separators
+//! mode: u32, mtime: u64 (seconds since the Unix epoch), size: u64
+//! data: `size` bytes (files only)
+//! ```
+//!
+//! The archive length is known before any file is read, so the whole tree can be
+//! encrypted with a single pad reservation while it is streamed from disk.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::timestamp;
+
+/// The bytes every archive starts with.
+pub const MAGIC: &[u8; 8] = b"OTPARCH1";
+const KIND_DIRECTORY: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_END: u8 = 0xff;
+const FIXED_HEADER_LEN: usize = 1 + 2 + 4 + 8 + 8;
+
+/// One directory or file stored in an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path relative to the archive root, with `/` separators.
+    pub path: String,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+    /// The Unix permission bits, without setuid, setgid and sticky bits.
+    pub mode: u32,
+    /// The modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    /// The file size in bytes; zero for directories.
+    pub size: u64,
+}
+
+impl Entry {
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(FIXED_HEADER_LEN + self.path.len());
+        header.push(if self.is_dir { KIND_DIRECTORY } else { KIND_FILE });
+        header.extend(u16::try_from(self.path.len()).unwrap_or(u16::MAX).to_be_bytes());
+        header.extend(self.path.as_bytes());
+        header.extend(self.mode.to_be_bytes());
+        header.extend(self.mtime.to_be_bytes());
+        header.extend(self.size.to_be_bytes());
+        header
+    }
+}
+
+/// A directory tree scanned for archiving.
+#[derive(Debug)]
+pub struct Source {
+    root: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Source {
+    /// Scans `root` recursively. Entries are sorted by path; symbolic links and other
+    /// special files are skipped and returned separately so the caller can report them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tree cannot be read or contains a non-UTF-8 or overlong path.
+    pub fn scan(root: &Path) -> io::Result<(Self, Vec<PathBuf>)> {
+        let mut entries = Vec::new();
+        let mut skipped = Vec::new();
+        scan_dir(root, "", &mut entries, &mut skipped)?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok((Self { root: root.to_path_buf(), entries }, skipped))
+    }
+
+    /// The scanned entries in archive order.
+    #[must_use]
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The exact number of bytes [`Source::reader`] will produce.
+    #[must_use]
+    pub fn archive_len(&self) -> u64 {
+        let headers: usize = self.entries.iter().map(|e| FIXED_HEADER_LEN + e.path.len()).sum();
+        let data: u64 = self.entries.iter().map(|e| e.size).sum();
+        (MAGIC.len() + headers + 1) as u64 + data
+    }
+
+    /// Streams the archive, opening one file at a time.
+    #[must_use]
+    pub fn reader(&self) -> ArchiveReader<'_> {
+        ArchiveReader {
+            source: self,
+            pending: self.entries.iter().collect(),
+            current: Box::new(io::Cursor::new(MAGIC.to_vec())),
+            finished: false,
+        }
+    }
+}
+
+fn scan_dir(dir: &Path, prefix: &str, entries: &mut Vec<Entry>, skipped: &mut Vec<PathBuf>) -> io::Result<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let file_type = dir_entry.file_type()?;
+        let name = dir_entry.file_name().into_string().map_err(|name| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("file name '{}' is not valid UTF-8", name.display()))
+        })?;
+        let path = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        if path.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("path '{path}' is too long")));
+        }
+        if !file_type.is_dir() && !file_type.is_file() {
+            skipped.push(dir_entry.path());
+            continue;
+        }
+        let metadata = dir_entry.metadata()?;
+        entries.push(Entry {
+            path: path.clone(),
+            is_dir: file_type.is_dir(),
+            mode: permission_bits(&metadata),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+            size: if file_type.is_dir() { 0 } else { metadata.len() },
+        });
+        if file_type.is_dir() {
+            scan_dir(&dir_entry.path(), &path, entries, skipped)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn permission_bits(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+pub(crate) fn permission_bits(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// A [`Read`] implementation producing the archive of a [`Source`].
+pub struct ArchiveReader<'a> {
+    source: &'a Source,
+    pending: VecDeque<&'a Entry>,
+    current: Box<dyn Read + 'a>,
+    finished: bool,
+}
+
+impl ArchiveReader<'_> {
+    /// Moves on to the next record. Returns `false` once the end marker has been produced.
+    fn advance(&mut self) -> io::Result<bool> {
+        if let Some(entry) = self.pending.pop_front() {
+            let header = io::Cursor::new(entry.header());
+            self.current = if entry.is_dir {
+                Box::new(header)
+            } else {
+                let file = fs::File::open(self.source.root.join(&entry.path))?;
+                Box::new(header.chain(ExactFile { inner: file.take(entry.size), path: &entry.path }))
+            };
+            Ok(true)
+        } else if self.finished {
+            Ok(false)
+        } else {
+            self.finished = true;
+            self.current = Box::new(io::Cursor::new(vec![KIND_END]));
+            Ok(true)
+        }
+    }
+}
+
+impl Read for ArchiveReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 || buf.is_empty() || !self.advance()? {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// A file body that must deliver exactly the size recorded when the tree was scanned.
+struct ExactFile<'a> {
+    inner: io::Take<fs::File>,
+    path: &'a str,
+}
+
+impl Read for ExactFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() && self.inner.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("'{}' shrank while it was being archived", self.path),
+            ));
+        }
+        Ok(read)
+    }
+}
+
+/// Parses an archive held in memory.
+///
+/// # Errors
+///
+/// Returns an error if the data is not an archive, is truncated, or contains a path
+/// that is absolute or escapes the archive root.
+pub fn parse(data: &[u8]) -> io::Result<Vec<(Entry, &[u8])>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut rest = data.strip_prefix(MAGIC.as_slice()).ok_or_else(|| invalid("not an archive".to_string()))?;
+    let mut take = |len: usize| -> io::Result<&[u8]> {
+        if rest.len() < len {
+            return Err(invalid("archive is truncated".to_string()));
+        }
+        let (head, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(head)
+    };
+    let mut entries = Vec::new();
+    loop {
+        let kind = take(1)?[0];
+        if kind == KIND_END {
+            return Ok(entries);
+        }
+        if kind != KIND_DIRECTORY && kind != KIND_FILE {
+            return Err(invalid(format!("unknown entry kind {kind}")));
+        }
+        let path_len = u16::from_be_bytes(take(2)?.try_into().unwrap_or_default());
+        let path = String::from_utf8(take(usize::from(path_len))?.to_vec())
+            .map_err(|_| invalid("entry path is not valid UTF-8".to_string()))?;
+        safe_relative_path(&path)?;
+        let mode = u32::from_be_bytes(take(4)?.try_into().unwrap_or_default());
+        let mtime = u64::from_be_bytes(take(8)?.try_into().unwrap_or_default());
+        let size = u64::from_be_bytes(take(8)?.try_into().unwrap_or_default());
+        let data_len = usize::try_from(size).map_err(|_| invalid(format!("'{path}' is too large")))?;
+        let content = if kind == KIND_FILE { take(data_len)? } else { &[] };
+        entries.push((Entry { path, is_dir: kind == KIND_DIRECTORY, mode, mtime, size }, content));
+    }
+}
+
+/// Converts an archive path into a relative filesystem path, refusing anything that
+/// could resolve outside the extraction directory.
+///
+/// # Errors
+///
+/// Returns an error for empty or absolute paths and for `..` components.
+pub fn safe_relative_path(path: &str) -> io::Result<PathBuf> {
+    let refuse = || {
+        io::Error::new(io::ErrorKind::InvalidData, format!("refusing unsafe archive path '{path}'"))
+    };
+    if path.is_empty() || path.contains('\\') {
+        return Err(refuse());
+    }
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(refuse()),
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(refuse());
+    }
+    Ok(relative)
+}
+
+/// Restores the archived tree below `destination`, creating it if needed.
+///
+/// Every entry is validated before anything is written, and existing files are never
+/// overwritten.
+///
+/// # Errors
+///
+/// Returns an error if the archive is invalid or a file cannot be written.
+pub fn extract(data: &[u8], destination: &Path) -> io::Result<Vec<Entry>> {
+    let entries = parse(data)?;
+    for (entry, _) in &entries {
+        let target = destination.join(safe_relative_path(&entry.path)?);
+        if !entry.is_dir && target.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' already exists", target.display()),
+            ));
+        }
+    }
+
+    fs::create_dir_all(destination)?;
+    for (entry, content) in &entries {
+        let target = destination.join(safe_relative_path(&entry.path)?);
+        if entry.is_dir {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(&target)?;
+        io::Write::write_all(&mut &file, content)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime))?;
+        set_permission_bits(&target, entry.mode)?;
+    }
+    // Directory times and permissions are applied last, after their contents exist.
+    for (entry, _) in entries.iter().rev().filter(|(e, _)| e.is_dir) {
+        let target = destination.join(safe_relative_path(&entry.path)?);
+        if let Ok(dir) = fs::File::open(&target) {
+            let _ = dir.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime));
+        }
+        set_permission_bits(&target, entry.mode)?;
+    }
+    Ok(entries.into_iter().map(|(entry, _)| entry).collect())
+}
+
+#[cfg(unix)]
+pub(crate) fn set_permission_bits(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // Setuid, setgid and sticky bits are never restored from untrusted input
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_permission_bits(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// Formats an entry like a line of `ls -l` for archive listings.
+#[must_use]
+pub fn describe(entry: &Entry) -> String {
+    let kind = if entry.is_dir { 'd' } else { '-' };
+    let suffix = if entry.is_dir { "/" } else { "" };
+    format!(
+        "{kind}{:04o} {:>12} {} {}{suffix}",
+        entry.mode,
+        entry.size,
+        timestamp::format_utc(entry.mtime),
+        entry.path
+    )
+}
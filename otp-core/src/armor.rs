@@ -0,0 +1,102 @@
+// File:    armor.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: ASCII armor for ciphertexts so they can travel through mail and chat as plain text.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! ASCII-armored messages.
+//!
+//! An armored message carries its decryption metadata as `Key: value` headers followed
+//! by a blank line and the base64 ciphertext, wrapped at 64 columns:
+//!
+//! ```text
+//! -----BEGIN OTP MESSAGE-----
+//! Pad-Id: 2f1c...
+//! Start-Byte: 4096
+//!
+//! q83vEjRWeJq8...
+//! -----END OTP MESSAGE-----
+//! ```
+
+use data_encoding::BASE64;
+
+/// The first line of an armored message.
+pub const BEGIN_LINE: &str = "-----BEGIN OTP MESSAGE-----";
+/// The last line of an armored message.
+pub const END_LINE: &str = "-----END OTP MESSAGE-----";
+const LINE_WIDTH: usize = 64;
+
+/// Wraps a ciphertext and its headers into an armored message.
+#[must_use]
+pub fn armor(headers: &[(&str, String)], body: &[u8]) -> String {
+    let mut lines = vec![BEGIN_LINE.to_string()];
+    lines.extend(headers.iter().map(|(key, value)| format!("{key}: {value}")));
+    lines.push(String::new());
+    let encoded = BASE64.encode(body);
+    lines.extend(
+        encoded
+            .as_bytes()
+            .chunks(LINE_WIDTH)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned()),
+    );
+    lines.push(END_LINE.to_string());
+    lines.join("\n") + "\n"
+}
+
+/// Checks whether the data starts like an armored message, ignoring leading whitespace.
+#[must_use]
+pub fn is_armored(data: &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(BEGIN_LINE.as_bytes())
+}
+
+/// The `Key: value` header lines of an armored message, in order.
+pub type Headers = Vec<(String, String)>;
+
+/// Extracts the headers and ciphertext from an armored message.
+///
+/// Text before the begin line and after the end line is ignored, so a message pasted
+/// together with mail quoting or signatures still parses.
+///
+/// # Errors
+///
+/// Returns an error if the armor lines are missing or the body is not valid base64.
+pub fn dearmor(text: &str) -> std::io::Result<(Headers, Vec<u8>)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != BEGIN_LINE);
+    if lines.next().is_none() {
+        return Err(invalid("missing '-----BEGIN OTP MESSAGE-----' line"));
+    }
+    let mut headers = Vec::new();
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed armor header"))?;
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    let mut encoded = String::new();
+    let mut terminated = false;
+    for line in lines {
+        if line == END_LINE {
+            terminated = true;
+            break;
+        }
+        encoded.push_str(line);
+    }
+    if !terminated {
+        return Err(invalid("missing '-----END OTP MESSAGE-----' line"));
+    }
+    let body = BASE64
+        .decode(encoded.as_bytes())
+        .map_err(|e| invalid(&format!("invalid base64 in armored body: {e}")))?;
+    Ok((headers, body))
+}
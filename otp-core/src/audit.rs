@@ -0,0 +1,272 @@
+// File:    audit.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: An append-only, hash-chained log of every operation on a vault.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! The vault audit log.
+//!
+//! Every operation that creates, consumes, exports or removes pad material appends one
+//! JSON line to `audit.jsonl` in the vault. Each line carries the SHA-256 of the line
+//! before it, so deleting, reordering or editing an entry breaks the chain from that
+//! point on and [`verify`] reports it:
+//!
+//! ```text
+//! hash = SHA-256(prev | "\n" | JSON of the record)
+//! ```
+//!
+//! The log says which bytes were used when and for what, never the message content.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// The log's file name within the vault.
+pub const LOG_FILE: &str = "audit.jsonl";
+/// The `prev` value of the first entry.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The front end an operation was started from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interface {
+    /// The `otp-cli` command line tool.
+    Cli,
+    /// The `otp-web` server.
+    Web,
+}
+
+/// What was done to the vault.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// New pads were generated.
+    Generate,
+    /// A pad was added from outside, e.g. typed in or uploaded.
+    Import,
+    /// Pad material or usage left the vault, e.g. printed or downloaded.
+    Export,
+    /// Pad bytes were consumed to encrypt a message.
+    Encrypt,
+    /// Pad bytes were used to decrypt a message.
+    Decrypt,
+    /// Pad bytes were marked as consumed by hand without carrying a message.
+    Burn,
+    /// A pad was retired or given an expiry date.
+    Retire,
+    /// A pad was revoked because a copy may have been exposed.
+    Revoke,
+    /// A pad was deleted.
+    Delete,
+    /// The whole vault was cleared.
+    Clear,
+    /// The vault was checked and its inconsistencies repaired or quarantined.
+    Repair,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = serde_json::to_value(self).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        f.write_str(&name)
+    }
+}
+
+/// The content of one log entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// When the operation happened, in seconds since the Unix epoch.
+    pub at: u64,
+    /// The front end that performed it.
+    pub interface: Interface,
+    /// What was done.
+    pub op: Operation,
+    /// The pad involved, if the operation concerns a single pad.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad_id: Option<String>,
+    /// The pad bytes involved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range<usize>>,
+    /// The file that was written or read, by name only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Anything else worth knowing, such as the export format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Record {
+    /// Starts a record of `op` performed now through `interface`.
+    #[must_use]
+    pub fn new(interface: Interface, op: Operation) -> Self {
+        Self { at: crate::timestamp::now(), interface, op, pad_id: None, range: None, file: None, detail: None }
+    }
+
+    /// Sets the pad the operation concerns.
+    #[must_use]
+    pub fn pad(mut self, pad_id: &str) -> Self {
+        self.pad_id = Some(pad_id.to_string());
+        self
+    }
+
+    /// Sets the pad bytes involved.
+    #[must_use]
+    pub const fn range(mut self, range: Range<usize>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Sets the file involved, keeping only its name.
+    #[must_use]
+    pub fn file(mut self, path: &Path) -> Self {
+        self.file = Some(path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned()));
+        self
+    }
+
+    /// Sets a free-form detail.
+    #[must_use]
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    fn chain_hash(&self, prev: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(prev.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(serde_json::to_vec(self).unwrap_or_default());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+impl std::fmt::Display for Interface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Cli => "cli",
+            Self::Web => "web",
+        })
+    }
+}
+
+/// Renders the record as one human-readable line.
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:<3} {:<8}", crate::timestamp::format_utc(self.at), self.interface, self.op.to_string())?;
+        if let Some(pad_id) = &self.pad_id {
+            write!(f, " pad {pad_id}")?;
+        }
+        if let Some(range) = &self.range {
+            write!(f, " bytes {}..{}", range.start, range.end)?;
+        }
+        if let Some(file) = &self.file {
+            write!(f, " file '{file}'")?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, " ({detail})")?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// What happened.
+    #[serde(flatten)]
+    pub record: Record,
+    /// The hash of the previous entry, or [`GENESIS`].
+    pub prev: String,
+    /// The hash of this entry.
+    pub hash: String,
+}
+
+/// Selects log entries by pad, operation and time.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only entries about this pad.
+    pub pad_id: Option<String>,
+    /// Only entries of this operation.
+    pub op: Option<Operation>,
+    /// Only entries at or after this time, in seconds since the Unix epoch.
+    pub since: Option<u64>,
+    /// Only entries before this time, in seconds since the Unix epoch.
+    pub until: Option<u64>,
+}
+
+impl Filter {
+    /// Checks whether `entry` passes the filter.
+    #[must_use]
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let record = &entry.record;
+        self.pad_id.as_ref().is_none_or(|id| record.pad_id.as_ref() == Some(id))
+            && self.op.is_none_or(|op| record.op == op)
+            && self.since.is_none_or(|since| record.at >= since)
+            && self.until.is_none_or(|until| record.at < until)
+    }
+}
+
+/// Appends `record` to the vault's log, chained to the last entry.
+///
+/// # Errors
+///
+/// Returns an error if the log cannot be read or written, or its last line is not a
+/// valid entry.
+pub fn append(vault_path: &Path, record: Record) -> io::Result<Entry> {
+    let path = vault_path.join(LOG_FILE);
+    let prev = match fs::read_to_string(&path) {
+        Ok(text) => match text.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => serde_json::from_str::<Entry>(line).map_err(io::Error::other)?.hash,
+            None => GENESIS.to_string(),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => GENESIS.to_string(),
+        Err(e) => return Err(e),
+    };
+    let entry = Entry { hash: record.chain_hash(&prev), record, prev };
+    let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    line.push('\n');
+    fs::OpenOptions::new().create(true).append(true).open(&path)?.write_all(line.as_bytes())?;
+    Ok(entry)
+}
+
+/// Reads all entries of the vault's log. A vault without a log has no entries.
+///
+/// # Errors
+///
+/// Returns an error if the log cannot be read or a line is not a valid entry.
+pub fn read(vault_path: &Path) -> io::Result<Vec<Entry>> {
+    let text = match fs::read_to_string(vault_path.join(LOG_FILE)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", index + 1)))
+        })
+        .collect()
+}
+
+/// Checks the hash chain.
+///
+/// # Errors
+///
+/// Returns the index of the first entry that does not follow from the one before it.
+pub fn verify(entries: &[Entry]) -> Result<(), usize> {
+    let mut prev = GENESIS;
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.prev != prev || entry.record.chain_hash(prev) != entry.hash {
+            return Err(index);
+        }
+        prev = &entry.hash;
+    }
+    Ok(())
+}
@@ -0,0 +1,301 @@
+// File:    backup.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Consistent vault snapshots with a checksummed manifest, and restoring them.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Vault backups.
+//!
+//! A snapshot is a plain directory holding copies of `vault_state.json`, `audit.jsonl`
+//! and everything below `pads/`, plus a `manifest.json` listing the size and SHA-256 of
+//! every copied file:
+//!
+//! ```text
+//! snapshot/
+//!   manifest.json
+//!   vault_state.json
+//!   audit.jsonl
+//!   pads/available/<id>.pad
+//!   pads/used/<id>.pad
+//! ```
+//!
+//! The state is read before and after the pad files are copied; if another process
+//! changed it in between, the copy is thrown away and taken again. Restoring a snapshot
+//! whose usage lags behind the vault would hand out pad bytes a second time, so
+//! [`stale_pads`] finds the pads for which that would happen, and [`added_pads`] those a
+//! restore would delete outright.
+
+use crate::archive;
+use crate::audit;
+use crate::state_manager::{self, VaultState};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The manifest's file name within a snapshot.
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// The snapshot format version written by [`create`].
+pub const FORMAT_VERSION: u32 = 1;
+const STATE_FILE: &str = "vault_state.json";
+const ATTEMPTS: usize = 5;
+
+/// Describes a snapshot and lets its files be verified.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// The snapshot format version.
+    pub version: u32,
+    /// When the snapshot was taken, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// The ID of the vault the snapshot was taken from, if it has one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub vault_id: String,
+    /// Every file in the snapshot except the manifest itself.
+    pub files: Vec<ManifestFile>,
+}
+
+/// One file of a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    /// The path relative to the snapshot root, with `/` separators.
+    pub path: String,
+    /// The file size in bytes.
+    pub size: u64,
+    /// The hex SHA-256 of the file.
+    pub sha256: String,
+}
+
+/// Takes a snapshot of the vault at `vault_path` into the new or empty directory `out`.
+///
+/// # Errors
+///
+/// Returns an error if `out` is not empty, a file cannot be read or written, or the
+/// vault kept changing while it was copied.
+pub fn create(vault_path: &Path, out: &Path) -> io::Result<Manifest> {
+    if out.exists() && fs::read_dir(out)?.next().is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' is not empty", out.display())));
+    }
+    for _ in 0..ATTEMPTS {
+        let result = try_create(vault_path, out);
+        if let Ok(Some(manifest)) = result {
+            return Ok(manifest);
+        }
+        if out.exists() {
+            fs::remove_dir_all(out)?;
+        }
+        match result {
+            // A pad file that moved between directories while being copied also means
+            // the vault changed under us.
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Err(io::Error::other("the vault kept changing during the backup; try again when it is idle"))
+}
+
+fn try_create(vault_path: &Path, out: &Path) -> io::Result<Option<Manifest>> {
+    let state_path = vault_path.join(STATE_FILE);
+    let before = fs::read(&state_path)?;
+    let state = state_manager::parse_state(&before)?;
+
+    fs::create_dir_all(out)?;
+    let mut files = vec![write_file(out, STATE_FILE, &mut before.as_slice())?];
+    if vault_path.join(audit::LOG_FILE).is_file() {
+        files.push(write_file(out, audit::LOG_FILE, &mut fs::File::open(vault_path.join(audit::LOG_FILE))?)?);
+    }
+    let mut pad_files = Vec::new();
+    collect_files(&vault_path.join("pads"), &mut pad_files)?;
+    pad_files.sort();
+    for path in pad_files {
+        let relative = path.strip_prefix(vault_path).map_err(io::Error::other)?;
+        let name = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        files.push(write_file(out, &name, &mut fs::File::open(&path)?)?);
+    }
+
+    if fs::read(&state_path)? != before {
+        return Ok(None);
+    }
+    let manifest = Manifest { version: FORMAT_VERSION, created_at: crate::timestamp::now(), vault_id: state.vault_id, files };
+    fs::write(out.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?)?;
+    Ok(Some(manifest))
+}
+
+/// Copies `reader` to `name` below `out` and returns its manifest entry.
+fn write_file(out: &Path, name: &str, reader: &mut dyn Read) -> io::Result<ManifestFile> {
+    let destination = out.join(archive::safe_relative_path(name)?);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::File::create(destination)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+    file.sync_all()?;
+    Ok(ManifestFile { path: name.to_string(), size, sha256: format!("{:x}", hasher.finalize()) })
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the manifest of the snapshot at `snapshot` and checks every file against it.
+///
+/// # Errors
+///
+/// Returns an error if the manifest is missing or invalid, or a file is missing, has
+/// the wrong size or the wrong checksum.
+pub fn verify(snapshot: &Path) -> io::Result<Manifest> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let manifest: Manifest = serde_json::from_slice(&fs::read(snapshot.join(MANIFEST_FILE))?).map_err(|e| invalid(format!("invalid manifest: {e}")))?;
+    if manifest.version != FORMAT_VERSION {
+        return Err(invalid(format!("unsupported snapshot version {}", manifest.version)));
+    }
+    if !manifest.files.iter().any(|file| file.path == STATE_FILE) {
+        return Err(invalid(format!("the snapshot has no {STATE_FILE}")));
+    }
+    for file in &manifest.files {
+        let data = fs::read(snapshot.join(archive::safe_relative_path(&file.path)?))
+            .map_err(|e| invalid(format!("cannot read '{}': {e}", file.path)))?;
+        if data.len() as u64 != file.size || format!("{:x}", Sha256::digest(&data)) != file.sha256 {
+            return Err(invalid(format!("'{}' does not match its checksum", file.path)));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Loads the vault state stored in the snapshot at `snapshot`.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read or parsed.
+pub fn snapshot_state(snapshot: &Path) -> io::Result<VaultState> {
+    state_manager::parse_state(&fs::read(snapshot.join(STATE_FILE))?)
+}
+
+/// Finds the pads whose usage in `current` goes beyond `snapshot`: bytes used or
+/// messages sent since the snapshot was taken, which restoring it would forget.
+///
+/// A pad the snapshot does not have counts if anything of it was used, as the restore
+/// drops its record along with the pad.
+#[must_use]
+pub fn stale_pads(snapshot: &VaultState, current: &VaultState) -> Vec<String> {
+    let mut stale: Vec<String> = current
+        .pads
+        .values()
+        .filter(|pad| {
+            snapshot.pads.get(&pad.id).map_or_else(|| pad.sent_sequence > 0 || pad.total_used_bytes() > 0, |old| {
+                old.sent_sequence < pad.sent_sequence
+                    || pad.used.iter().chain(pad.next_offset.map(|watermark| 0..watermark)).any(|range| !old.unused_parts(range.start, range.end).is_empty())
+            })
+        })
+        .map(|pad| pad.id.clone())
+        .collect();
+    stale.sort();
+    stale
+}
+
+/// Finds the pads of `current` that `snapshot` does not have, such as pads generated or
+/// imported since it was taken. Restoring the snapshot deletes them.
+#[must_use]
+pub fn added_pads(snapshot: &VaultState, current: &VaultState) -> Vec<String> {
+    let mut added: Vec<String> = current.pads.keys().filter(|id| !snapshot.pads.contains_key(*id)).cloned().collect();
+    added.sort();
+    added
+}
+
+/// Replaces the state and pad files of the vault at `vault_path` with the verified
+/// snapshot at `snapshot`.
+///
+/// The snapshot's pads are copied next to the vault's `pads/` directory first and only
+/// swapped in once all of them are there, so a failed copy leaves the vault as it was.
+/// The vault's audit log is kept if it continues the snapshot's, so entries written
+/// since the backup are not lost; otherwise the snapshot's log is restored.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be copied or the pad directories cannot be swapped.
+pub fn restore(snapshot: &Path, manifest: &Manifest, vault_path: &Path) -> io::Result<()> {
+    let pads_dir = vault_path.join("pads");
+    let staging = vault_path.join("pads.restore");
+    let previous = vault_path.join("pads.old");
+    // An earlier restore may have stopped between the two renames of the swap
+    if previous.exists() && !pads_dir.exists() {
+        fs::rename(&previous, &pads_dir)?;
+    }
+    for leftover in [&staging, &previous] {
+        if leftover.exists() {
+            fs::remove_dir_all(leftover)?;
+        }
+    }
+
+    let temp_path = vault_path.join("vault_state.json.tmp");
+    if let Err(e) = stage(snapshot, manifest, &staging).and_then(|()| fs::copy(snapshot.join(STATE_FILE), &temp_path).map(drop)) {
+        // Best effort: the vault itself has not been touched
+        let _ = fs::remove_dir_all(&staging);
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    if pads_dir.exists() {
+        fs::rename(&pads_dir, &previous)?;
+    }
+    if let Err(e) = fs::rename(&staging, &pads_dir) {
+        if previous.exists() {
+            fs::rename(&previous, &pads_dir)?;
+        }
+        return Err(e);
+    }
+    fs::rename(temp_path, vault_path.join(STATE_FILE))?;
+
+    if let Ok(saved_log) = fs::read(snapshot.join(audit::LOG_FILE)) {
+        let current_log = fs::read(vault_path.join(audit::LOG_FILE)).unwrap_or_default();
+        if !current_log.starts_with(&saved_log) {
+            fs::write(vault_path.join(audit::LOG_FILE), saved_log)?;
+        }
+    }
+    if previous.exists() {
+        fs::remove_dir_all(previous)?;
+    }
+    Ok(())
+}
+
+/// Copies the snapshot's pad files into the new directory `staging`.
+fn stage(snapshot: &Path, manifest: &Manifest, staging: &Path) -> io::Result<()> {
+    fs::create_dir_all(staging.join("available"))?;
+    fs::create_dir_all(staging.join("used"))?;
+    for file in manifest.files.iter().filter_map(|file| file.path.strip_prefix("pads/")) {
+        let relative = archive::safe_relative_path(file)?;
+        let destination = staging.join(&relative);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(snapshot.join("pads").join(&relative), destination)?;
+    }
+    Ok(())
+}
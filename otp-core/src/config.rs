@@ -0,0 +1,262 @@
+// File:    config.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: The shared configuration file and the rules for combining it with environment and flags.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Configuration shared by `otp-cli` and `otp-web`.
+//!
+//! Settings come from four places. The first one that sets a value wins:
+//!
+//! 1. command-line flags,
+//! 2. environment variables ([`ENV_VAULT`], [`ENV_PAD_SIZE`], [`ENV_ENCODING`]),
+//! 3. the selected profile in the config file,
+//! 4. the top level of the config file,
+//!
+//! and finally the built-in defaults: the vault `./.otp_vault` and 1 MB pads. The profile
+//! is chosen with `--profile` or [`ENV_PROFILE`]. The config file is [`ENV_CONFIG`] if
+//! set, else `$XDG_CONFIG_HOME/otp/config.toml`, else `~/.config/otp/config.toml`. It
+//! uses a small subset of TOML:
+//!
+//! ```toml
+//! vault = "~/otp/personal"
+//! pad_size = 1          # megabytes
+//! encoding = "armor"    # raw, hex, base64 or armor
+//!
+//! [profiles.work]
+//! vault = "/srv/otp/work"
+//! pad_size = 16
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Overrides the config file location.
+pub const ENV_CONFIG: &str = "OTP_CONFIG";
+/// Selects a profile when `--profile` is not given.
+pub const ENV_PROFILE: &str = "OTP_PROFILE";
+/// Sets the vault path.
+pub const ENV_VAULT: &str = "OTP_VAULT_PATH";
+/// Sets the default pad size in megabytes.
+pub const ENV_PAD_SIZE: &str = "OTP_PAD_SIZE";
+/// Sets the default ciphertext encoding.
+pub const ENV_ENCODING: &str = "OTP_ENCODING";
+/// The vault used when nothing else names one.
+pub const DEFAULT_VAULT: &str = "./.otp_vault";
+/// The pad size in megabytes used when nothing else sets one.
+pub const DEFAULT_PAD_SIZE: usize = 1;
+
+/// Values that may be set at one level of the configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// The vault directory.
+    pub vault: Option<PathBuf>,
+    /// The size of newly generated pads in megabytes.
+    pub pad_size: Option<usize>,
+    /// The default output format of `encrypt`: `raw`, `hex`, `base64` or `armor`.
+    pub encoding: Option<String>,
+}
+
+impl Settings {
+    /// Fills every value not set here from `fallback`.
+    #[must_use]
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            vault: self.vault.or(fallback.vault),
+            pad_size: self.pad_size.or(fallback.pad_size),
+            encoding: self.encoding.or(fallback.encoding),
+        }
+    }
+}
+
+/// The contents of a config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigFile {
+    /// The settings outside any section.
+    pub defaults: Settings,
+    /// The `[profiles.<name>]` sections.
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+/// The configuration in effect after combining all sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The selected profile, if any.
+    pub profile: Option<String>,
+    /// The vault directory.
+    pub vault: PathBuf,
+    /// The size of newly generated pads in megabytes.
+    pub pad_size: usize,
+    /// The default output format of `encrypt`, if configured.
+    pub encoding: Option<String>,
+}
+
+/// Where the config file is looked for, given a way to read environment variables.
+#[must_use]
+pub fn config_path(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(path) = env(ENV_CONFIG) {
+        return Some(PathBuf::from(path));
+    }
+    env("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("otp").join("config.toml"))
+}
+
+/// Reads the config file at `path`. A missing file is an empty configuration.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid.
+pub fn load_file(path: &Path) -> io::Result<ConfigFile> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parses the TOML subset described in the module documentation.
+///
+/// # Errors
+///
+/// Returns an error naming the line of an unknown key, section or malformed value.
+pub fn parse(text: &str) -> Result<ConfigFile, String> {
+    let mut file = ConfigFile::default();
+    let mut section: Option<String> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let line = strip_comment(raw).trim();
+        let fail = |message: String| format!("line {}: {message}", index + 1);
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            let name = header.trim().strip_prefix("profiles.").map(|name| name.trim().trim_matches('"'));
+            match name {
+                Some(name) if !name.is_empty() => {
+                    file.profiles.entry(name.to_string()).or_default();
+                    section = Some(name.to_string());
+                }
+                _ => return Err(fail(format!("unknown section [{}]", header.trim()))),
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(fail("expected `key = value`".to_string()));
+        };
+        let settings = match &section {
+            Some(name) => file.profiles.entry(name.clone()).or_default(),
+            None => &mut file.defaults,
+        };
+        let value = value.trim();
+        match key.trim() {
+            "vault" => settings.vault = Some(expand_home(&parse_string(value).map_err(fail)?)),
+            "pad_size" => {
+                settings.pad_size = Some(value.parse().ok().filter(|size| *size > 0).ok_or_else(|| fail(format!("invalid pad_size {value}")))?);
+            }
+            "encoding" => settings.encoding = Some(parse_string(value).map_err(fail)?),
+            other => return Err(fail(format!("unknown key '{other}'"))),
+        }
+    }
+    Ok(file)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted string, found {value}"))?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            other => return Err(format!("unsupported escape \\{}", other.map(String::from).unwrap_or_default())),
+        }
+    }
+    Ok(result)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Combines `flags`, the environment read through `env`, and `file` into the effective
+/// configuration. `profile` is the `--profile` flag.
+///
+/// # Errors
+///
+/// Returns an error if the selected profile is not in the file or an environment
+/// variable holds an invalid value.
+pub fn resolve(file: &ConfigFile, profile: Option<&str>, flags: Settings, env: &dyn Fn(&str) -> Option<String>) -> Result<Config, String> {
+    let profile = profile.map(str::to_string).or_else(|| env(ENV_PROFILE).filter(|name| !name.is_empty()));
+    let from_profile = match &profile {
+        Some(name) => file.profiles.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = file.profiles.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("profile '{name}' is not defined; the config file has no profiles")
+            } else {
+                format!("profile '{name}' is not defined; known profiles: {}", known.join(", "))
+            }
+        })?,
+        None => Settings::default(),
+    };
+    let pad_size = match env(ENV_PAD_SIZE) {
+        Some(value) => Some(value.parse().ok().filter(|size| *size > 0).ok_or_else(|| format!("{ENV_PAD_SIZE} is not a valid size: {value}"))?),
+        None => None,
+    };
+    let from_env = Settings { vault: env(ENV_VAULT).map(PathBuf::from), pad_size, encoding: env(ENV_ENCODING) };
+    let settings = flags.or(from_env).or(from_profile).or(file.defaults.clone());
+    Ok(Config {
+        profile,
+        vault: settings.vault.unwrap_or_else(|| PathBuf::from(DEFAULT_VAULT)),
+        pad_size: settings.pad_size.unwrap_or(DEFAULT_PAD_SIZE),
+        encoding: settings.encoding,
+    })
+}
+
+/// Loads the configuration from the process environment and the config file it points to.
+///
+/// # Errors
+///
+/// Returns an error if the config file is invalid or [`resolve`] fails.
+pub fn load(profile: Option<&str>, flags: Settings) -> Result<Config, String> {
+    let env = |name: &str| std::env::var(name).ok();
+    let file = match config_path(&env) {
+        Some(path) => load_file(&path).map_err(|e| e.to_string())?,
+        None => ConfigFile::default(),
+    };
+    resolve(&file, profile, flags, &env)
+}
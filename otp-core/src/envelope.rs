@@ -0,0 +1,198 @@
+// File:    envelope.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: An inner header and integrity trailer that travel inside the pad-protected payload.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Payload envelopes.
+//!
+//! Before encryption the payload is wrapped as
+//!
+//! ```text
+//! "OTPFILE1" | header_len: u32 (big-endian) | header: JSON | payload | SHA-256(payload)
+//! ```
+//!
+//! so the original file name and attributes are encrypted together with the content
+//! and the receiver can verify the plaintext end to end. The digest is a trailer
+//! rather than part of the header so that input of unknown length can be streamed.
+//! When the header carries a sequence number, the digest covers the header JSON as well,
+//! binding the number to the content: `SHA-256(header | payload)`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The bytes every wrapped payload starts with.
+pub const MAGIC: &[u8; 8] = b"OTPFILE1";
+const DIGEST_LEN: usize = 32;
+
+/// What is known about the original plaintext.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileHeader {
+    /// The original file name, without any directory part.
+    pub name: Option<String>,
+    /// The payload size in bytes, if it was known before encryption.
+    pub size: Option<u64>,
+    /// The modification time in seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    /// The Unix permission bits.
+    pub mode: Option<u32>,
+    /// The vault ID of the sender, naming the sequence `seq` belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// The sender's sequence number for this pad, starting at 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl FileHeader {
+    /// Describes a file or directory on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path's metadata cannot be read.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            name: path.file_name().map(|name| name.to_string_lossy().into_owned()),
+            size: metadata.is_file().then_some(metadata.len()),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            mode: Some(crate::archive::permission_bits(&metadata)),
+            ..Self::default()
+        })
+    }
+
+    /// The stored name, if it is a plain file name that cannot point anywhere else.
+    #[must_use]
+    pub fn safe_file_name(&self) -> Option<&str> {
+        let name = self.name.as_deref()?;
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !name.contains(['/', '\\']) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Restores the modification time and permission bits on a written file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the attributes cannot be set.
+    pub fn apply_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(mtime) = self.mtime {
+            fs::File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+        if let Some(mode) = self.mode {
+            crate::archive::set_permission_bits(path, mode)?;
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let json = self.json();
+        let mut prefix = Vec::with_capacity(MAGIC.len() + 4 + json.len());
+        prefix.extend(MAGIC);
+        prefix.extend(u32::try_from(json.len()).unwrap_or(u32::MAX).to_be_bytes());
+        prefix.extend(json);
+        prefix
+    }
+
+    fn json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// A digest with the header already fed in when the header must be covered too.
+    fn digest_start(&self, header_json: &[u8]) -> Sha256 {
+        let mut hasher = Sha256::new();
+        if self.seq.is_some() {
+            hasher.update(header_json);
+        }
+        hasher
+    }
+}
+
+/// The number of bytes a payload of `payload_len` bytes occupies once wrapped.
+#[must_use]
+pub fn wrapped_len(header: &FileHeader, payload_len: usize) -> usize {
+    header.encode().len() + payload_len + DIGEST_LEN
+}
+
+/// Wraps a payload stream in the envelope, hashing it as it passes through.
+pub struct Wrap<R> {
+    prefix: io::Cursor<Vec<u8>>,
+    inner: R,
+    hasher: Option<Sha256>,
+    trailer: io::Cursor<Vec<u8>>,
+}
+
+impl<R: Read> Wrap<R> {
+    /// Starts wrapping `inner` behind `header`.
+    pub fn new(header: &FileHeader, inner: R) -> Self {
+        Self {
+            prefix: io::Cursor::new(header.encode()),
+            inner,
+            hasher: Some(header.digest_start(&header.json())),
+            trailer: io::Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl<R: Read> Read for Wrap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.prefix.read(buf)?;
+        if read > 0 {
+            return Ok(read);
+        }
+        if let Some(hasher) = self.hasher.as_mut() {
+            let read = self.inner.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                hasher.update(&buf[..read]);
+                return Ok(read);
+            }
+            let digest = self.hasher.take().map(Sha256::finalize).unwrap_or_default();
+            self.trailer = io::Cursor::new(digest.to_vec());
+        }
+        self.trailer.read(buf)
+    }
+}
+
+/// Removes the envelope from decrypted data and checks the payload digest.
+///
+/// Returns `None` for data that was encrypted without an envelope.
+///
+/// # Errors
+///
+/// Returns an error if the envelope is malformed or the payload does not match its digest.
+pub fn unwrap(data: &[u8]) -> io::Result<Option<(FileHeader, &[u8])>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let Some(rest) = data.strip_prefix(MAGIC.as_slice()) else {
+        return Ok(None);
+    };
+    let (len_bytes, rest) = rest.split_at_checked(4).ok_or_else(|| invalid("inner header is truncated"))?;
+    let header_len = u32::from_be_bytes(len_bytes.try_into().unwrap_or_default()) as usize;
+    let (header_json, rest) = rest.split_at_checked(header_len).ok_or_else(|| invalid("inner header is truncated"))?;
+    let header: FileHeader = serde_json::from_slice(header_json).map_err(|e| invalid(&format!("inner header is invalid: {e}")))?;
+    let payload_len = rest.len().checked_sub(DIGEST_LEN).ok_or_else(|| invalid("plaintext digest is missing"))?;
+    let (payload, digest) = rest.split_at(payload_len);
+    if header.digest_start(header_json).chain_update(payload).finalize().as_slice() != digest {
+        return Err(invalid("plaintext SHA-256 does not match; the message is corrupt or the wrong pad bytes were used"));
+    }
+    if header.size.is_some_and(|size| size != payload.len() as u64) {
+        return Err(invalid("plaintext size does not match the inner header"));
+    }
+    Ok(Some((header, payload)))
+}
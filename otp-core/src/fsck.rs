@@ -0,0 +1,307 @@
+// File:    fsck.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Finds and repairs disagreements between the vault state and the pad files on disk.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Vault consistency checking.
+//!
+//! [`check`] compares `vault_state.json` with the files under `pads/available` and
+//! `pads/used` and with itself. [`repair`] then fixes what cannot make a pad byte usable
+//! twice: out-of-bounds and duplicate segment records, the `is_fully_used` flag and the
+//! directory a file lives in. Everything else is quarantined, either by marking the pad
+//! as quarantined or by moving a stray file to `pads/quarantine`, so nobody encrypts with
+//! it until a person has looked at it.
+
+use crate::state_manager::{Pad, UsedSegment, VaultState};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Where [`repair`] moves pad files it cannot account for, relative to the vault.
+pub const QUARANTINE_DIR: &str = "pads/quarantine";
+
+/// One inconsistency in a vault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A file in a pad directory that no pad in the state refers to.
+    OrphanFile {
+        /// The file.
+        path: PathBuf,
+    },
+    /// A pad whose file is in neither pad directory.
+    MissingFile {
+        /// The pad.
+        pad_id: String,
+    },
+    /// A pad whose file is not as long as the state says.
+    SizeMismatch {
+        /// The pad.
+        pad_id: String,
+        /// The size recorded in the state.
+        expected: usize,
+        /// The size of the file.
+        actual: u64,
+    },
+    /// A used segment that is reversed or reaches past the end of the pad.
+    OutOfBounds {
+        /// The pad.
+        pad_id: String,
+        /// The recorded segment.
+        segment: Range<usize>,
+    },
+    /// A used segment recorded more than once.
+    Duplicate {
+        /// The pad.
+        pad_id: String,
+        /// The repeated segment.
+        segment: Range<usize>,
+    },
+    /// Two different used segments sharing bytes, which may mean the bytes were used twice.
+    Overlap {
+        /// The pad.
+        pad_id: String,
+        /// The shared bytes.
+        bytes: Range<usize>,
+    },
+    /// An `is_fully_used` flag that does not match the recorded usage.
+    StaleFlag {
+        /// The pad.
+        pad_id: String,
+        /// The flag as recorded.
+        recorded: bool,
+    },
+    /// A pad file in `available` although the pad is fully used, or the other way round.
+    WrongDirectory {
+        /// The pad.
+        pad_id: String,
+        /// Where the file is.
+        path: PathBuf,
+    },
+}
+
+impl Problem {
+    /// Whether [`repair`] fixes the problem rather than quarantining it.
+    #[must_use]
+    pub const fn is_repairable(&self) -> bool {
+        matches!(self, Self::OutOfBounds { .. } | Self::Duplicate { .. } | Self::StaleFlag { .. } | Self::WrongDirectory { .. })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OrphanFile { path } => write!(f, "'{}' does not belong to any pad", path.display()),
+            Self::MissingFile { pad_id } => write!(f, "pad '{pad_id}' has no pad file"),
+            Self::SizeMismatch { pad_id, expected, actual } => {
+                write!(f, "pad '{pad_id}' should be {expected} bytes, but its file has {actual}")
+            }
+            Self::OutOfBounds { pad_id, segment } => {
+                write!(f, "pad '{pad_id}' has an invalid used segment {}..{}", segment.start, segment.end)
+            }
+            Self::Duplicate { pad_id, segment } => {
+                write!(f, "pad '{pad_id}' records segment {}..{} more than once", segment.start, segment.end)
+            }
+            Self::Overlap { pad_id, bytes } => {
+                write!(f, "pad '{pad_id}' has overlapping used segments on bytes {}..{}", bytes.start, bytes.end)
+            }
+            Self::StaleFlag { pad_id, recorded: true } => write!(f, "pad '{pad_id}' is marked fully used but has bytes left"),
+            Self::StaleFlag { pad_id, recorded: false } => write!(f, "pad '{pad_id}' is fully used but not marked so"),
+            Self::WrongDirectory { pad_id, path } => write!(f, "pad '{pad_id}' is in the wrong directory at '{}'", path.display()),
+        }
+    }
+}
+
+/// Finds every problem in the vault at `vault_path` with `state`.
+///
+/// # Errors
+///
+/// Returns an error if a pad directory cannot be read.
+pub fn check(vault_path: &Path, state: &VaultState) -> io::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let mut pads: Vec<&Pad> = state.pads.values().collect();
+    pads.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut accounted = HashSet::new();
+
+    for pad in pads {
+        let pad_id = pad.id.clone();
+        let segments = repaired_segments(pad);
+        for segment in &pad.used_segments {
+            if !in_bounds(segment, pad.size) {
+                problems.push(Problem::OutOfBounds { pad_id: pad_id.clone(), segment: segment.start..segment.end });
+            }
+        }
+        let mut seen = HashSet::new();
+        for segment in pad.used_segments.iter().filter(|s| in_bounds(s, pad.size)) {
+            if !seen.insert((segment.start, segment.end)) {
+                problems.push(Problem::Duplicate { pad_id: pad_id.clone(), segment: segment.start..segment.end });
+            }
+        }
+        for bytes in overlaps(&segments) {
+            problems.push(Problem::Overlap { pad_id: pad_id.clone(), bytes });
+        }
+
+        let full = pad.next_offset.unwrap_or(0) >= pad.size || segments.iter().map(Range::len).sum::<usize>() >= pad.size;
+        if pad.is_fully_used != full {
+            problems.push(Problem::StaleFlag { pad_id: pad_id.clone(), recorded: pad.is_fully_used });
+        }
+
+        let expected = pad_path(vault_path, &pad.file_name, full);
+        let other = pad_path(vault_path, &pad.file_name, !full);
+        let found = if expected.is_file() {
+            if other.is_file() {
+                problems.push(Problem::OrphanFile { path: other.clone() });
+            }
+            Some(expected.clone())
+        } else if other.is_file() {
+            problems.push(Problem::WrongDirectory { pad_id: pad_id.clone(), path: other.clone() });
+            Some(other.clone())
+        } else {
+            problems.push(Problem::MissingFile { pad_id: pad_id.clone() });
+            None
+        };
+        if let Some(path) = found {
+            let actual = fs::metadata(&path)?.len();
+            if actual != pad.size as u64 {
+                problems.push(Problem::SizeMismatch { pad_id, expected: pad.size, actual });
+            }
+        }
+        accounted.insert(expected);
+        accounted.insert(other);
+    }
+
+    for dir in ["available", "used"] {
+        let dir = vault_path.join("pads").join(dir);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let mut orphans = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() && !accounted.contains(&path) {
+                orphans.push(path);
+            }
+        }
+        orphans.sort();
+        problems.extend(orphans.into_iter().map(|path| Problem::OrphanFile { path }));
+    }
+    Ok(problems)
+}
+
+/// Repairs or quarantines `problems`, as found by [`check`], and returns a description
+/// of each action taken. The caller saves the state afterwards.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be moved; the actions before it have been applied.
+pub fn repair(vault_path: &Path, state: &mut VaultState, problems: &[Problem]) -> io::Result<Vec<String>> {
+    let mut actions = Vec::new();
+    let mut cleaned = HashSet::new();
+    for problem in problems {
+        match problem {
+            Problem::OutOfBounds { pad_id, .. } | Problem::Duplicate { pad_id, .. } => {
+                if let Some(pad) = state.pads.get_mut(pad_id)
+                    && cleaned.insert(pad_id.clone())
+                {
+                    let mut kept: Vec<UsedSegment> = Vec::new();
+                    for mut segment in std::mem::take(&mut pad.used_segments) {
+                        let Some(range) = clamp(segment.start..segment.end, pad.size) else {
+                            continue;
+                        };
+                        if !kept.iter().any(|s| (s.start, s.end) == (range.start, range.end)) {
+                            (segment.start, segment.end) = (range.start, range.end);
+                            kept.push(segment);
+                        }
+                    }
+                    pad.used_segments = kept;
+                    pad.reindex();
+                    actions.push(format!("Cleaned up the used segments of pad '{pad_id}'"));
+                }
+            }
+            Problem::StaleFlag { pad_id, recorded } => {
+                if let Some(pad) = state.pads.get_mut(pad_id) {
+                    pad.is_fully_used = !recorded;
+                    actions.push(format!("Marked pad '{pad_id}' as {}", if *recorded { "not fully used" } else { "fully used" }));
+                }
+            }
+            Problem::WrongDirectory { pad_id, path } => {
+                if let Some(pad) = state.pads.get(pad_id) {
+                    let destination = pad_path(vault_path, &pad.file_name, pad.is_fully_used);
+                    fs::rename(path, &destination)?;
+                    actions.push(format!("Moved pad '{pad_id}' to '{}'", destination.display()));
+                }
+            }
+            Problem::OrphanFile { path } => {
+                let quarantine = vault_path.join(QUARANTINE_DIR);
+                fs::create_dir_all(&quarantine)?;
+                let destination = quarantine.join(path.file_name().unwrap_or_default());
+                fs::rename(path, &destination)?;
+                actions.push(format!("Quarantined '{}' as '{}'", path.display(), destination.display()));
+            }
+            Problem::MissingFile { pad_id } | Problem::SizeMismatch { pad_id, .. } | Problem::Overlap { pad_id, .. } => {
+                if let Some(pad) = state.pads.get_mut(pad_id) {
+                    if pad.quarantined.is_none() {
+                        pad.quarantined = Some(format!("vault check: {problem}"));
+                    }
+                    actions.push(format!("Quarantined pad '{pad_id}'"));
+                }
+            }
+        }
+    }
+    actions.dedup();
+    Ok(actions)
+}
+
+fn pad_path(vault_path: &Path, file_name: &str, fully_used: bool) -> PathBuf {
+    vault_path.join("pads").join(if fully_used { "used" } else { "available" }).join(file_name)
+}
+
+const fn in_bounds(segment: &UsedSegment, size: usize) -> bool {
+    segment.start <= segment.end && segment.end <= size
+}
+
+/// Turns a recorded segment into the bytes it can safely stand for. A reversed segment
+/// is read the other way round, so no byte it might have meant becomes free again.
+fn clamp(range: Range<usize>, size: usize) -> Option<Range<usize>> {
+    let (start, end) = if range.start > range.end { (range.end, range.start) } else { (range.start, range.end) };
+    (start < size.min(end)).then(|| start..end.min(size))
+}
+
+/// The distinct, in-bounds segments of `pad` as [`repair`] leaves them.
+fn repaired_segments(pad: &Pad) -> Vec<Range<usize>> {
+    let mut segments: Vec<Range<usize>> = Vec::new();
+    for range in pad.used_segments.iter().filter_map(|s| clamp(s.start..s.end, pad.size)) {
+        if !segments.contains(&range) {
+            segments.push(range);
+        }
+    }
+    segments
+}
+
+/// The bytes covered by more than one of `segments`.
+fn overlaps(segments: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut sorted = segments.to_vec();
+    sorted.sort_by_key(|r| (r.start, r.end));
+    let mut shared: Vec<Range<usize>> = Vec::new();
+    let mut reach = 0;
+    for (index, range) in sorted.iter().enumerate() {
+        if index > 0 && range.start < reach {
+            let bytes = range.start..range.end.min(reach);
+            match shared.last_mut() {
+                Some(last) if last.end >= bytes.start => last.end = last.end.max(bytes.end),
+                _ => shared.push(bytes),
+            }
+        }
+        reach = reach.max(range.end);
+    }
+    shared
+}
@@ -0,0 +1,296 @@
+// File:    letters.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Pen-and-paper one-time pad ciphers on letters and digits using modular addition.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Classic hand ciphers for partners who encrypt or decrypt without a computer.
+//!
+//! Two tables are supported:
+//!
+//! * **DIANA** works on the 26 letters. Plaintext, key and ciphertext letters always
+//!   satisfy `P + K + C ≡ 25 (mod 26)`, so the same trigraph table both encrypts and
+//!   decrypts. Whitespace is written as `X` and digits are spelled out.
+//! * **Straddling checkerboard** turns text into digits with the `AT ONE SIR`
+//!   checkerboard, then adds key digits modulo 10 without carry.
+//!
+//! ```text
+//!      0 1 2 3 4 5 6 7 8 9
+//!      A T   O N E   S I R
+//!   2  B C D F G H J K L M
+//!   6  P Q U V W X Y Z _ /      _ = space, / = next digit is a figure
+//! ```
+//!
+//! Key symbols are drawn from ordinary pad bytes by rejection sampling: a byte is only
+//! used when it falls below the largest multiple of the modulus, so every key symbol is
+//! uniformly distributed. As a result a message of `n` symbols consumes slightly more
+//! than `n` pad bytes.
+
+/// The hand cipher table to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    /// The reciprocal DIANA table over the letters A-Z.
+    Diana,
+    /// The `AT ONE SIR` straddling checkerboard with mod-10 addition.
+    Checkerboard,
+}
+
+const DIGIT_WORDS: [&str; 10] = [
+    "ZERO", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE",
+];
+const CHECKERBOARD_TOP: [Option<char>; 10] = [
+    Some('A'), Some('T'), None, Some('O'), Some('N'), Some('E'), None, Some('S'), Some('I'), Some('R'),
+];
+const CHECKERBOARD_ROW_2: [char; 10] = ['B', 'C', 'D', 'F', 'G', 'H', 'J', 'K', 'L', 'M'];
+const CHECKERBOARD_ROW_6: [char; 8] = ['P', 'Q', 'U', 'V', 'W', 'X', 'Y', 'Z'];
+const CHECKERBOARD_SPACE: u8 = 8;
+const CHECKERBOARD_FIGURE: u8 = 9;
+const GROUP_SIZE: usize = 5;
+const GROUPS_PER_LINE: usize = 5;
+
+impl Table {
+    /// The number of distinct symbols in the table's cipher alphabet.
+    #[must_use]
+    pub const fn modulus(self) -> u8 {
+        match self {
+            Self::Diana => 26,
+            Self::Checkerboard => 10,
+        }
+    }
+
+    /// The largest multiple of the modulus that fits in a byte. Pad bytes at or above it
+    /// would bias the low symbols and are skipped.
+    const fn rejection_limit(self) -> u16 {
+        256 - (256 % self.modulus() as u16)
+    }
+
+    const fn symbol_char(self, symbol: u8) -> char {
+        match self {
+            Self::Diana => (b'A' + symbol) as char,
+            Self::Checkerboard => (b'0' + symbol) as char,
+        }
+    }
+
+    const fn char_symbol(self, c: char) -> Option<u8> {
+        let c = c.to_ascii_uppercase();
+        match self {
+            Self::Diana if c.is_ascii_uppercase() => Some(c as u8 - b'A'),
+            Self::Checkerboard if c.is_ascii_digit() => Some(c as u8 - b'0'),
+            _ => None,
+        }
+    }
+}
+
+/// Draws `count` uniformly distributed key symbols from pad bytes.
+///
+/// Returns the key symbols and the number of pad bytes consumed to produce them, or
+/// `None` if `pad_bytes` runs out first.
+#[must_use]
+pub fn key_stream(table: Table, pad_bytes: &[u8], count: usize) -> Option<(Vec<u8>, usize)> {
+    let modulus = table.modulus();
+    let limit = table.rejection_limit();
+    let mut keys = Vec::with_capacity(count);
+    if count == 0 {
+        return Some((keys, 0));
+    }
+    for (index, &byte) in pad_bytes.iter().enumerate() {
+        if u16::from(byte) < limit {
+            keys.push(byte % modulus);
+            if keys.len() == count {
+                return Some((keys, index + 1));
+            }
+        }
+    }
+    None
+}
+
+/// An upper bound on the pad bytes needed for `count` symbols, for reserving pad space.
+///
+/// Rejection sampling discards at most 22 of every 256 bytes on average, so a quarter
+/// extra plus a fixed allowance makes running out practically impossible.
+#[must_use]
+pub const fn pad_budget(count: usize) -> usize {
+    count + count / 4 + 64
+}
+
+/// Converts plaintext into cipher symbols using the table's conversion rules.
+///
+/// # Errors
+///
+/// Returns an error naming the first character the table cannot represent.
+pub fn encode_plaintext(table: Table, text: &str) -> Result<Vec<u8>, String> {
+    let mut symbols = Vec::with_capacity(text.len());
+    for c in text.trim().chars() {
+        let upper = c.to_ascii_uppercase();
+        match table {
+            Table::Diana => {
+                if upper.is_ascii_uppercase() {
+                    symbols.push(upper as u8 - b'A');
+                } else if c.is_whitespace() {
+                    symbols.push(b'X' - b'A');
+                } else if let Some(digit) = c.to_digit(10) {
+                    symbols.extend(DIGIT_WORDS[digit as usize].bytes().map(|b| b - b'A'));
+                } else {
+                    return Err(format!("character '{c}' cannot be written with the DIANA table"));
+                }
+            }
+            Table::Checkerboard => {
+                if let Some(digit) = c.to_digit(10) {
+                    symbols.extend([6, CHECKERBOARD_FIGURE, u8::try_from(digit).unwrap_or_default()]);
+                } else if c.is_whitespace() {
+                    symbols.extend([6, CHECKERBOARD_SPACE]);
+                } else if let Some(column) = CHECKERBOARD_TOP.iter().position(|&t| t == Some(upper)) {
+                    symbols.push(u8::try_from(column).unwrap_or_default());
+                } else if let Some(column) = CHECKERBOARD_ROW_2.iter().position(|&t| t == upper) {
+                    symbols.extend([2, u8::try_from(column).unwrap_or_default()]);
+                } else if let Some(column) = CHECKERBOARD_ROW_6.iter().position(|&t| t == upper) {
+                    symbols.extend([6, u8::try_from(column).unwrap_or_default()]);
+                } else {
+                    return Err(format!("character '{c}' is not on the straddling checkerboard"));
+                }
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// Converts decrypted cipher symbols back into text.
+///
+/// DIANA output is returned as letters; spaces written as `X` cannot be told apart
+/// from a real `X` and are left for the reader, as on paper.
+#[must_use]
+pub fn decode_plaintext(table: Table, symbols: &[u8]) -> String {
+    match table {
+        Table::Diana => symbols.iter().map(|&s| table.symbol_char(s)).collect(),
+        Table::Checkerboard => {
+            let mut text = String::with_capacity(symbols.len());
+            let mut iter = symbols.iter().copied();
+            while let Some(symbol) = iter.next() {
+                match symbol {
+                    2 => {
+                        if let Some(column) = iter.next() {
+                            text.push(CHECKERBOARD_ROW_2[usize::from(column)]);
+                        }
+                    }
+                    6 => match iter.next() {
+                        Some(CHECKERBOARD_SPACE) => text.push(' '),
+                        Some(CHECKERBOARD_FIGURE) => {
+                            if let Some(digit) = iter.next() {
+                                text.push(table.symbol_char(digit));
+                            }
+                        }
+                        Some(column) => text.push(CHECKERBOARD_ROW_6[usize::from(column)]),
+                        None => {}
+                    },
+                    column => {
+                        if let Some(c) = CHECKERBOARD_TOP[usize::from(column)] {
+                            text.push(c);
+                        }
+                    }
+                }
+            }
+            text
+        }
+    }
+}
+
+/// Encrypts plaintext symbols with key symbols of the same length.
+///
+/// # Panics
+///
+/// Panics if the slices are not of equal length.
+#[must_use]
+pub fn encrypt(table: Table, plaintext: &[u8], key: &[u8]) -> Vec<u8> {
+    assert_eq!(plaintext.len(), key.len(), "Plaintext and key must have the same length.");
+    let modulus = table.modulus();
+    plaintext
+        .iter()
+        .zip(key)
+        .map(|(&p, &k)| match table {
+            Table::Diana => (2 * modulus - 1 - p - k) % modulus,
+            Table::Checkerboard => (p + k) % modulus,
+        })
+        .collect()
+}
+
+/// Decrypts ciphertext symbols with key symbols of the same length.
+///
+/// # Panics
+///
+/// Panics if the slices are not of equal length.
+#[must_use]
+pub fn decrypt(table: Table, ciphertext: &[u8], key: &[u8]) -> Vec<u8> {
+    assert_eq!(ciphertext.len(), key.len(), "Ciphertext and key must have the same length.");
+    match table {
+        // DIANA is reciprocal: the same operation recovers the plaintext.
+        Table::Diana => encrypt(table, ciphertext, key),
+        Table::Checkerboard => ciphertext
+            .iter()
+            .zip(key)
+            .map(|(&c, &k)| (c + table.modulus() - k) % table.modulus())
+            .collect(),
+    }
+}
+
+/// Formats symbols as five-character groups, five groups to a line.
+#[must_use]
+pub fn format_groups(table: Table, symbols: &[u8]) -> String {
+    symbols
+        .chunks(GROUP_SIZE * GROUPS_PER_LINE)
+        .map(|line| {
+            line.chunks(GROUP_SIZE)
+                .map(|group| group.iter().map(|&s| table.symbol_char(s)).collect::<String>())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parses grouped ciphertext back into symbols, ignoring whitespace.
+///
+/// # Errors
+///
+/// Returns an error naming the first character outside the table's alphabet.
+pub fn parse_groups(table: Table, text: &str) -> Result<Vec<u8>, String> {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            table
+                .char_symbol(c)
+                .ok_or_else(|| format!("'{c}' is not a valid ciphertext character"))
+        })
+        .collect()
+}
+
+/// Renders the key symbols derived from a run of pad bytes as a printable key sheet.
+///
+/// Each line is prefixed with the pad offset at which its key stream starts, so a
+/// partner holding the sheet can find the key for a message that starts at that offset.
+#[must_use]
+pub fn render_key_sheet(table: Table, pad_bytes: &[u8], start: usize) -> Vec<String> {
+    let per_line = GROUP_SIZE * GROUPS_PER_LINE;
+    let mut lines = Vec::new();
+    let mut keys = Vec::with_capacity(per_line);
+    let mut line_start = start;
+    for (index, &byte) in pad_bytes.iter().enumerate() {
+        if keys.is_empty() {
+            line_start = start + index;
+        }
+        if u16::from(byte) < table.rejection_limit() {
+            keys.push(byte % table.modulus());
+            if keys.len() == per_line {
+                lines.push(format!("{line_start:08x}  {}", format_groups(table, &keys)));
+                keys.clear();
+            }
+        }
+    }
+    if !keys.is_empty() {
+        lines.push(format!("{line_start:08x}  {}", format_groups(table, &keys)));
+    }
+    lines
+}
@@ -13,9 +13,37 @@
 //! This library provides the core functionality for one-time pad (OTP) encryption,
 //! including pad generation, state management, and the cryptographic operations.
 
+/// Packing directory trees into a single archive stream.
+pub mod archive;
+/// ASCII armor for sending ciphertexts as plain text.
+pub mod armor;
+/// The append-only, hash-chained audit log of vault operations.
+pub mod audit;
+/// Consistent vault snapshots and restoring them.
+pub mod backup;
+/// The configuration file shared by the command line and web front ends.
+pub mod config;
 /// Cryptographic operations for encryption and decryption.
 pub mod crypto;
+/// The encrypted inner header carrying file attributes and a plaintext digest.
+pub mod envelope;
+/// Consistency checks and repair of the vault state against the pad files.
+pub mod fsck;
+/// Pen-and-paper ciphers on letters and digits for hand decryption.
+pub mod letters;
 /// Utilities for generating new one-time pads.
 pub mod pad_generator;
+/// Free-space maps and usage bars of pads.
+pub mod pad_map;
+/// Printable text and QR-code transfer of pad material.
+pub mod paper;
+/// Message sequence numbers and replay detection.
+pub mod sequence;
 /// Manages the state of the OTP vault, including pad usage.
-pub mod state_manager;
\ No newline at end of file
+pub mod state_manager;
+/// Authenticated usage receipts for keeping partners' vaults in agreement.
+pub mod sync;
+/// Warnings and replenishment when pad material runs low.
+pub mod thresholds;
+/// Unix timestamps and UTC formatting.
+pub mod timestamp;
@@ -0,0 +1,123 @@
+// File:    pad_map.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Reports where a pad's free bytes are and draws them as a usage bar.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Free-space maps of pads.
+//!
+//! A pad's remaining total says little once it is fragmented: a pad with half its bytes
+//! left in thousands of tiny gaps cannot carry a long message. A [`PadMap`] lists the
+//! free ranges, the largest contiguous gap and the ranges this vault may still consume,
+//! and [`PadMap::bar`] draws the usage as one line of text.
+
+use crate::state_manager::Pad;
+use serde::Serialize;
+use std::ops::Range;
+
+/// The characters a usage bar is drawn with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BarStyle {
+    /// Unicode block shades.
+    #[default]
+    Unicode,
+    /// Plain ASCII for terminals without Unicode.
+    Ascii,
+}
+
+impl BarStyle {
+    /// The glyphs for a free, a partly used and a fully used cell.
+    const fn glyphs(self) -> [char; 3] {
+        match self {
+            Self::Unicode => ['░', '▒', '█'],
+            Self::Ascii => ['.', '+', '#'],
+        }
+    }
+
+    /// Explains the glyphs.
+    #[must_use]
+    pub fn legend(self) -> String {
+        let [free, partial, used] = self.glyphs();
+        format!("{used} used  {partial} partly used  {free} free")
+    }
+}
+
+/// Where the free bytes of a pad are.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PadMap {
+    /// The pad's ID.
+    pub pad_id: String,
+    /// The pad's size in bytes.
+    pub size: usize,
+    /// The bytes recorded as used.
+    pub used_bytes: usize,
+    /// The bytes not used yet.
+    pub free_bytes: usize,
+    /// The length of the longest free range.
+    pub largest_gap: usize,
+    /// The unused byte ranges in ascending order.
+    pub free_ranges: Vec<Range<usize>>,
+    /// The free ranges this vault may consume under the pad's lifecycle state and
+    /// direction or group policy.
+    pub usable_ranges: Vec<Range<usize>>,
+}
+
+impl PadMap {
+    /// Maps the free space of `pad`.
+    #[must_use]
+    pub fn of(pad: &Pad) -> Self {
+        let free_ranges = pad.free_ranges();
+        let free_bytes = free_ranges.iter().map(ExactSizeIterator::len).sum::<usize>();
+        Self {
+            pad_id: pad.id.clone(),
+            size: pad.size,
+            used_bytes: pad.size - free_bytes,
+            free_bytes,
+            largest_gap: free_ranges.iter().map(ExactSizeIterator::len).max().unwrap_or(0),
+            free_ranges,
+            usable_ranges: pad.allocatable_ranges(),
+        }
+    }
+
+    /// The first free range of the largest length, if any byte is free.
+    #[must_use]
+    pub fn largest_free_range(&self) -> Option<Range<usize>> {
+        self.free_ranges.iter().rev().max_by_key(|range| range.len()).cloned()
+    }
+
+    /// Draws the usage as `width` cells, each covering an equal share of the pad. A pad
+    /// smaller than `width` bytes gets one cell per byte.
+    #[must_use]
+    pub fn bar(&self, width: usize, style: BarStyle) -> String {
+        let [free_glyph, partial_glyph, used_glyph] = style.glyphs();
+        let width = width.min(self.size);
+        (0..width)
+            .map(|cell| {
+                let cell = self.cell_range(cell, width);
+                // The free ranges are sorted and disjoint, so only those after the first one
+                // ending inside the cell can overlap it.
+                let first = self.free_ranges.partition_point(|free| free.end <= cell.start);
+                let free: usize = self.free_ranges[first..]
+                    .iter()
+                    .take_while(|free| free.start < cell.end)
+                    .map(|free| free.end.min(cell.end) - free.start.max(cell.start))
+                    .sum();
+                match free {
+                    0 => used_glyph,
+                    free if free == cell.len() => free_glyph,
+                    _ => partial_glyph,
+                }
+            })
+            .collect()
+    }
+
+    /// The bytes covered by `cell` of a bar `width` cells wide.
+    fn cell_range(&self, cell: usize, width: usize) -> Range<usize> {
+        let boundary = |cell: usize| usize::try_from(cell as u128 * self.size as u128 / width as u128).unwrap_or(self.size);
+        boundary(cell)..boundary(cell + 1)
+    }
+}
@@ -0,0 +1,501 @@
+// File:    paper.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Renders pad material as checksummed text lines for printing or QR transfer, and parses them back.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Paper and QR-code transfer of pad material.
+//!
+//! A printed pad is a sequence of *sections*. Each section starts with a header line
+//! naming the pad and the byte range it covers, followed by numbered data lines and a
+//! closing line carrying the SHA-256 of the section. Every data line ends with a short
+//! checksum over its offset and content, so a mistyped or swapped line is reported by
+//! line number instead of silently producing a corrupt pad.
+//!
+//! ```text
+//! # OTP-PAD v1 id=<pad-id> size=1048576 range=0..64 encoding=hex peer=back
+//! 0001 00000000 a1b2 c3d4 e5f6 0718 9abc def0 1234 5678 : 3e4f
+//! ...
+//! # END sha256=<hex digest of the section bytes>
+//! ```
+//!
+//! The optional `peer` field carries the direction policy the receiving partner must
+//! follow, so a paired pad is set up consistently on both sides. The optional `group`
+//! field, e.g. `group=stripes/4096:alice,bob,carol`, carries the membership of a group
+//! pad.
+//!
+//! QR frames use the exact same format, one short section per frame, so scanned frames
+//! and typed pages are imported by the same parser.
+
+use data_encoding::{BASE32_NOPAD, HEXLOWER_PERMISSIVE};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::state_manager::{Direction, Group, IntervalSet};
+
+const HEADER_PREFIX: &str = "# OTP-PAD v1";
+const FOOTER_PREFIX: &str = "# END";
+
+/// The text encoding used for the data lines of a printed pad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lowercase hexadecimal, 16 bytes per line.
+    Hex,
+    /// RFC 4648 base32 without padding, 20 bytes per line.
+    Base32,
+}
+
+impl Encoding {
+    /// The number of pad bytes rendered on a single line.
+    #[must_use]
+    pub const fn bytes_per_line(self) -> usize {
+        match self {
+            Self::Hex => 16,
+            Self::Base32 => 20,
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Base32 => "base32",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Self::Hex),
+            "base32" => Some(Self::Base32),
+            _ => None,
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> String {
+        match self {
+            Self::Hex => data_encoding::HEXLOWER.encode(data),
+            Self::Base32 => BASE32_NOPAD.encode(data),
+        }
+    }
+
+    fn decode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Self::Hex => HEXLOWER_PERMISSIVE.decode(text.as_bytes()).ok(),
+            Self::Base32 => BASE32_NOPAD.decode(text.to_ascii_uppercase().as_bytes()).ok(),
+        }
+    }
+
+    const fn is_valid_char(self, c: char) -> bool {
+        match self {
+            Self::Hex => c.is_ascii_hexdigit(),
+            Self::Base32 => matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7'),
+        }
+    }
+}
+
+/// A contiguous run of pad bytes recovered from text.
+#[derive(Debug, Clone)]
+pub struct Section {
+    /// The ID of the pad the bytes belong to.
+    pub pad_id: String,
+    /// The total size of the pad in bytes.
+    pub pad_size: usize,
+    /// The offset of the first byte of `data` within the pad.
+    pub start: usize,
+    /// The recovered pad bytes.
+    pub data: Vec<u8>,
+    /// How the importing party is asked to share the pad.
+    pub sharing: Sharing,
+}
+
+/// The sharing terms printed with a pad, telling the importer which bytes they may use.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sharing {
+    /// The direction policy the importing partner is asked to follow.
+    pub peer: Option<Direction>,
+    /// The group the pad is shared with.
+    pub group: Option<Group>,
+}
+
+/// A problem found while parsing printed pad text, tied to the offending line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    /// The 1-based line number within the input text.
+    pub line_number: usize,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.message)
+    }
+}
+
+/// Computes the 4-hex-digit checksum of a data line.
+///
+/// The offset is part of the checksummed input so that two correct lines typed in the
+/// wrong order are still caught.
+fn line_checksum(offset: usize, data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(offset.to_be_bytes());
+    hasher.update(data);
+    let digest = hasher.finalize();
+    format!("{:02x}{:02x}", digest[0], digest[1])
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Renders a range of pad bytes as a printable section.
+///
+/// `start` is the offset of `data[0]` within the pad and is used for line offsets.
+/// `sharing` holds the terms the receiving party should record for the pad.
+#[must_use]
+pub fn render_section(
+    pad_id: &str,
+    pad_size: usize,
+    start: usize,
+    data: &[u8],
+    encoding: Encoding,
+    sharing: &Sharing,
+) -> Vec<String> {
+    let mut lines = Vec::with_capacity(data.len() / encoding.bytes_per_line() + 3);
+    let peer_field = sharing.peer.map(|p| format!(" peer={p}")).unwrap_or_default();
+    let group_field = sharing.group.as_ref().map(|g| format!(" group={g}")).unwrap_or_default();
+    lines.push(format!(
+        "{HEADER_PREFIX} id={pad_id} size={pad_size} range={start}..{} encoding={}{peer_field}{group_field}",
+        start + data.len(),
+        encoding.name()
+    ));
+    for (index, chunk) in data.chunks(encoding.bytes_per_line()).enumerate() {
+        let offset = start + index * encoding.bytes_per_line();
+        let encoded = encoding.encode(chunk);
+        let groups: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(4)
+            .map(|g| std::str::from_utf8(g).unwrap_or_default())
+            .collect();
+        lines.push(format!(
+            "{:04} {offset:08x} {} : {}",
+            index + 1,
+            groups.join(" "),
+            line_checksum(offset, chunk)
+        ));
+    }
+    lines.push(format!("{FOOTER_PREFIX} sha256={}", sha256_hex(data)));
+    lines
+}
+
+/// Splits a range of pad bytes into QR-sized sections.
+///
+/// Each returned string is a complete, self-describing section holding at most
+/// `lines_per_frame` data lines, suitable for encoding into a single QR code.
+#[must_use]
+pub fn render_qr_frames(
+    pad_id: &str,
+    pad_size: usize,
+    start: usize,
+    data: &[u8],
+    encoding: Encoding,
+    lines_per_frame: usize,
+    sharing: &Sharing,
+) -> Vec<String> {
+    let frame_bytes = encoding.bytes_per_line() * lines_per_frame.max(1);
+    data.chunks(frame_bytes)
+        .enumerate()
+        .map(|(index, chunk)| {
+            render_section(pad_id, pad_size, start + index * frame_bytes, chunk, encoding, sharing)
+                .join("\n")
+        })
+        .collect()
+}
+
+struct OpenSection {
+    header_line: usize,
+    pad_id: String,
+    pad_size: usize,
+    start: usize,
+    end: usize,
+    encoding: Encoding,
+    sharing: Sharing,
+    data: Vec<u8>,
+}
+
+impl OpenSection {
+    /// Reserves the slot of an unreadable line so the offsets of later lines still line up.
+    fn skip_line(&mut self) {
+        let remaining = self.end - self.start - self.data.len();
+        let skipped = self.encoding.bytes_per_line().min(remaining);
+        self.data.resize(self.data.len() + skipped, 0);
+    }
+}
+
+/// Parses a section header. `text_len` bounds the range a header may claim: every pad
+/// byte takes at least one character to write down, so a larger range cannot be real.
+fn parse_header(line: &str, text_len: usize) -> Result<OpenSection, String> {
+    let mut pad_id = None;
+    let mut pad_size = None;
+    let mut range = None;
+    let mut encoding = None;
+    let mut sharing = Sharing::default();
+    for field in line.trim_start_matches(HEADER_PREFIX).split_whitespace() {
+        let Some((key, value)) = field.split_once('=') else {
+            return Err(format!("malformed header field '{field}'"));
+        };
+        match key {
+            "id" => pad_id = Some(value.to_string()),
+            "size" => pad_size = value.parse::<usize>().ok(),
+            "range" => {
+                range = value
+                    .split_once("..")
+                    .and_then(|(a, b)| Some((a.parse::<usize>().ok()?, b.parse::<usize>().ok()?)));
+            }
+            "encoding" => encoding = Encoding::from_name(value),
+            "peer" => sharing.peer = Some(value.parse::<Direction>()?),
+            "group" => sharing.group = Some(value.parse::<Group>()?),
+            _ => {}
+        }
+    }
+    let (Some(pad_id), Some(pad_size), Some((start, end)), Some(encoding)) =
+        (pad_id, pad_size, range, encoding)
+    else {
+        return Err("header must contain id, size, range and encoding".to_string());
+    };
+    if start > end || end > pad_size {
+        return Err(format!("range {start}..{end} does not fit a pad of {pad_size} bytes"));
+    }
+    if end - start > text_len {
+        return Err(format!("range {start}..{end} is longer than the text that follows"));
+    }
+    Ok(OpenSection {
+        header_line: 0,
+        pad_id,
+        pad_size,
+        start,
+        end,
+        encoding,
+        sharing,
+        data: Vec::with_capacity(end - start),
+    })
+}
+
+/// Parses one data line of an open section, appending its bytes on success.
+///
+/// A gap before this line is reported but does not stop the line itself from being
+/// read, so one missing line does not cascade into errors on every line after it.
+fn parse_data_line(section: &mut OpenSection, line: &str, problems: &mut Vec<String>) {
+    let Some((body, checksum)) = line.rsplit_once(':') else {
+        problems.push("missing ' : checksum' at the end of the line".to_string());
+        section.skip_line();
+        return;
+    };
+    let mut fields = body.split_whitespace();
+    let (Some(_line_no), Some(offset_text)) = (fields.next(), fields.next()) else {
+        problems.push("expected a line number and an offset".to_string());
+        section.skip_line();
+        return;
+    };
+    let Ok(offset) = usize::from_str_radix(offset_text, 16) else {
+        problems.push(format!("offset '{offset_text}' is not hexadecimal"));
+        section.skip_line();
+        return;
+    };
+    let expected_offset = section.start + section.data.len();
+    if offset < expected_offset || offset >= section.end {
+        problems.push(format!(
+            "offset {offset:08x} out of sequence, expected {expected_offset:08x} (duplicated or misplaced line)"
+        ));
+        return;
+    }
+    if offset > expected_offset {
+        problems.push(format!(
+            "bytes {expected_offset:08x}..{offset:08x} are missing before this line"
+        ));
+        section.data.resize(offset - section.start, 0);
+    }
+
+    // Find the column of the first character the encoding cannot contain, so the
+    // user is pointed at the exact typo rather than just the line.
+    let data_start = line.find(offset_text).unwrap_or(0) + offset_text.len();
+    for (column, c) in line[data_start..body.len()].char_indices() {
+        if !c.is_whitespace() && !section.encoding.is_valid_char(c) {
+            problems.push(format!(
+                "column {}: '{c}' is not a valid {} character",
+                data_start + column + 1,
+                section.encoding.name()
+            ));
+            section.skip_line();
+            return;
+        }
+    }
+
+    let encoded: String = fields.collect();
+    let Some(data) = section.encoding.decode(&encoded) else {
+        problems.push(format!(
+            "'{encoded}' has an invalid length for {}",
+            section.encoding.name()
+        ));
+        section.skip_line();
+        return;
+    };
+    let computed = line_checksum(offset, &data);
+    let written = checksum.trim().to_ascii_lowercase();
+    if computed != written {
+        problems.push(format!(
+            "checksum mismatch at offset {offset:08x}: line reads '{written}' but the data hashes to '{computed}'; re-check this line for a typo"
+        ));
+        section.skip_line();
+        return;
+    }
+    if section.data.len() + data.len() > section.end - section.start {
+        problems.push("line runs past the end of the range given in the header".to_string());
+        return;
+    }
+    section.data.extend_from_slice(&data);
+}
+
+/// Parses printed or scanned pad text into its sections.
+///
+/// Every line is checked; all problems are returned together so a user can correct a
+/// whole page in one pass. Blank lines and text outside of sections are ignored.
+///
+/// # Errors
+///
+/// Returns every [`LineError`] found if any header, data line or section digest fails
+/// validation.
+pub fn parse_sections(text: &str) -> Result<Vec<Section>, Vec<LineError>> {
+    let mut sections = Vec::new();
+    let mut errors = Vec::new();
+    let mut open: Option<OpenSection> = None;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with(HEADER_PREFIX) {
+            if let Some(unclosed) = open.take() {
+                errors.push(LineError {
+                    line_number: unclosed.header_line,
+                    message: "section is missing its '# END' line".to_string(),
+                });
+            }
+            match parse_header(line, text.len()) {
+                Ok(mut section) => {
+                    section.header_line = line_number;
+                    open = Some(section);
+                }
+                Err(message) => errors.push(LineError { line_number, message }),
+            }
+        } else if let Some(digest) = line.strip_prefix(FOOTER_PREFIX) {
+            let Some(section) = open.take() else {
+                errors.push(LineError { line_number, message: "'# END' without a header".to_string() });
+                continue;
+            };
+            let expected_len = section.end - section.start;
+            if section.data.len() != expected_len {
+                errors.push(LineError {
+                    line_number,
+                    message: format!(
+                        "section holds {} of {expected_len} bytes; lines are missing",
+                        section.data.len()
+                    ),
+                });
+                continue;
+            }
+            let written = digest.trim().trim_start_matches("sha256=");
+            if written != sha256_hex(&section.data) {
+                // Only meaningful if every line passed; otherwise the line errors say more.
+                if errors.is_empty() {
+                    errors.push(LineError {
+                        line_number,
+                        message: "section digest does not match its data".to_string(),
+                    });
+                }
+                continue;
+            }
+            sections.push(Section {
+                pad_id: section.pad_id,
+                pad_size: section.pad_size,
+                start: section.start,
+                data: section.data,
+                sharing: section.sharing,
+            });
+        } else if let Some(section) = open.as_mut() {
+            let mut problems = Vec::new();
+            parse_data_line(section, line, &mut problems);
+            errors.extend(problems.into_iter().map(|message| LineError { line_number, message }));
+        }
+    }
+    if let Some(unclosed) = open {
+        errors.push(LineError {
+            line_number: unclosed.header_line,
+            message: "section is missing its '# END' line".to_string(),
+        });
+    }
+
+    if errors.is_empty() { Ok(sections) } else { Err(errors) }
+}
+
+/// Reassembles a complete pad from parsed sections.
+///
+/// Sections may arrive in any order (as QR frames often do) and may overlap, but must
+/// all belong to the same pad, agree where they overlap, and together cover every byte.
+/// A printout of only part of a pad, as made with `--range`, is refused: the missing
+/// bytes could never be told apart from pad material.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the sections disagree or leave gaps.
+pub fn assemble_pad(sections: &[Section]) -> Result<(String, Vec<u8>), String> {
+    let Some(first) = sections.first() else {
+        return Err("no pad sections found".to_string());
+    };
+    let mut covered = IntervalSet::new();
+    for section in sections {
+        if section.pad_id != first.pad_id || section.pad_size != first.pad_size {
+            return Err(format!(
+                "sections belong to different pads ('{}' and '{}')",
+                first.pad_id, section.pad_id
+            ));
+        }
+        if section.sharing != first.sharing {
+            return Err("sections disagree about how the pad is shared".to_string());
+        }
+        match section.start.checked_add(section.data.len()) {
+            Some(end) if end <= first.pad_size => covered.insert(section.start..end),
+            _ => return Err(format!("a section runs past the end of the {}-byte pad", first.pad_size)),
+        };
+    }
+    // Checked before allocating: the sizes come from the text, the data does not lie
+    if let Some(missing) = covered.gaps(0..first.pad_size).next() {
+        return Err(format!(
+            "the text holds {} of the {} pad bytes; bytes {}..{} are missing. A printout of part of a pad cannot be imported; print the whole pad",
+            covered.covered(),
+            first.pad_size,
+            missing.start,
+            missing.end
+        ));
+    }
+
+    let mut pad = vec![0u8; first.pad_size];
+    let mut written = IntervalSet::new();
+    for section in sections {
+        let range = section.start..section.start + section.data.len();
+        for done in written.iter() {
+            let overlap = done.start.max(range.start)..done.end.min(range.end);
+            if let Some(position) = overlap.into_iter().find(|&p| pad[p] != section.data[p - range.start]) {
+                return Err(format!("sections disagree about byte {position}"));
+            }
+        }
+        pad[range.clone()].copy_from_slice(&section.data);
+        written.insert(range);
+    }
+    Ok((first.pad_id.clone(), pad))
+}
@@ -0,0 +1,94 @@
+// File:    sequence.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Per-pad message sequence numbers and the receive window that detects replays.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Replay and reordering detection.
+//!
+//! Every message a vault encrypts with a pad carries the next number of that vault's
+//! sequence for the pad, starting at 1, inside the encrypted inner header. The receiver
+//! keeps one sliding window per pad and sender, in the style of the IPsec anti-replay
+//! window: the highest number seen plus a bitmap of the [`WINDOW`] numbers below it.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How many sequence numbers below the highest one are remembered individually.
+pub const WINDOW: u64 = 64;
+
+/// What a receiver has seen of one sender's sequence on one pad.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceWindow {
+    /// The highest sequence number accepted so far, or 0 if none.
+    pub highest: u64,
+    /// Bit `i` is set if `highest - i` was accepted.
+    pub seen: u64,
+}
+
+/// How an incoming sequence number relates to what was received before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// The next expected message.
+    InOrder,
+    /// A newer message; the numbers in `missing` were skipped and may still arrive.
+    Gap {
+        /// The sequence numbers that have not arrived yet.
+        missing: Range<u64>,
+    },
+    /// An older message that had not been seen yet.
+    OutOfOrder,
+    /// A message that was already received.
+    Replay,
+    /// A message too far behind the window to tell whether it was seen.
+    Stale,
+}
+
+impl Delivery {
+    /// Whether the message must be refused unless the user insists.
+    #[must_use]
+    pub const fn is_suspicious(&self) -> bool {
+        matches!(self, Self::Replay | Self::Stale)
+    }
+}
+
+impl SequenceWindow {
+    /// Classifies `seq` without recording it.
+    #[must_use]
+    pub const fn check(&self, seq: u64) -> Delivery {
+        if seq == 0 {
+            return Delivery::Stale;
+        }
+        if seq > self.highest {
+            return if seq == self.highest + 1 {
+                Delivery::InOrder
+            } else {
+                Delivery::Gap { missing: self.highest + 1..seq }
+            };
+        }
+        let behind = self.highest - seq;
+        if behind >= WINDOW {
+            Delivery::Stale
+        } else if self.seen & (1 << behind) != 0 {
+            Delivery::Replay
+        } else {
+            Delivery::OutOfOrder
+        }
+    }
+
+    /// Records `seq` as received.
+    pub const fn accept(&mut self, seq: u64) {
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.seen = if shift >= WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = seq;
+        } else if self.highest - seq < WINDOW {
+            self.seen |= 1 << (self.highest - seq);
+        }
+    }
+}
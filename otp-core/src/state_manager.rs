@@ -8,22 +8,546 @@
 // This project is licensed under the terms of the GNU AGPLv3 license.
 // See the LICENSE.md file in the project root for full license information.
 
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::sequence::SequenceWindow;
+use crate::thresholds::Thresholds;
+use crate::timestamp;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, btree_map};
 use std::fs;
+use std::iter::FusedIterator;
+use std::ops::Range;
 use std::path::Path;
 
+/// A set of byte offsets, kept as disjoint ranges that never touch.
+///
+/// A range is merged with its neighbours as it is inserted, so the set holds one entry
+/// per contiguous run of used bytes however many messages consumed them, and lookups
+/// take O(log n) in the number of runs. It serializes as a list of `[start, end]` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    /// Range starts mapped to their (exclusive) ends.
+    ranges: BTreeMap<usize, usize>,
+    /// The number of offsets in the set.
+    covered: usize,
+}
+
+impl IntervalSet {
+    /// Creates an empty set.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { ranges: BTreeMap::new(), covered: 0 }
+    }
+
+    /// Adds the offsets of `range`, merging it with the ranges it overlaps or touches.
+    /// Returns how many offsets were not in the set before.
+    pub fn insert(&mut self, range: Range<usize>) -> usize {
+        if range.is_empty() {
+            return 0;
+        }
+        let before = self.covered;
+        let (mut start, mut end) = (range.start, range.end);
+        if let Some((&previous_start, &previous_end)) = self.ranges.range(..=start).next_back()
+            && previous_end >= start
+        {
+            if previous_end >= end {
+                return 0;
+            }
+            start = previous_start;
+        }
+        while let Some((&next_start, &next_end)) = self.ranges.range(start..=end).next() {
+            self.ranges.remove(&next_start);
+            self.covered -= next_end - next_start;
+            end = end.max(next_end);
+        }
+        self.ranges.insert(start, end);
+        self.covered += end - start;
+        self.covered - before
+    }
+
+    /// The number of offsets in the set.
+    #[must_use]
+    pub const fn covered(&self) -> usize {
+        self.covered
+    }
+
+    /// The number of disjoint ranges the set is made of.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Checks whether the set holds no offset.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Checks whether any offset of `range` is in the set. An empty range overlaps a
+    /// range of the set that strictly contains its position.
+    #[must_use]
+    pub fn overlaps(&self, range: &Range<usize>) -> bool {
+        self.ranges.range(..range.end).next_back().is_some_and(|(_, &end)| end > range.start)
+    }
+
+    /// Returns the ranges of the set in ascending order.
+    #[must_use]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Range<usize>> + '_ {
+        self.ranges.iter().map(|(&start, &end)| start..end)
+    }
+
+    /// Returns the runs of offsets in `within` that are not in the set, in ascending
+    /// order. Iterating from either end costs O(log n) to start.
+    #[must_use]
+    pub fn gaps(&self, within: Range<usize>) -> Gaps<'_> {
+        let back = within.end.max(within.start);
+        let front = self
+            .ranges
+            .range(..within.start)
+            .next_back()
+            .map_or(within.start, |(_, &end)| end.clamp(within.start, back));
+        Gaps { ranges: self.ranges.range(within.start..back), front, back }
+    }
+}
+
+impl Extend<Range<usize>> for IntervalSet {
+    fn extend<I: IntoIterator<Item = Range<usize>>>(&mut self, ranges: I) {
+        for range in ranges {
+            self.insert(range);
+        }
+    }
+}
+
+impl FromIterator<Range<usize>> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = Range<usize>>>(ranges: I) -> Self {
+        let mut set = Self::new();
+        set.extend(ranges);
+        set
+    }
+}
+
+impl Serialize for IntervalSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.ranges.iter().map(|(&start, &end)| [start, end]))
+    }
+}
+
+impl<'de> Deserialize<'de> for IntervalSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs = Vec::<[usize; 2]>::deserialize(deserializer)?;
+        if let Some([start, end]) = pairs.iter().find(|[start, end]| start > end) {
+            return Err(D::Error::custom(format!("range {start}..{end} ends before it starts")));
+        }
+        Ok(pairs.into_iter().map(|[start, end]| start..end).collect())
+    }
+}
+
+/// The gaps of an [`IntervalSet`] within a window, from [`IntervalSet::gaps`].
+#[derive(Debug, Clone)]
+pub struct Gaps<'a> {
+    /// The set's ranges starting inside the window that have not been passed yet.
+    ranges: btree_map::Range<'a, usize, usize>,
+    /// Where the next gap from the front may start.
+    front: usize,
+    /// Where the next gap from the back may end.
+    back: usize,
+}
+
+impl Iterator for Gaps<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        while self.front < self.back {
+            let Some((&start, &end)) = self.ranges.next() else {
+                let gap = self.front..self.back;
+                self.front = self.back;
+                return Some(gap);
+            };
+            let gap = self.front..start.min(self.back);
+            self.front = end.clamp(self.front, self.back);
+            if !gap.is_empty() {
+                return Some(gap);
+            }
+        }
+        None
+    }
+}
+
+impl DoubleEndedIterator for Gaps<'_> {
+    fn next_back(&mut self) -> Option<Range<usize>> {
+        while self.front < self.back {
+            let Some((&start, &end)) = self.ranges.next_back() else {
+                let gap = self.front..self.back;
+                self.back = self.front;
+                return Some(gap);
+            };
+            let gap = end.max(self.front)..self.back;
+            self.back = start.clamp(self.front, self.back);
+            if !gap.is_empty() {
+                return Some(gap);
+            }
+        }
+        None
+    }
+}
+
+impl FusedIterator for Gaps<'_> {}
+
 /// Represents a segment of a pad that has been used.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+///
+/// Everything but the byte range is optional, so state files written by older versions,
+/// which only recorded `start` and `end`, still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct UsedSegment {
     /// The starting byte (inclusive) of the used segment.
     pub start: usize,
     /// The ending byte (exclusive) of the used segment.
     pub end: usize,
+    /// Whether this vault encrypted or received the message. `None` for segments of
+    /// unknown provenance, such as those recorded by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// When the segment was consumed, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub used_at: Option<u64>,
+    /// The operation that consumed the segment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<SegmentOperation>,
+    /// The SHA-256 of the ciphertext, which sender and receiver both see.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// The message's sequence number on this pad.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// The contact or vault on the other end of the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    /// Why the bytes were burned by hand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl From<Range<usize>> for UsedSegment {
+    fn from(range: Range<usize>) -> Self {
+        Self { start: range.start, end: range.end, ..Self::default() }
+    }
+}
+
+impl UsedSegment {
+    /// Records `range` as consumed now by `operation`.
+    #[must_use]
+    pub fn consumed(range: Range<usize>, operation: SegmentOperation) -> Self {
+        let origin = match operation {
+            SegmentOperation::Encrypt => Some(Origin::Sent),
+            SegmentOperation::Decrypt => Some(Origin::Received),
+            SegmentOperation::Burn | SegmentOperation::SyncImport => None,
+        };
+        Self { origin, used_at: Some(crate::timestamp::now()), operation: Some(operation), ..range.into() }
+    }
+
+    /// Sets the ciphertext digest.
+    #[must_use]
+    pub fn digest(mut self, digest: impl Into<String>) -> Self {
+        self.digest = Some(digest.into());
+        self
+    }
+
+    /// Sets the message's sequence number.
+    #[must_use]
+    pub const fn seq(mut self, seq: Option<u64>) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// Sets the other end of the message.
+    #[must_use]
+    pub fn counterparty(mut self, counterparty: Option<String>) -> Self {
+        self.counterparty = counterparty;
+        self
+    }
+
+    /// Sets why the bytes were burned.
+    #[must_use]
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+/// Where the message that consumed a segment came from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// This vault encrypted an outgoing message with the bytes.
+    Sent,
+    /// This vault decrypted an incoming message with the bytes.
+    Received,
+}
+
+/// The operation that consumed a segment.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentOperation {
+    /// An outgoing message was encrypted.
+    Encrypt,
+    /// An incoming message was decrypted.
+    Decrypt,
+    /// The bytes were destroyed by hand without carrying a message.
+    Burn,
+    /// A partner's usage receipt reported the bytes as used.
+    SyncImport,
+}
+
+impl std::fmt::Display for SegmentOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Encrypt => "encrypt",
+            Self::Decrypt => "decrypt",
+            Self::Burn => "burn",
+            Self::SyncImport => "sync import",
+        })
+    }
+}
+
+/// How long before its expiry date a pad counts as expiring, in seconds.
+pub const EXPIRY_WARNING: u64 = 7 * 24 * 60 * 60;
+
+/// Where a pad stands in its lifecycle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PadStatus {
+    /// The pad may be used to encrypt.
+    Active,
+    /// The pad may still be used to encrypt, but expires within [`EXPIRY_WARNING`].
+    Expiring,
+    /// The pad was retired or has expired. Old messages still decrypt.
+    Retired,
+    /// A copy of the pad may have been exposed. Old messages still decrypt, but may
+    /// have been read by others.
+    Compromised,
+}
+
+impl PadStatus {
+    /// Whether new messages may be encrypted with a pad in this state.
+    #[must_use]
+    pub const fn can_encrypt(self) -> bool {
+        matches!(self, Self::Active | Self::Expiring)
+    }
+}
+
+impl std::fmt::Display for PadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Active => "active",
+            Self::Expiring => "expiring",
+            Self::Retired => "retired",
+            Self::Compromised => "compromised",
+        })
+    }
+}
+
+/// Why and when a pad was taken out of service before being used up.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Retirement {
+    /// When the pad was retired, in seconds since the Unix epoch.
+    pub at: u64,
+    /// Whether the pad was revoked because a copy may have been exposed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compromised: bool,
+    /// Why the pad was retired.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Which part of a shared pad this vault may consume, so partners never pick the same bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Direction {
+    /// The initiator consumes the pad from the front.
+    Front,
+    /// The responder consumes the pad from the back.
+    Back,
+    /// Only the assigned byte range may be consumed.
+    Range {
+        /// The first byte (inclusive) this side may use.
+        start: usize,
+        /// The last byte (exclusive) this side may use.
+        end: usize,
+    },
+}
+
+impl Direction {
+    /// The policy the partner must follow, if it follows from this one.
+    #[must_use]
+    pub const fn peer(self) -> Option<Self> {
+        match self {
+            Self::Front => Some(Self::Back),
+            Self::Back => Some(Self::Front),
+            Self::Range { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Front => write!(f, "front"),
+            Self::Back => write!(f, "back"),
+            Self::Range { start, end } => write!(f, "{start}..{end}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "front" => Ok(Self::Front),
+            "back" => Ok(Self::Back),
+            _ => s
+                .split_once("..")
+                .and_then(|(a, b)| Some(Self::Range { start: a.parse().ok()?, end: b.parse().ok()? }))
+                .filter(|d| matches!(d, Self::Range { start, end } if start < end))
+                .ok_or_else(|| format!("'{s}' is not a direction; expected 'front', 'back' or 'START..END'")),
+        }
+    }
+}
+
+/// How the bytes of a group pad are divided among its members.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GroupLayout {
+    /// Each member owns one contiguous, equally sized range, in member order.
+    Ranges,
+    /// Members take turns owning consecutive stripes of `width` bytes.
+    Stripes {
+        /// The stripe width in bytes; a single message must fit into one stripe.
+        width: usize,
+    },
+}
+
+/// A pad shared among more than two parties, with its byte space partitioned per member.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// How the pad is partitioned.
+    pub layout: GroupLayout,
+    /// The member names, in the order their partitions are assigned.
+    pub members: Vec<String>,
+}
+
+impl Group {
+    /// Creates a group after checking the member names and layout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are fewer than two members, a name is empty, repeated or
+    /// contains whitespace, `,` or `:`, or the stripe width is zero or so large that a
+    /// round of stripes overflows.
+    pub fn new(layout: GroupLayout, members: Vec<String>) -> Result<Self, String> {
+        if members.len() < 2 {
+            return Err("a group needs at least two members".to_string());
+        }
+        if let Some(name) = members
+            .iter()
+            .find(|name| name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ',' || c == ':'))
+        {
+            return Err(format!("'{name}' is not a valid member name"));
+        }
+        if let Some((index, name)) = members.iter().enumerate().find(|(index, name)| members[..*index].contains(name)) {
+            return Err(format!("member '{name}' is listed twice (position {})", index + 1));
+        }
+        if let GroupLayout::Stripes { width } = layout {
+            if width == 0 {
+                return Err("the stripe width must be at least one byte".to_string());
+            }
+            if width.checked_mul(members.len()).is_none() {
+                return Err(format!("a stripe width of {width} bytes is too large for {} members", members.len()));
+            }
+        }
+        Ok(Self { layout, members })
+    }
+
+    /// Returns the byte ranges of a pad of `pad_size` bytes that belong to `member`.
+    #[must_use]
+    pub fn member_ranges(&self, pad_size: usize, member: &str) -> Vec<Range<usize>> {
+        let Some(index) = self.members.iter().position(|name| name == member) else {
+            return Vec::new();
+        };
+        let count = self.members.len();
+        // `pad_size * index / count` without the product overflowing
+        let boundary = |index: usize| pad_size / count * index + pad_size % count * index / count;
+        match self.layout {
+            GroupLayout::Ranges => std::iter::once(boundary(index)..boundary(index + 1)).collect(),
+            // A state file is not checked by `new`; a stripe past the end of memory is past the pad
+            GroupLayout::Stripes { width: 0 } => Vec::new(),
+            GroupLayout::Stripes { width } => index
+                .checked_mul(width)
+                .map_or(pad_size..pad_size, |start| start..pad_size)
+                .step_by(width.saturating_mul(count))
+                .map(|start| start..start.saturating_add(width).min(pad_size))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.layout {
+            GroupLayout::Ranges => write!(f, "ranges")?,
+            GroupLayout::Stripes { width } => write!(f, "stripes/{width}")?,
+        }
+        write!(f, ":{}", self.members.join(","))
+    }
+}
+
+impl std::str::FromStr for Group {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not a group; expected 'ranges:A,B,..' or 'stripes/WIDTH:A,B,..'");
+        let (layout, members) = s.split_once(':').ok_or_else(invalid)?;
+        let layout = match layout.split_once('/') {
+            None if layout == "ranges" => GroupLayout::Ranges,
+            Some(("stripes", width)) => GroupLayout::Stripes { width: width.parse().map_err(|_| invalid())? },
+            _ => return Err(invalid()),
+        };
+        Self::new(layout, members.split(',').map(str::to_string).collect())
+    }
+}
+
+/// This vault's place in a group pad.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupMembership {
+    /// The group the pad is shared with.
+    pub group: Group,
+    /// The member this vault belongs to; only their partition is consumed locally.
+    pub member: String,
+}
+
+/// How a pad hands out its bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AllocationMode {
+    /// Any unused range may be allocated, and every use is recorded as a segment.
+    #[default]
+    FreeRanges,
+    /// Bytes are handed out front to back above a single watermark. Nothing below it is
+    /// ever allocated again; the history still records each use.
+    Watermark,
+}
+
+impl std::fmt::Display for AllocationMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FreeRanges => write!(f, "free ranges"),
+            Self::Watermark => write!(f, "watermark"),
+        }
+    }
 }
 
 /// Represents the state of a single one-time pad file.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Pad {
     /// A unique identifier for the pad.
     pub id: String,
@@ -31,29 +555,246 @@ pub struct Pad {
     pub file_name: String,
     /// The total size of the pad in bytes.
     pub size: usize,
-    /// A list of segments that have been used.
+    /// Every use of the pad, oldest first.
     pub used_segments: Vec<UsedSegment>,
+    /// Every used byte, merged into disjoint ranges. Allocation only looks at these;
+    /// `used_segments` keeps the history of each message. Record uses with [`Pad::record_use`] to keep both in
+    /// step.
+    #[serde(default, skip_serializing_if = "IntervalSet::is_empty")]
+    pub used: IntervalSet,
+    /// The watermark of a pad in [`AllocationMode::Watermark`]: every byte below it
+    /// counts as used, and it only moves up. `None` in free-range mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
     /// Whether the pad has been fully consumed.
     pub is_fully_used: bool,
+    /// Used segments the partner already knows about, through a sync export or import.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub synced_segments: Vec<UsedSegment>,
+    /// The part of a shared pad this vault may consume. `None` allows the whole pad.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    /// The group sharing this pad. Takes precedence over a `Range` direction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<GroupMembership>,
+    /// The sequence number of the last message this vault encrypted with the pad.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub sent_sequence: u64,
+    /// The receive window of each sender's sequence on this pad, keyed by sender vault ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub received_sequences: BTreeMap<String, SequenceWindow>,
+    /// Why the pad was taken out of service, e.g. after a two-time-pad collision. A
+    /// quarantined pad is never used to encrypt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantined: Option<String>,
+    /// Whether the pad never leaves this vault, e.g. for encrypting your own backups.
+    /// Only local pads are generated automatically when pad material runs low.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub local: bool,
+    /// When the pad stops being used to encrypt, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Set when the pad was retired or revoked by hand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retirement: Option<Retirement>,
+    /// Free-form tags for finding the pad, e.g. a project or a partner's initials.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// A free-text note, e.g. where the partner's copy is kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// When the pad was generated in this vault, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// When the pad was imported into this vault, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported_at: Option<u64>,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
 impl Pad {
+    /// Adds `label` unless the pad already has it. Labels are kept sorted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the label is empty or contains whitespace or a comma.
+    pub fn add_label(&mut self, label: &str) -> Result<(), String> {
+        validate_label(label)?;
+        if let Err(index) = self.labels.binary_search_by(|existing| existing.as_str().cmp(label)) {
+            self.labels.insert(index, label.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes `label`, returning whether the pad had it.
+    pub fn remove_label(&mut self, label: &str) -> bool {
+        let before = self.labels.len();
+        self.labels.retain(|existing| existing != label);
+        self.labels.len() != before
+    }
+
+    /// When the pad entered this vault, by generation or import.
+    #[must_use]
+    pub fn added_at(&self) -> Option<u64> {
+        self.created_at.or(self.imported_at)
+    }
+
+    /// Calculates the number of bytes not used yet.
+    #[must_use]
+    pub const fn remaining_bytes(&self) -> usize {
+        self.size.saturating_sub(self.total_used_bytes())
+    }
+
     /// Calculates the total number of bytes used in the pad.
     #[must_use]
-    pub fn total_used_bytes(&self) -> usize {
-        self.used_segments.iter().map(|s| s.end - s.start).sum()
+    pub const fn total_used_bytes(&self) -> usize {
+        self.watermark() + self.used.covered()
+    }
+
+    /// Returns how the pad hands out its bytes.
+    #[must_use]
+    pub const fn allocation_mode(&self) -> AllocationMode {
+        if self.next_offset.is_some() { AllocationMode::Watermark } else { AllocationMode::FreeRanges }
+    }
+
+    /// The first byte allocation may hand out; 0 in free-range mode.
+    const fn watermark(&self) -> usize {
+        match self.next_offset {
+            Some(watermark) => watermark,
+            None => 0,
+        }
+    }
+
+    /// Records that `segment` was consumed and adds it to the history. In free-range mode
+    /// its bytes join the used bytes; in watermark mode the watermark moves past it.
+    ///
+    /// Returns the free bytes a watermark skipped to get there: a segment that starts above
+    /// the watermark, such as a partner's message or a burned range, gives up the bytes in
+    /// between for good. The range is empty in free-range mode.
+    pub fn record_use(&mut self, segment: UsedSegment) -> Range<usize> {
+        let skipped = match &mut self.next_offset {
+            Some(watermark) if segment.start < segment.end => {
+                let skipped = *watermark..segment.start.max(*watermark);
+                *watermark = (*watermark).max(segment.end);
+                skipped
+            }
+            Some(watermark) => *watermark..*watermark,
+            None => {
+                self.used.insert(segment.start..segment.end);
+                0..0
+            }
+        };
+        self.used_segments.push(segment);
+        skipped
+    }
+
+    /// Rebuilds the used bytes after segments were changed in place: the history is
+    /// merged in again, and used bytes past the end of the pad are dropped. Used bytes
+    /// without a segment stay used, and a watermark never moves down.
+    pub fn reindex(&mut self) {
+        let size = self.size;
+        self.used = self.used.iter().map(|range| range.start.min(size)..range.end.min(size)).collect();
+        self.absorb_history();
+    }
+
+    /// Marks every byte of the history as used. In watermark mode the watermark moves
+    /// past them, and past any used range, so that only the watermark is left.
+    fn absorb_history(&mut self) {
+        let history = self.used_segments.iter().map(|s| s.start..s.end);
+        match &mut self.next_offset {
+            Some(watermark) => {
+                let used = std::mem::take(&mut self.used);
+                *watermark = history.chain(used.iter()).filter(|r| !r.is_empty()).map(|r| r.end).fold(*watermark, usize::max);
+            }
+            None => self.used.extend(history),
+        }
+    }
+
+    /// Switches the pad to `mode`, returning how many free bytes the switch gave up.
+    ///
+    /// Switching to a watermark places it after the last used byte, so the free bytes
+    /// before it are never used. Switching back marks everything below the watermark as
+    /// used, which gives up nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when switching a pad with a direction or group policy to a
+    /// watermark: the partners' messages would push the watermark past this vault's bytes.
+    pub fn convert(&mut self, mode: AllocationMode) -> Result<usize, String> {
+        match (self.allocation_mode(), mode) {
+            (AllocationMode::FreeRanges, AllocationMode::Watermark) => {
+                if self.direction.is_some() || self.group.is_some() {
+                    return Err(format!(
+                        "pad '{}' is shared with a direction or group policy; only pads consumed front to back can use a watermark",
+                        self.id
+                    ));
+                }
+                let watermark = self.used.iter().next_back().map_or(0, |last| last.end);
+                let forfeited = watermark - self.used.covered();
+                self.used = IntervalSet::new();
+                self.next_offset = Some(watermark);
+                Ok(forfeited)
+            }
+            (AllocationMode::Watermark, AllocationMode::FreeRanges) => {
+                let watermark = self.watermark();
+                self.next_offset = None;
+                self.used.insert(0..watermark);
+                Ok(0)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Returns the pad's lifecycle state at `now`, in seconds since the Unix epoch. A
+    /// quarantined pad counts as compromised.
+    #[must_use]
+    pub const fn status_at(&self, now: u64) -> PadStatus {
+        match (&self.retirement, self.expires_at) {
+            _ if self.quarantined.is_some() => PadStatus::Compromised,
+            (Some(retirement), _) if retirement.compromised => PadStatus::Compromised,
+            (Some(_), _) => PadStatus::Retired,
+            (None, Some(expiry)) if expiry <= now => PadStatus::Retired,
+            (None, Some(expiry)) if expiry - now <= EXPIRY_WARNING => PadStatus::Expiring,
+            _ => PadStatus::Active,
+        }
+    }
+
+    /// Returns the pad's lifecycle state now.
+    #[must_use]
+    pub fn status(&self) -> PadStatus {
+        self.status_at(timestamp::now())
+    }
+
+    /// Explains why the pad must not encrypt at `now`, or `None` if it may.
+    #[must_use]
+    pub fn encrypt_refusal(&self, now: u64) -> Option<String> {
+        if let Some(reason) = &self.quarantined {
+            return Some(format!("it is quarantined: {reason}"));
+        }
+        if let Some(retirement) = &self.retirement {
+            let what = if retirement.compromised { "revoked as compromised" } else { "retired" };
+            let when = timestamp::format_utc(retirement.at);
+            let reason = retirement.reason.as_ref().map(|reason| format!(": {reason}")).unwrap_or_default();
+            return Some(format!("it was {what} at {when}{reason}"));
+        }
+        self.expires_at
+            .filter(|expiry| *expiry <= now)
+            .map(|expiry| format!("it expired at {}", timestamp::format_utc(expiry)))
     }
 
     /// Checks if the pad is fully consumed.
     #[must_use]
-    pub fn is_fully_used(&self) -> bool {
+    pub const fn is_fully_used(&self) -> bool {
         self.total_used_bytes() >= self.size
     }
 
     /// Checks if the pad was fully used *before* a new segment of a given length was notionally added.
     /// This is important for finding the correct pad file directory during decryption.
     #[must_use]
-    pub fn is_fully_used_before(&self, new_segment_length: usize) -> bool {
+    pub const fn is_fully_used_before(&self, new_segment_length: usize) -> bool {
         let current_usage = self.total_used_bytes();
         // If the current usage is already conclusive, no need to subtract.
         if current_usage >= self.size {
@@ -69,65 +810,258 @@ impl Pad {
     }
 
 
-    /// Finds the first available contiguous segment of a given length.
+    /// Checks whether the byte range `start..end` lies within the pad and overlaps no used segment.
     #[must_use]
-    pub fn find_available_segment(&self, length: usize) -> Option<usize> {
-        if self.is_fully_used() {
-            return None;
+    pub fn is_range_available(&self, start: usize, end: usize) -> bool {
+        end <= self.size && start >= self.watermark() && !self.used.overlaps(&(start..end))
+    }
+
+    /// Returns the unused byte ranges of the pad in ascending order.
+    #[must_use]
+    pub fn free_ranges(&self) -> Vec<Range<usize>> {
+        self.free_within(0..self.size).collect()
+    }
+
+    /// Returns the unused byte ranges of the pad inside `range`, in ascending order.
+    fn free_within(&self, range: Range<usize>) -> Gaps<'_> {
+        self.used.gaps(range.start.max(self.watermark()).min(self.size)..range.end.min(self.size))
+    }
+
+    /// Returns the parts of `start..end` that no used segment covers yet.
+    #[must_use]
+    pub fn unused_parts(&self, start: usize, end: usize) -> Vec<Range<usize>> {
+        self.free_within(start..end).collect()
+    }
+
+    /// Returns the byte ranges the pad's group or direction policy lets this vault
+    /// consume, in ascending order. A pad that may not encrypt in its current lifecycle
+    /// state allows none.
+    #[must_use]
+    pub fn allowed_ranges(&self) -> Vec<Range<usize>> {
+        if !self.status().can_encrypt() {
+            return Vec::new();
+        }
+        if let Some(membership) = &self.group {
+            return membership.group.member_ranges(self.size, &membership.member);
         }
+        match self.direction {
+            Some(Direction::Range { start, end }) => std::iter::once(start.min(self.size)..end.min(self.size)).collect(),
+            _ => std::iter::once(0..self.size).collect(),
+        }
+    }
 
-        // Sort segments by start byte to iterate through them in order.
-        let mut sorted_segments = self.used_segments.clone();
-        sorted_segments.sort_by_key(|s| s.start);
+    /// Checks whether the policy permits consuming `start..end` as one message.
+    #[must_use]
+    pub fn is_range_allowed(&self, start: usize, end: usize) -> bool {
+        self.allowed_ranges().iter().any(|allowed| allowed.start <= start && end <= allowed.end)
+    }
 
-        // Handle case for an empty or completely available pad
-        if sorted_segments.is_empty() {
-            return if self.size >= length { Some(0) } else { None };
+    /// Returns the unused byte ranges this vault may consume, in ascending order.
+    #[must_use]
+    pub fn allocatable_ranges(&self) -> Vec<Range<usize>> {
+        self.allowed_ranges().into_iter().flat_map(|allowed| self.free_within(allowed)).collect()
+    }
+
+    /// Finds the bytes of an incoming message at `start..end` that already served a
+    /// different message: a two-time pad.
+    ///
+    /// Overlap with any segment this vault sent is a collision, and so is partial overlap
+    /// with another received message. The identical segment of a received message is the
+    /// same message read again. When `from_partner` is false the sender is unknown, so
+    /// the identical segment of a sent message is assumed to be that message itself.
+    /// Segments of unknown provenance are not reported. Used bytes no segment explains,
+    /// such as those older versions dropped from the history, are always reported; bytes
+    /// a watermark gave up are not.
+    #[must_use]
+    pub fn incoming_collisions(&self, start: usize, end: usize, from_partner: bool) -> Vec<Range<usize>> {
+        // Usually the bytes are fresh, which the used bytes tell without walking the history
+        if start >= self.watermark() && !self.used.overlaps(&(start..end)) {
+            return Vec::new();
         }
+        let overlapping: Vec<&UsedSegment> = self.used_segments.iter().filter(|s| s.start < end && start < s.end).collect();
+        let explained: IntervalSet = overlapping.iter().map(|s| s.start..s.end).chain(self.used.gaps(start..end)).collect();
+        let mut collisions: Vec<Range<usize>> = overlapping
+            .iter()
+            .filter(|s| {
+                let identical = s.start == start && s.end == end;
+                match s.origin {
+                    Some(Origin::Sent) => from_partner || !identical,
+                    Some(Origin::Received) => !identical,
+                    None => false,
+                }
+            })
+            .map(|s| s.start.max(start)..s.end.min(end))
+            .chain(explained.gaps(start..end))
+            .collect();
+        collisions.sort_by_key(|range| range.start);
+        collisions
+    }
+
+    /// Returns the largest contiguous byte range this vault may consume, preferring the
+    /// earliest on ties.
+    #[must_use]
+    pub fn largest_free_range(&self) -> Option<Range<usize>> {
+        self.allocatable_ranges()
+            .into_iter()
+            .rev()
+            .max_by_key(ExactSizeIterator::len)
+    }
 
-        // Check for space before the first used segment
-        if sorted_segments[0].start >= length {
-            return Some(0);
+    /// Finds an available contiguous segment of a given length.
+    ///
+    /// Pads used from the back return the highest fitting segment; all others return the
+    /// first one. Only the range allowed by the direction policy is considered, and in
+    /// watermark mode only the bytes from the watermark on. The
+    /// search starts at the first (or last) allowed byte in O(log n) time and only walks
+    /// past gaps too short for `length`.
+    #[must_use]
+    pub fn find_available_segment(&self, length: usize) -> Option<usize> {
+        if self.is_fully_used() {
+            return None;
         }
-        
-        // Now, iterate through the gaps between used segments.
-        let mut last_end = sorted_segments[0].end;
-        for segment in sorted_segments.iter().skip(1) {
-            let gap = segment.start.saturating_sub(last_end);
-            if gap >= length {
-                return Some(last_end); // Found a suitable gap
-            }
-            last_end = segment.end;
+        let allowed = self.allowed_ranges();
+        if self.direction == Some(Direction::Back) {
+            allowed
+                .into_iter()
+                .rev()
+                .find_map(|allowed| self.free_within(allowed).rev().find(|r| r.len() >= length))
+                .map(|r| r.end - length)
+        } else {
+            allowed
+                .into_iter()
+                .find_map(|allowed| self.free_within(allowed).find(|r| r.len() >= length))
+                .map(|r| r.start)
         }
+    }
+}
 
-        // Finally, check for space after the very last segment
-        if self.size.saturating_sub(last_end) >= length {
-            return Some(last_end);
-        }
+/// A partner this vault shares pads with.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Contact {
+    /// The name used to address the contact, e.g. with `encrypt --to`.
+    pub name: String,
+    /// Free-form notes, such as how to reach the contact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// The IDs of the pads shared with this contact.
+    #[serde(default)]
+    pub pad_ids: Vec<String>,
+    /// The direction this vault consumes the shared pads in, applied to pads linked later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+}
 
-        None
+/// Checks that `label` can be used as a pad label.
+///
+/// # Errors
+///
+/// Returns an error if the label is empty or contains whitespace or a comma.
+pub fn validate_label(label: &str) -> Result<(), String> {
+    if label.is_empty() || label.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(format!("invalid label '{label}': labels must be non-empty and contain no spaces or commas"));
     }
+    Ok(())
+}
+
+/// The order of a pad listing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PadSort {
+    /// By pad ID.
+    #[default]
+    Id,
+    /// Most bytes left first.
+    Remaining,
+    /// Largest pad first.
+    Size,
+    /// Oldest first, by when the pad was generated or imported.
+    Added,
+}
+
+/// Which pads to list, and in what order.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PadQuery {
+    /// Only pads carrying this label.
+    pub label: Option<String>,
+    /// Only pads this vault can still encrypt with.
+    pub available_only: bool,
+    /// The order of the listing.
+    pub sort: PadSort,
 }
 
 /// Represents the state of an OTP Vault.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct VaultState {
+    /// A random ID naming this vault as the sender of its messages. Empty until first needed.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub vault_id: String,
     /// A map of pad IDs to their corresponding `Pad` state.
     pub pads: HashMap<String, Pad>,
+    /// A map of contact names to the partners pads are shared with.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub contacts: HashMap<String, Contact>,
+    /// When to warn about running out of pad material, and whether to generate more.
+    #[serde(default, skip_serializing_if = "Thresholds::is_empty")]
+    pub thresholds: Thresholds,
 }
 
 impl VaultState {
-    /// Adds a new pad to the state.
+    /// Adds a newly generated pad to the state.
     pub fn add_pad(&mut self, id: String, file_name: String, size: usize) {
         let pad = Pad {
             id: id.clone(),
             file_name,
             size,
-            used_segments: vec![],
-            is_fully_used: false,
+            created_at: Some(timestamp::now()),
+            ..Default::default()
+        };
+        self.pads.insert(id, pad);
+    }
+
+    /// Adds a pad imported from outside the vault to the state.
+    pub fn import_pad(&mut self, id: String, file_name: String, size: usize) {
+        let pad = Pad {
+            id: id.clone(),
+            file_name,
+            size,
+            imported_at: Some(timestamp::now()),
+            ..Default::default()
         };
         self.pads.insert(id, pad);
     }
+
+    /// Returns the pads matching `query`, in its order.
+    #[must_use]
+    pub fn query(&self, query: &PadQuery) -> Vec<&Pad> {
+        let mut pads: Vec<&Pad> = self
+            .pads
+            .values()
+            .filter(|pad| query.label.as_ref().is_none_or(|label| pad.labels.contains(label)))
+            .filter(|pad| !query.available_only || !pad.allocatable_ranges().is_empty())
+            .collect();
+        pads.sort_by(|a, b| match query.sort {
+            PadSort::Id => a.id.cmp(&b.id),
+            PadSort::Remaining => b.remaining_bytes().cmp(&a.remaining_bytes()).then_with(|| a.id.cmp(&b.id)),
+            PadSort::Size => b.size.cmp(&a.size).then_with(|| a.id.cmp(&b.id)),
+            PadSort::Added => a.added_at().cmp(&b.added_at()).then_with(|| a.id.cmp(&b.id)),
+        });
+        pads
+    }
+
+    /// Returns the vault's sender ID, generating it on first use.
+    pub fn vault_id(&mut self) -> &str {
+        if self.vault_id.is_empty() {
+            self.vault_id = uuid::Uuid::new_v4().to_string();
+        }
+        &self.vault_id
+    }
+
+    /// Returns the contact a pad is shared with, if any.
+    #[must_use]
+    pub fn contact_for_pad(&self, pad_id: &str) -> Option<&Contact> {
+        self.contacts.values().find(|contact| contact.pad_ids.iter().any(|id| id == pad_id))
+    }
 }
 
 /// Loads the state from a specific vault path.
@@ -138,21 +1072,40 @@ impl VaultState {
 pub fn load_state(vault_path: &Path) -> std::io::Result<VaultState> {
     let state_file_path = vault_path.join("vault_state.json");
     if state_file_path.exists() {
-        let state_str = fs::read_to_string(state_file_path)?;
-        serde_json::from_str(&state_str).map_err(std::io::Error::other)
+        parse_state(&fs::read(state_file_path)?)
     } else {
         Ok(VaultState::default())
     }
 }
 
+/// Parses the contents of a state file, such as one kept in a backup.
+///
+/// # Errors
+///
+/// This function will return an error if `data` is not a valid state.
+pub fn parse_state(data: &[u8]) -> std::io::Result<VaultState> {
+    let mut state: VaultState = serde_json::from_slice(data).map_err(std::io::Error::other)?;
+    // Files written before the used bytes were stored, or edited by hand, may record
+    // segments the set misses. Allocating from such a set would reuse pad bytes.
+    for pad in state.pads.values_mut() {
+        pad.absorb_history();
+    }
+    Ok(state)
+}
+
 /// Saves the state to a specific vault path.
 ///
+/// The state is written to a temporary file first and renamed over the old one, so a
+/// reader never sees a half-written state.
+///
 /// # Errors
 ///
 /// This function will return an error if the state file cannot be written to.
 pub fn save_state(vault_path: &Path, state: &VaultState) -> std::io::Result<()> {
     let state_file_path = vault_path.join("vault_state.json");
+    let temp_path = vault_path.join("vault_state.json.tmp");
     let state_str =
         serde_json::to_string_pretty(state).map_err(std::io::Error::other)?;
-    fs::write(state_file_path, state_str)
+    fs::write(&temp_path, state_str)?;
+    fs::rename(temp_path, state_file_path)
 }
\ No newline at end of file
@@ -0,0 +1,279 @@
+// File:    sync.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Authenticated usage receipts that keep two copies of the same pads in agreement.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Two-party usage synchronization.
+//!
+//! Each party records as used only the bytes it consumed itself or decrypted.
+//! A sync bundle carries, per pad, the messages the partner has not been told about
+//! yet, one range per message with the digest of its ciphertext. Every receipt is
+//! authenticated with HMAC-SHA256 under a key derived from the whole pad, so only a
+//! holder of the same pad can produce or alter it, while the pad bytes themselves are
+//! never revealed.
+
+use crate::state_manager::{Origin, Pad, SegmentOperation, UsedSegment};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// The bundle format version written by [`SyncBundle::new`].
+pub const BUNDLE_VERSION: u32 = 1;
+const MAC_DOMAIN: &[u8] = b"otp-sync-mac-v1";
+const HMAC_BLOCK: usize = 64;
+
+/// The used ranges of one pad, authenticated with the pad-derived key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsageReceipt {
+    /// The pad the ranges belong to.
+    pub pad_id: String,
+    /// Used byte ranges sorted by start, one per message where the message is known,
+    /// with the ciphertext digest if it was recorded. Older versions sent merged ranges
+    /// without digests.
+    pub ranges: Vec<UsedSegment>,
+    /// Hex HMAC-SHA256 over the pad ID, ranges and digests.
+    pub mac: String,
+}
+
+impl UsageReceipt {
+    /// Creates a receipt for `segments`, authenticated with `key` from [`mac_key`].
+    ///
+    /// Only the range and digest of each segment are sent. Segments are sorted, and empty
+    /// or repeated ones dropped, but adjacent and overlapping ones stay apart.
+    #[must_use]
+    pub fn new(pad_id: &str, segments: &[UsedSegment], key: &[u8; 32]) -> Self {
+        let mut ranges: Vec<UsedSegment> = segments
+            .iter()
+            .filter(|s| s.start < s.end)
+            .map(|s| UsedSegment { digest: s.digest.clone(), ..UsedSegment::from(s.start..s.end) })
+            .collect();
+        ranges.sort_by(|a, b| (a.start, a.end, &a.digest).cmp(&(b.start, b.end, &b.digest)));
+        ranges.dedup_by(|a, b| (a.start, a.end, &a.digest) == (b.start, b.end, &b.digest));
+        let mac = data_encoding::HEXLOWER.encode(&hmac_sha256(key, &receipt_message(pad_id, &ranges)));
+        Self { pad_id: pad_id.to_string(), ranges, mac }
+    }
+
+    /// Checks the receipt against `key` from [`mac_key`].
+    #[must_use]
+    pub fn verify(&self, key: &[u8; 32]) -> bool {
+        let expected = hmac_sha256(key, &receipt_message(&self.pad_id, &self.ranges));
+        data_encoding::HEXLOWER_PERMISSIVE
+            .decode(self.mac.as_bytes())
+            .is_ok_and(|mac| mac.len() == expected.len() && mac.iter().zip(expected).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0)
+    }
+
+    /// The number of distinct bytes the receipt's ranges cover.
+    #[must_use]
+    pub fn used_bytes(&self) -> usize {
+        normalize(self.byte_ranges()).iter().map(ExactSizeIterator::len).sum()
+    }
+
+    /// The receipt's ranges as byte ranges.
+    #[must_use]
+    pub fn byte_ranges(&self) -> Vec<Range<usize>> {
+        self.ranges.iter().map(|s| s.start..s.end).collect()
+    }
+}
+
+/// A set of usage receipts exchanged between partners.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncBundle {
+    /// The bundle format version.
+    pub version: u32,
+    /// When the bundle was exported, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// One receipt per pad with news for the partner.
+    pub receipts: Vec<UsageReceipt>,
+}
+
+impl SyncBundle {
+    /// Creates a bundle stamped with the current time.
+    #[must_use]
+    pub fn new(receipts: Vec<UsageReceipt>) -> Self {
+        Self { version: BUNDLE_VERSION, created_at: crate::timestamp::now(), receipts }
+    }
+}
+
+/// The result of merging a partner's receipt into a pad.
+#[derive(Debug, Default)]
+pub struct MergeOutcome {
+    /// Ranges that were newly marked as used.
+    pub added: Vec<Range<usize>>,
+    /// Ranges both parties consumed for different messages: a two-time pad.
+    pub conflicts: Vec<Range<usize>>,
+    /// Free bytes a watermark skipped to record the added ranges, which can no longer be
+    /// used.
+    pub skipped: Vec<Range<usize>>,
+}
+
+/// Derives a pad's receipt MAC key from its full contents.
+#[must_use]
+pub fn mac_key(pad_bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(MAC_DOMAIN);
+    hasher.update(pad_bytes);
+    hasher.finalize().into()
+}
+
+/// The used ranges of `pad`, including the bytes below its watermark.
+#[must_use]
+pub fn used_ranges(pad: &Pad) -> Vec<Range<usize>> {
+    normalize(pad.used.iter().chain(pad.next_offset.map(|watermark| 0..watermark)))
+}
+
+/// The used ranges of `pad` the partner has not been told about.
+#[must_use]
+pub fn pending_ranges(pad: &Pad) -> Vec<Range<usize>> {
+    subtract(&used_ranges(pad), &normalize(segments(&pad.synced_segments)))
+}
+
+/// The used bytes of `pad` as receipt segments: one per message in the history, plus the
+/// used bytes no recorded message explains.
+#[must_use]
+pub fn used_segments(pad: &Pad) -> Vec<UsedSegment> {
+    message_segments(pad, &[])
+}
+
+/// The receipt segments of `pad` the partner has not been told about, see
+/// [`used_segments`].
+#[must_use]
+pub fn pending_segments(pad: &Pad) -> Vec<UsedSegment> {
+    message_segments(pad, &normalize(segments(&pad.synced_segments)))
+}
+
+/// The history segments with bytes outside normalized `synced`, and the unexplained used
+/// bytes outside it.
+fn message_segments(pad: &Pad, synced: &[Range<usize>]) -> Vec<UsedSegment> {
+    let messages = pad.used_segments.iter().filter(|s| s.start < s.end && !subtract(std::slice::from_ref(&(s.start..s.end)), synced).is_empty());
+    let unexplained = subtract(&subtract(&used_ranges(pad), &normalize(segments(&pad.used_segments))), synced);
+    messages.cloned().chain(unexplained.into_iter().map(UsedSegment::from)).collect()
+}
+
+/// Records that the partner now knows about `ranges`.
+pub fn mark_synced(pad: &mut Pad, ranges: &[Range<usize>]) {
+    pad.synced_segments = normalize(segments(&pad.synced_segments).chain(ranges.iter().cloned()))
+        .into_iter()
+        .map(UsedSegment::from)
+        .collect();
+}
+
+/// Merges a partner's used segments into `pad`.
+///
+/// Bytes not yet used locally are recorded as used. A partner segment that overlaps
+/// local bytes the partner did not know about is a conflict, with two exceptions. A
+/// local segment with the same range, and the same digest where both have one, is the
+/// same message recorded once by its sender and once by its receiver. A message this
+/// vault received that lies strictly inside the partner's segment was sent by the
+/// partner, which older receipts merged with its neighbours.
+///
+/// # Errors
+///
+/// Returns an error if a range lies outside the pad.
+pub fn merge(pad: &mut Pad, incoming: &[UsedSegment]) -> Result<MergeOutcome, String> {
+    let size = pad.size;
+    if let Some(segment) = incoming.iter().find(|s| s.start > s.end || s.end > size) {
+        return Err(format!("range {}..{} lies outside pad '{}'", segment.start, segment.end, pad.id));
+    }
+    // Below a watermark only the history tells which bytes this vault used, so bytes the
+    // watermark gave up never count as conflicts.
+    let synced = normalize(segments(&pad.synced_segments));
+    let local_only = subtract(&normalize(pad.used.iter().chain(segments(&pad.used_segments))), &synced);
+    let mut conflicts = Vec::new();
+    for segment in incoming {
+        let range = segment.start..segment.end;
+        let same_message = |local: &UsedSegment| {
+            local.start == segment.start && local.end == segment.end && (local.digest.is_none() || segment.digest.is_none() || local.digest == segment.digest)
+        };
+        if intersect(&local_only, &normalize([range.clone()])).is_empty() || pad.used_segments.iter().any(same_message) {
+            continue;
+        }
+        let received_inside = |local: &UsedSegment| {
+            local.origin == Some(Origin::Received) && range.start <= local.start && local.end <= range.end && local.end - local.start < range.len()
+        };
+        let others = normalize(pad.used_segments.iter().filter(|local| !received_inside(local)).map(|local| local.start..local.end));
+        let unexplained = subtract(&normalize(pad.used.iter()), &normalize(segments(&pad.used_segments)));
+        let local = subtract(&normalize(others.into_iter().chain(unexplained)), &synced);
+        conflicts.extend(intersect(&local, &normalize([range])));
+    }
+
+    let mut added = Vec::new();
+    let mut skipped = Vec::new();
+    for segment in incoming {
+        for part in pad.unused_parts(segment.start, segment.end) {
+            let digest = (part == (segment.start..segment.end)).then(|| segment.digest.clone()).flatten();
+            skipped.push(pad.record_use(UsedSegment { digest, ..UsedSegment::consumed(part.clone(), SegmentOperation::SyncImport) }));
+            added.push(part);
+        }
+    }
+    pad.is_fully_used = pad.total_used_bytes() >= pad.size;
+    mark_synced(pad, &incoming.iter().map(|s| s.start..s.end).collect::<Vec<_>>());
+    Ok(MergeOutcome { added: normalize(added), conflicts: normalize(conflicts), skipped: normalize(skipped) })
+}
+
+fn segments(list: &[UsedSegment]) -> impl Iterator<Item = Range<usize>> + '_ {
+    list.iter().map(|s| s.start..s.end)
+}
+
+fn receipt_message(pad_id: &str, ranges: &[UsedSegment]) -> Vec<u8> {
+    let mut message = format!("{}\n{pad_id}\n", String::from_utf8_lossy(MAC_DOMAIN));
+    message.extend(ranges.iter().map(|r| {
+        let digest = r.digest.as_ref().map_or_else(String::new, |digest| format!(" {digest}"));
+        format!("{}..{}{digest}\n", r.start, r.end)
+    }));
+    message.into_bytes()
+}
+
+/// HMAC-SHA256 as specified in RFC 2104.
+fn hmac_sha256(key: &[u8; 32], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; HMAC_BLOCK];
+    block[..key.len()].copy_from_slice(key);
+    let pad_key = |fill: u8| block.map(|b| b ^ fill);
+    let inner = Sha256::new().chain_update(pad_key(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad_key(0x5c)).chain_update(inner).finalize().into()
+}
+
+/// Sorts ranges and merges overlapping or adjacent ones, dropping empty ranges.
+fn normalize(ranges: impl IntoIterator<Item = Range<usize>>) -> Vec<Range<usize>> {
+    let mut sorted: Vec<Range<usize>> = ranges.into_iter().filter(|r| r.start < r.end).collect();
+    sorted.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// The parts of normalized `a` not covered by normalized `b`.
+fn subtract(a: &[Range<usize>], b: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    for range in a {
+        let mut cursor = range.start;
+        for cut in b.iter().filter(|c| c.end > range.start && c.start < range.end) {
+            if cut.start > cursor {
+                result.push(cursor..cut.start);
+            }
+            cursor = cursor.max(cut.end);
+        }
+        if cursor < range.end {
+            result.push(cursor..range.end);
+        }
+    }
+    result
+}
+
+/// The overlap of normalized `a` and normalized `b`.
+fn intersect(a: &[Range<usize>], b: &[Range<usize>]) -> Vec<Range<usize>> {
+    a.iter()
+        .flat_map(|x| b.iter().map(move |y| x.start.max(y.start)..x.end.min(y.end)))
+        .filter(|r| r.start < r.end)
+        .collect()
+}
+
@@ -0,0 +1,200 @@
+// File:    thresholds.rs
+// Author:  apezoo
+// Date:    2025-07-17
+//
+// Description: Warns before a vault runs out of pad material and replenishes local pads.
+//
+// License:
+// This project is licensed under the terms of the GNU AGPLv3 license.
+// See the LICENSE.md file in the project root for full license information.
+
+//! Low pad material warnings.
+//!
+//! A vault may set two thresholds: the bytes left to encrypt with across all usable pads,
+//! and the largest single message that still fits into one pad. [`after_operation`] is
+//! run after every operation that changes a vault. If the vault opted in, it first tops
+//! up the vault's local pads, which never leave the vault and can therefore be replaced
+//! without a key exchange, and then reports the thresholds that are still crossed.
+
+use crate::audit::{self, Interface, Operation, Record};
+use crate::pad_generator;
+use crate::state_manager::{self, Pad, VaultState};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The most pads one replenishment generates, so a bad setting cannot fill the disk.
+pub const MAX_AUTO_PADS: usize = 16;
+
+/// A vault's low-material thresholds and replenishment policy.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Thresholds {
+    /// Warn when fewer bytes than this are left across all usable pads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_remaining: Option<usize>,
+    /// Warn when the largest contiguous free range of any pad is smaller than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_largest_gap: Option<usize>,
+    /// Generate local pads of this many bytes while the local pads cross a threshold.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_generate: Option<usize>,
+}
+
+impl Thresholds {
+    /// Whether nothing is configured.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.min_remaining.is_none() && self.min_largest_gap.is_none() && self.auto_generate.is_none()
+    }
+
+    /// Checks `material` against the thresholds.
+    #[must_use]
+    pub fn check(&self, material: Material) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        if let Some(threshold) = self.min_remaining.filter(|t| material.remaining < *t) {
+            warnings.push(Warning::Remaining { remaining: material.remaining, threshold });
+        }
+        if let Some(threshold) = self.min_largest_gap.filter(|t| material.largest_gap < *t) {
+            warnings.push(Warning::LargestGap { largest_gap: material.largest_gap, threshold });
+        }
+        warnings
+    }
+}
+
+/// How much pad material is left to encrypt with.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Material {
+    /// The free bytes this vault may consume, summed over all usable pads.
+    pub remaining: usize,
+    /// The longest contiguous range of free bytes in a single pad.
+    pub largest_gap: usize,
+}
+
+impl Material {
+    /// Measures the usable pads of `state` for which `include` holds. Quarantined pads
+    /// and bytes reserved for partners by a direction or group policy do not count.
+    #[must_use]
+    pub fn of(state: &VaultState, include: impl Fn(&Pad) -> bool) -> Self {
+        state.pads.values().filter(|pad| include(pad)).fold(Self::default(), |material, pad| {
+            let ranges = pad.allocatable_ranges();
+            Self {
+                remaining: material.remaining + ranges.iter().map(ExactSizeIterator::len).sum::<usize>(),
+                largest_gap: material.largest_gap.max(ranges.iter().map(ExactSizeIterator::len).max().unwrap_or(0)),
+            }
+        })
+    }
+}
+
+/// A crossed threshold.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    /// Too few bytes are left overall.
+    Remaining {
+        /// The bytes left.
+        remaining: usize,
+        /// The configured minimum.
+        threshold: usize,
+    },
+    /// No pad has a long enough free range left.
+    LargestGap {
+        /// The longest free range.
+        largest_gap: usize,
+        /// The configured minimum.
+        threshold: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remaining { remaining, threshold } => write!(
+                f,
+                "only {} of pad material left (warning below {}); generate and exchange new pads soon",
+                format_size(*remaining),
+                format_size(*threshold)
+            ),
+            Self::LargestGap { largest_gap, threshold } => write!(
+                f,
+                "the largest message that still fits is {} (warning below {})",
+                format_size(*largest_gap),
+                format_size(*threshold)
+            ),
+        }
+    }
+}
+
+/// What [`after_operation`] did and found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The IDs of the local pads generated.
+    pub generated: Vec<String>,
+    /// The thresholds still crossed afterwards.
+    pub warnings: Vec<Warning>,
+}
+
+/// Replenishes local pads if the vault's policy asks for it, then checks all thresholds.
+/// Generated pads are saved to the state and recorded in the audit log under `interface`.
+///
+/// # Errors
+///
+/// Returns an error if a pad cannot be generated or the state cannot be saved.
+pub fn after_operation(vault_path: &Path, state: &mut VaultState, interface: Interface) -> io::Result<Report> {
+    let thresholds = state.thresholds.clone();
+    let mut generated = Vec::new();
+    if let Some(pad_size) = thresholds.auto_generate.filter(|size| *size > 0) {
+        while generated.len() < MAX_AUTO_PADS && !thresholds.check(Material::of(state, |pad| pad.local)).is_empty() {
+            let pad_id = uuid::Uuid::new_v4().to_string();
+            let file_name = format!("{pad_id}.pad");
+            let pad_path = vault_path.join("pads/available").join(&file_name);
+            pad_generator::generate_pad(&pad_path.to_string_lossy(), pad_size)?;
+            state.add_pad(pad_id.clone(), file_name, pad_size);
+            if let Some(pad) = state.pads.get_mut(&pad_id) {
+                pad.local = true;
+            }
+            audit::append(vault_path, Record::new(interface, Operation::Generate).pad(&pad_id).range(0..pad_size).detail("auto-generated"))?;
+            generated.push(pad_id);
+        }
+        if !generated.is_empty() {
+            state_manager::save_state(vault_path, state)?;
+        }
+    }
+    Ok(Report { generated, warnings: thresholds.check(Material::of(state, |_| true)) })
+}
+
+/// Parses a size such as `10MB`, `512 KB`, `1GB` or a plain number of bytes. Units are
+/// binary: 1 KB is 1024 bytes.
+///
+/// # Errors
+///
+/// Returns an error for an unknown unit or a number that does not fit.
+pub fn parse_size(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: usize = number.parse().map_err(|_| format!("invalid size '{text}'"))?;
+    let factor: usize = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("unknown unit in '{text}'; use B, KB, MB or GB")),
+    };
+    number.checked_mul(factor).ok_or_else(|| format!("size '{text}' is too large"))
+}
+
+/// Formats a byte count with the largest binary unit that keeps it readable.
+#[must_use]
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [(&str, u128); 3] = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10)];
+    let bytes_wide = bytes as u128;
+    UNITS.iter().find(|(_, factor)| bytes_wide >= *factor).map_or_else(
+        || format!("{bytes} bytes"),
+        |(unit, factor)| {
+            // Hundredths of the unit, rounded half up
+            let hundredths = (bytes_wide * 100 + factor / 2) / factor;
+            format!("{}.{:02} {unit}", hundredths / 100, hundredths % 100)
+        },
+    )
+}
@@ -101,11 +101,13 @@ fn test_pad_is_moved_when_fully_consumed() {
     let large_input_path = temp_dir.path().join("large_input.txt");
     fs::write(&large_input_path, &large_input_content).expect("Failed to write large input file");
     
-    // 2. Encrypt the large file
+    // 2. Encrypt the large file. Two halves fill the pad only without inner headers: the
+    // first is sent without one on request, the second because no room is left for it.
     Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
         .arg("--vault").arg(&vault_path)
         .arg("encrypt").arg(&large_input_path)
         .arg("--pad-id").arg(&pad_id)
+        .arg("--no-header")
         .assert().success();
     
     // The pad should still be available
@@ -0,0 +1,123 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::io::Read;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::archive;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+/// Builds a raw archive holding a single file record with the given path and mode.
+fn single_file_archive(path: &str, mode: u32) -> Vec<u8> {
+    let mut data = archive::MAGIC.to_vec();
+    data.push(1);
+    data.extend(u16::try_from(path.len()).unwrap().to_be_bytes());
+    data.extend(path.as_bytes());
+    data.extend(mode.to_be_bytes());
+    data.extend(0u64.to_be_bytes());
+    data.extend(2u64.to_be_bytes());
+    data.extend(b"hi");
+    data.push(0xff);
+    data
+}
+
+#[test]
+fn test_archive_roundtrip_and_path_traversal() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let source_dir = temp_dir.path().join("tree");
+    fs::create_dir_all(source_dir.join("nested/deeper")).expect("Failed to create tree");
+    fs::write(source_dir.join("top.txt"), "top").expect("Failed to write file");
+    fs::write(source_dir.join("nested/deeper/leaf.bin"), [0u8, 1, 2, 255]).expect("Failed to write file");
+
+    let (source, skipped) = archive::Source::scan(&source_dir).expect("Scan should succeed");
+    assert!(skipped.is_empty());
+    let mut data = Vec::new();
+    source.reader().read_to_end(&mut data).expect("Archive should stream");
+    assert_eq!(data.len() as u64, source.archive_len());
+
+    let restored = temp_dir.path().join("restored");
+    let entries = archive::extract(&data, &restored).expect("Extraction should succeed");
+    assert_eq!(entries.len(), 4);
+    assert_eq!(fs::read(restored.join("nested/deeper/leaf.bin")).unwrap(), [0u8, 1, 2, 255]);
+    assert!(archive::extract(&data, &restored).is_err(), "Existing files must not be overwritten");
+
+    for evil in ["../escape.txt", "/etc/escape.txt", "a/../../escape.txt"] {
+        let target = temp_dir.path().join("evil");
+        assert!(archive::extract(&single_file_archive(evil, 0o644), &target).is_err(), "{evil} should be refused");
+        assert!(!target.exists(), "Nothing may be written for {evil}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_extraction_drops_special_permission_bits() {
+    use std::os::unix::fs::PermissionsExt;
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let target = temp_dir.path().join("extracted");
+    archive::extract(&single_file_archive("tool", 0o6755), &target).expect("Extraction should succeed");
+    let mode = fs::metadata(target.join("tool")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o755);
+
+    // Nor are they captured when archiving
+    fs::set_permissions(target.join("tool"), fs::Permissions::from_mode(0o4755)).unwrap();
+    let (source, _) = archive::Source::scan(&target).expect("Scan should succeed");
+    let mut data = Vec::new();
+    source.reader().read_to_end(&mut data).expect("Archive should stream");
+    let entries = archive::extract(&data, &temp_dir.path().join("again")).expect("Extraction should succeed");
+    assert!(entries.iter().all(|entry| entry.mode & !0o777 == 0));
+}
+
+#[test]
+fn test_encrypt_directory_recursively() {
+    // 1. Setup
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault_path = temp_dir.path().join("my_test_vault");
+    let source_dir = temp_dir.path().join("project");
+    fs::create_dir_all(source_dir.join("src")).expect("Failed to create tree");
+    fs::write(source_dir.join("README"), "read me").expect("Failed to write file");
+    fs::write(source_dir.join("src/main.rs"), "fn main() {}").expect("Failed to write file");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("vault").arg("init")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("pad").arg("generate")
+        .assert().success();
+
+    // 2. A directory needs --recursive
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg(&source_dir)
+        .assert().failure();
+
+    let encrypted_path = temp_dir.path().join("project.enc");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg(&source_dir).arg("--recursive")
+        .assert().success();
+    assert!(encrypted_path.exists());
+    let metadata_path = temp_dir.path().join("project.enc.metadata.json");
+
+    // 3. List without extracting
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg(&encrypted_path)
+        .arg("--metadata").arg(&metadata_path)
+        .arg("--list")
+        .assert().success()
+        .stdout(predicate::str::contains("src/main.rs").and(predicate::str::contains("README")));
+
+    // 4. Restore the tree
+    let restored = temp_dir.path().join("restored");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg(&encrypted_path)
+        .arg("--output").arg(&restored)
+        .arg("--metadata").arg(&metadata_path)
+        .assert().success();
+    assert_eq!(fs::read_to_string(restored.join("src/main.rs")).unwrap(), "fn main() {}");
+    assert_eq!(fs::read_to_string(restored.join("README")).unwrap(), "read me");
+}
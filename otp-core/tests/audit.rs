@@ -0,0 +1,87 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::audit::{self, Filter, Interface, Operation, Record};
+use otp_core::timestamp;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_log_is_hash_chained_and_detects_tampering() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path();
+    audit::append(vault, Record::new(Interface::Cli, Operation::Generate).pad("pad-1").range(0..100)).expect("Failed to append");
+    audit::append(vault, Record::new(Interface::Web, Operation::Encrypt).pad("pad-1").range(0..10).file(Path::new("/tmp/out/msg.enc")))
+        .expect("Failed to append");
+    audit::append(vault, Record::new(Interface::Cli, Operation::Delete).pad("pad-2")).expect("Failed to append");
+
+    let entries = audit::read(vault).expect("Failed to read log");
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].prev, audit::GENESIS);
+    assert_eq!(entries[1].prev, entries[0].hash);
+    assert_eq!(entries[1].record.file.as_deref(), Some("msg.enc"));
+    assert_eq!(audit::verify(&entries), Ok(()));
+
+    let filter = Filter { pad_id: Some("pad-1".to_string()), op: Some(Operation::Encrypt), ..Filter::default() };
+    assert_eq!(entries.iter().filter(|entry| filter.matches(entry)).count(), 1);
+
+    // Editing a recorded range breaks the chain at that entry
+    let log_path = vault.join(audit::LOG_FILE);
+    let text = fs::read_to_string(&log_path).expect("Failed to read log file");
+    fs::write(&log_path, text.replacen("\"end\":10}", "\"end\":20}", 1)).expect("Failed to write log file");
+    assert_eq!(audit::verify(&audit::read(vault).expect("Failed to read log")), Err(1));
+
+    // So does removing an entry
+    let mut lines: Vec<_> = text.lines().collect();
+    lines.remove(1);
+    fs::write(&log_path, lines.join("\n")).expect("Failed to write log file");
+    assert_eq!(audit::verify(&audit::read(vault).expect("Failed to read log")), Err(1));
+}
+
+#[test]
+fn test_parse_date() {
+    assert_eq!(timestamp::parse_date("1970-01-02"), Some(86_400));
+    assert_eq!(timestamp::parse_date("2024-02-29").map(timestamp::format_utc).as_deref(), Some("2024-02-29T00:00:00Z"));
+    assert_eq!(timestamp::parse_date("2023-02-29"), None);
+    assert_eq!(timestamp::parse_date("yesterday"), None);
+    assert_eq!(timestamp::parse_date("9999-12-31").map(timestamp::format_utc).as_deref(), Some("9999-12-31T00:00:00Z"));
+    assert_eq!(timestamp::parse_date("10000-01-01"), None);
+    assert_eq!(timestamp::parse_date("999999999999999-01-01"), None);
+}
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_vault_log_records_cli_operations() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let message = temp_dir.path().join("secret.asc");
+    otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("-o").arg(&message).arg("--encoding").arg("armor").assert().success();
+    otp(&vault).arg("decrypt").arg("-i").arg(&message).arg("-o").arg(temp_dir.path().join("plain.txt")).assert().success();
+
+    otp(&vault)
+        .arg("vault").arg("log").arg("--op").arg("encrypt")
+        .assert().success()
+        .stdout(predicate::str::contains(format!("pad {pad_id} bytes 0..")).and(predicate::str::contains("file 'secret.asc'")))
+        .stdout(predicate::str::contains("decrypt").not());
+    otp(&vault)
+        .arg("vault").arg("log").arg("--pad").arg(&pad_id)
+        .assert().success()
+        .stdout(predicate::str::contains("generate").and(predicate::str::contains("decrypt")));
+    otp(&vault).arg("vault").arg("log").arg("--until").arg("2000-01-01").assert().success().stdout(predicate::str::is_empty());
+    otp(&vault).arg("vault").arg("log").arg("--since").arg("not-a-date").assert().failure();
+
+    let log_path = vault.join(audit::LOG_FILE);
+    let text = fs::read_to_string(&log_path).expect("Failed to read log file");
+    fs::write(&log_path, text.replacen("\"cli\"", "\"web\"", 1)).expect("Failed to write log file");
+    otp(&vault).arg("vault").arg("log").assert().failure().stderr(predicate::str::contains("hash chain is broken at entry 1"));
+}
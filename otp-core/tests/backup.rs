@@ -0,0 +1,100 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::{audit, backup, state_manager};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+fn encrypt(vault: &Path, text: &str, out: &Path) {
+    otp(vault).arg("encrypt").arg("--text").arg(text).arg("-o").arg(out).arg("--encoding").arg("armor").assert().success();
+}
+
+#[test]
+fn test_backup_and_restore_refuse_stale_usage() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    encrypt(&vault, "first", &temp_dir.path().join("first.asc"));
+
+    let snapshot = temp_dir.path().join("snapshot");
+    otp(&vault).arg("vault").arg("backup").arg("--out").arg(&snapshot).assert().success();
+    let manifest = backup::verify(&snapshot).expect("Snapshot verifies");
+    let paths: Vec<&str> = manifest.files.iter().map(|file| file.path.as_str()).collect();
+    assert!(paths.contains(&"vault_state.json") && paths.contains(&audit::LOG_FILE));
+    assert!(paths.contains(&format!("pads/available/{pad_id}.pad").as_str()));
+    otp(&vault).arg("vault").arg("backup").arg("--out").arg(&snapshot).assert().failure().stderr(predicate::str::contains("not empty"));
+
+    // Usage recorded after the backup must not be rolled back silently
+    encrypt(&vault, "second", &temp_dir.path().join("second.asc"));
+    let used = state_manager::load_state(&vault).expect("Failed to load state").pads[&pad_id].total_used_bytes();
+    otp(&vault)
+        .arg("vault").arg("restore").arg(&snapshot)
+        .assert().failure()
+        .stderr(predicate::str::contains("pad reuse").and(predicate::str::contains(&pad_id)));
+    assert_eq!(state_manager::load_state(&vault).expect("Failed to load state").pads[&pad_id].total_used_bytes(), used);
+    otp(&vault).arg("vault").arg("restore").arg(&snapshot).arg("--force").assert().success().stderr(predicate::str::contains("WARNING"));
+    assert!(state_manager::load_state(&vault).expect("Failed to load state").pads[&pad_id].total_used_bytes() < used);
+    // The log written since the backup survives the restore
+    let log = audit::read(&vault).expect("Failed to read log");
+    assert_eq!(audit::verify(&log), Ok(()));
+    assert_eq!(log.iter().filter(|entry| entry.record.op == audit::Operation::Encrypt).count(), 2);
+
+    // A fresh vault can be restored into, but not from a damaged snapshot
+    let fresh = temp_dir.path().join("fresh");
+    otp(&fresh).arg("vault").arg("restore").arg(&snapshot).assert().success();
+    otp(&fresh).arg("vault").arg("fsck").assert().success();
+    fs::write(snapshot.join("pads/available").join(format!("{pad_id}.pad")), b"tampered").expect("Failed to tamper");
+    otp(&temp_dir.path().join("other")).arg("vault").arg("restore").arg(&snapshot).assert().failure().stderr(predicate::str::contains("checksum"));
+}
+
+#[test]
+fn test_restore_keeps_pads_added_since_the_backup() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    otp(&vault).arg("pad").arg("generate").assert().success();
+    let snapshot = temp_dir.path().join("snapshot");
+    otp(&vault).arg("vault").arg("backup").arg("--out").arg(&snapshot).assert().success();
+
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let added = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let added_file = vault.join("pads/available").join(format!("{added}.pad"));
+    otp(&vault)
+        .arg("vault").arg("restore").arg(&snapshot)
+        .assert().failure()
+        .stderr(predicate::str::contains("delete").and(predicate::str::contains(&added)));
+    assert!(added_file.is_file());
+    let saved = backup::snapshot_state(&snapshot).expect("Failed to read snapshot state");
+    let current = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(backup::added_pads(&saved, &current), vec![added.clone()]);
+    // An unused new pad loses no usage record; a used one does
+    assert!(backup::stale_pads(&saved, &current).is_empty());
+    encrypt(&vault, "hello", &temp_dir.path().join("hello.asc"));
+    let current = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(backup::stale_pads(&saved, &current).len(), 1);
+
+    // A copy that fails partway leaves the vault's pads where they were
+    let pad_file = snapshot.join("pads/available").join(fs::read_dir(snapshot.join("pads/available")).unwrap().next().unwrap().unwrap().file_name());
+    let manifest = backup::verify(&snapshot).expect("Snapshot verifies");
+    fs::rename(&pad_file, temp_dir.path().join("moved.pad")).expect("Failed to move pad file");
+    assert!(backup::restore(&snapshot, &manifest, &vault).is_err());
+    assert!(added_file.is_file() || vault.join("pads/used").join(format!("{added}.pad")).is_file());
+    assert!(!vault.join("pads.restore").exists());
+    assert_eq!(state_manager::load_state(&vault).expect("Failed to load state").pads.len(), 2);
+    fs::rename(temp_dir.path().join("moved.pad"), &pad_file).expect("Failed to move pad file back");
+
+    otp(&vault).arg("vault").arg("restore").arg(&snapshot).arg("--force").assert().success().stderr(predicate::str::contains("WARNING"));
+    assert_eq!(state_manager::load_state(&vault).expect("Failed to load state").pads.len(), 1);
+    assert!(!vault.join("pads.old").exists());
+    otp(&vault).arg("vault").arg("fsck").assert().success();
+}
@@ -0,0 +1,62 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, SegmentOperation};
+use otp_core::audit;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+fn burn(vault: &Path, pad_id: &str, range: &str) -> Command {
+    let mut command = otp(vault);
+    command.arg("pad").arg("burn").arg(pad_id).arg("--range").arg(range).arg("--reason").arg("handwritten note");
+    command
+}
+
+#[test]
+fn test_burn_records_only_unused_bytes() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+
+    burn(&vault, &pad_id, "100..200").assert().success().stdout(predicate::str::contains("Burned 100 byte(s)"));
+    // Overlapping the burned range only adds the new bytes
+    burn(&vault, &pad_id, "150..250").assert().success().stdout(predicate::str::contains("Burned 50 byte(s)"));
+    burn(&vault, &pad_id, "120..180").assert().failure().stderr(predicate::str::contains("already used"));
+    burn(&vault, &pad_id, "0..2000000").assert().failure().stderr(predicate::str::contains("outside pad"));
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("0..10").assert().failure().stderr(predicate::str::contains("--reason"));
+
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    let pad = &state.pads[&pad_id];
+    assert_eq!(pad.total_used_bytes(), 150);
+    assert!(pad.used_segments.iter().all(|s| s.operation == Some(SegmentOperation::Burn) && s.reason.as_deref() == Some("handwritten note")));
+    let log = audit::read(&vault).expect("Failed to read log");
+    let burns: Vec<_> = log.iter().filter(|entry| entry.record.op == audit::Operation::Burn).map(|entry| entry.record.range.clone()).collect();
+    assert_eq!(burns, vec![Some(100..200), Some(200..250)]);
+
+    // The history names the reason
+    otp(&vault).arg("pad").arg("show").arg(&pad_id).assert().success().stdout(predicate::str::contains("(handwritten note)"));
+}
+
+#[test]
+fn test_burning_the_rest_moves_the_pad_to_used() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let size = 1024 * 1024;
+
+    burn(&vault, &pad_id, &format!("0..{size}")).assert().success().stdout(predicate::str::contains("moved to pads/used"));
+    assert!(vault.join("pads/used").join(format!("{pad_id}.pad")).exists());
+    assert!(state_manager::load_state(&vault).expect("Failed to load state").pads[&pad_id].is_fully_used);
+    otp(&vault).arg("vault").arg("fsck").assert().success();
+}
@@ -0,0 +1,75 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Origin, Pad, UsedSegment};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn segment(start: usize, end: usize, origin: Option<Origin>) -> UsedSegment {
+    UsedSegment { origin, ..(start..end).into() }
+}
+
+#[test]
+fn test_incoming_collisions_depend_on_provenance() {
+    let mut pad = Pad {
+        size: 100,
+        used_segments: vec![segment(0, 10, Some(Origin::Sent)), segment(20, 30, Some(Origin::Received)), segment(40, 50, None)],
+        ..Default::default()
+    };
+    pad.reindex();
+    // A partner's message on bytes we sent is a collision, even on the identical range
+    assert_eq!(pad.incoming_collisions(0, 10, true), vec![0..10]);
+    assert_eq!(pad.incoming_collisions(5, 25, true), vec![5..10, 20..25]);
+    // Without a sender the identical range is most likely our own message
+    assert!(pad.incoming_collisions(0, 10, false).is_empty());
+    assert_eq!(pad.incoming_collisions(0, 12, false), vec![0..10]);
+    // Reading a received message again is fine; unknown provenance is never reported
+    assert!(pad.incoming_collisions(20, 30, true).is_empty());
+    assert!(pad.incoming_collisions(40, 50, true).is_empty());
+    assert_eq!(pad.unused_parts(5, 25), vec![10..20]);
+}
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_decrypting_a_colliding_message_quarantines_the_pad() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let alice = temp_dir.path().join("alice");
+    let bob = temp_dir.path().join("bob");
+    for vault in [&alice, &bob] {
+        otp(vault).arg("vault").arg("init").assert().success();
+    }
+    let output = otp(&alice).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let printout = temp_dir.path().join("pad.txt");
+    otp(&alice).arg("pad").arg("print").arg(&pad_id).arg("--output").arg(&printout).assert().success();
+    otp(&bob).arg("pad").arg("import").arg("--from-text").arg(&printout).assert().success();
+
+    // Without a direction policy both start at byte 0
+    let from_alice = temp_dir.path().join("from_alice.asc");
+    let from_bob = temp_dir.path().join("from_bob.asc");
+    otp(&alice).arg("encrypt").arg("--text").arg("hello bob").arg("-o").arg(&from_alice).arg("--encoding").arg("armor").assert().success();
+    otp(&bob).arg("encrypt").arg("--text").arg("hello alice").arg("-o").arg(&from_bob).arg("--encoding").arg("armor").assert().success();
+
+    let plaintext = temp_dir.path().join("plain.txt");
+    otp(&bob)
+        .arg("decrypt").arg("-i").arg(&from_alice).arg("-o").arg(&plaintext)
+        .assert().failure()
+        .stderr(predicate::str::contains("PAD REUSE DETECTED"));
+    assert_eq!(fs::read_to_string(&plaintext).expect("The message is still decrypted"), "hello bob");
+
+    let state = state_manager::load_state(&bob).expect("Failed to load state");
+    assert!(state.pads[&pad_id].quarantined.is_some());
+    otp(&bob)
+        .arg("encrypt").arg("--text").arg("again").arg("-o").arg(temp_dir.path().join("again.asc"))
+        .arg("--pad-id").arg(&pad_id)
+        .assert().failure()
+        .stderr(predicate::str::contains("quarantined"));
+    otp(&bob).arg("pad").arg("list").assert().success().stdout(predicate::str::contains("is quarantined"));
+}
@@ -0,0 +1,100 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::config::{self, Settings};
+use otp_core::state_manager;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+const SAMPLE: &str = r#"
+# Personal vault
+vault = "/data/personal"   # the default
+pad_size = 2
+
+[profiles.work]
+vault = "/data/work # not a comment"
+encoding = "armor"
+
+[profiles."travel"]
+pad_size = 8
+"#;
+
+#[test]
+fn test_config_file_parses_profiles() {
+    let file = config::parse(SAMPLE).expect("Valid config");
+    assert_eq!(file.defaults.vault, Some(PathBuf::from("/data/personal")));
+    assert_eq!(file.defaults.pad_size, Some(2));
+    assert_eq!(file.profiles["work"].vault, Some(PathBuf::from("/data/work # not a comment")));
+    assert_eq!(file.profiles["work"].encoding.as_deref(), Some("armor"));
+    assert_eq!(file.profiles["travel"].pad_size, Some(8));
+
+    assert!(config::parse("vault_path = \"x\"").expect_err("Unknown key").contains("line 1"));
+    assert!(config::parse("[servers]").is_err());
+    assert!(config::parse("pad_size = 0").is_err());
+    assert!(config::parse("vault = unquoted").is_err());
+}
+
+#[test]
+fn test_flags_beat_environment_beat_profile_beat_file() {
+    let file = config::parse(SAMPLE).expect("Valid config");
+    let mut vars = HashMap::new();
+    let resolve = |vars: &HashMap<&str, &str>, profile: Option<&str>, flags: Settings| {
+        config::resolve(&file, profile, flags, &|name| vars.get(name).map(ToString::to_string))
+    };
+
+    let plain = resolve(&vars, None, Settings::default()).expect("Resolves");
+    assert_eq!((plain.vault, plain.pad_size, plain.encoding), (PathBuf::from("/data/personal"), 2, None));
+
+    let work = resolve(&vars, Some("work"), Settings::default()).expect("Resolves");
+    assert_eq!((work.vault, work.pad_size, work.encoding.as_deref()), (PathBuf::from("/data/work # not a comment"), 2, Some("armor")));
+
+    vars.insert(config::ENV_PROFILE, "travel");
+    vars.insert(config::ENV_VAULT, "/env/vault");
+    let from_env = resolve(&vars, None, Settings::default()).expect("Resolves");
+    assert_eq!((from_env.profile.as_deref(), from_env.vault, from_env.pad_size), (Some("travel"), PathBuf::from("/env/vault"), 8));
+
+    let flags = Settings { vault: Some(PathBuf::from("/flag/vault")), ..Settings::default() };
+    assert_eq!(resolve(&vars, None, flags).expect("Resolves").vault, PathBuf::from("/flag/vault"));
+
+    assert!(resolve(&vars, Some("home"), Settings::default()).expect_err("Unknown profile").contains("work"));
+    let empty = config::resolve(&config::ConfigFile::default(), None, Settings::default(), &|_| None).expect("Resolves");
+    assert_eq!((empty.vault, empty.pad_size), (PathBuf::from(config::DEFAULT_VAULT), config::DEFAULT_PAD_SIZE));
+}
+
+#[test]
+fn test_cli_uses_the_configured_profile() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let work_vault = temp_dir.path().join("work");
+    let config_home = temp_dir.path().join("config");
+    fs::create_dir_all(config_home.join("otp")).expect("Failed to create config dir");
+    fs::write(
+        config_home.join("otp/config.toml"),
+        format!("[profiles.work]\nvault = \"{}\"\npad_size = 2\n", work_vault.display()),
+    )
+    .expect("Failed to write config");
+    let otp = || {
+        let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+        command
+            .env("XDG_CONFIG_HOME", &config_home)
+            .env_remove(config::ENV_CONFIG)
+            .env_remove(config::ENV_PROFILE)
+            .env_remove(config::ENV_VAULT)
+            .env_remove(config::ENV_PAD_SIZE)
+            .env_remove(config::ENV_ENCODING)
+            .current_dir(temp_dir.path());
+        command
+    };
+
+    otp().arg("--profile").arg("work").arg("vault").arg("init").assert().success();
+    otp().arg("--profile").arg("work").arg("pad").arg("generate").assert().success();
+    let state = state_manager::load_state(&work_vault).expect("Failed to load state");
+    assert_eq!(state.pads.values().map(|pad| pad.size).collect::<Vec<_>>(), vec![2 * 1024 * 1024]);
+
+    otp().env(config::ENV_PROFILE, "work").arg("pad").arg("list").assert().success().stdout(predicate::str::contains("2.00"));
+    // Without a profile the shared default vault is used, which does not exist here
+    otp().arg("pad").arg("list").assert().failure().stderr(predicate::str::contains(".otp_vault"));
+    otp().arg("--profile").arg("home").arg("pad").arg("list").assert().failure().stderr(predicate::str::contains("profile 'home'"));
+}
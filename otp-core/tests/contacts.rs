@@ -0,0 +1,107 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Direction};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn generate_pad(vault: &Path) -> String {
+    let output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(vault)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string()
+}
+
+#[test]
+fn test_encrypt_to_contact_uses_only_their_pads() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("vault").arg("init")
+        .assert().success();
+    let alice_pad = generate_pad(&vault);
+    let other_pad = generate_pad(&vault);
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("contact").arg("add").arg("alice")
+        .arg("--pad").arg(&alice_pad)
+        .arg("--role").arg("responder")
+        .arg("--notes").arg("met at the conference")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("contact").arg("add").arg("bob")
+        .arg("--pad").arg(&alice_pad)
+        .assert().failure()
+        .stderr(predicate::str::contains("already shared with contact 'alice'"));
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads[&alice_pad].direction, Some(Direction::Back));
+    assert_eq!(state.contact_for_pad(&alice_pad).map(|c| c.name.as_str()), Some("alice"));
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("contact").arg("list")
+        .assert().success()
+        .stdout(predicate::str::contains("alice").and(predicate::str::contains("met at the conference")));
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("contact").arg("show").arg("alice")
+        .assert().success()
+        .stdout(predicate::str::contains(&alice_pad).and(predicate::str::contains("back")));
+
+    // Encrypting for Alice picks her pad, never the other one
+    let plaintext = temp_dir.path().join("note.txt");
+    fs::write(&plaintext, "for alice only").expect("Failed to write plaintext");
+    let ciphertext = temp_dir.path().join("note.txt.enc");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("encrypt").arg(&plaintext)
+        .arg("--to").arg("alice")
+        .arg("-o").arg(&ciphertext)
+        .assert().success();
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads[&alice_pad].used_segments.len(), 1);
+    assert!(state.pads[&other_pad].used_segments.is_empty());
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("encrypt").arg(&plaintext)
+        .arg("--to").arg("alice")
+        .arg("--pad-id").arg(&other_pad)
+        .assert().failure()
+        .stderr(predicate::str::contains("not shared with contact 'alice'"));
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("encrypt").arg(&plaintext)
+        .arg("--to").arg("carol")
+        .assert().failure()
+        .stderr(predicate::str::contains("Contact 'carol' not found"));
+
+    // Decryption names the contact the pad is shared with
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("decrypt")
+        .arg("-i").arg(&ciphertext)
+        .arg("--metadata").arg(temp_dir.path().join("note.txt.enc.metadata.json"))
+        .arg("-o").arg(temp_dir.path().join("note.out"))
+        .assert().success()
+        .stdout(predicate::str::contains("From: alice"));
+
+    // A state that records more used bytes than the pad holds, e.g. from a bad sync, still lists
+    let mut state = state_manager::load_state(&vault).expect("Failed to load state");
+    let pad = state.pads.get_mut(&alice_pad).unwrap();
+    let size = pad.size;
+    pad.record_use((0..size + 100).into());
+    state_manager::save_state(&vault, &state).expect("Failed to save state");
+    for command in ["list", "show"] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(&vault)
+            .arg("contact").arg(command).args((command == "show").then_some("alice"))
+            .assert().success();
+    }
+}
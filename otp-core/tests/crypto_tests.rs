@@ -4,7 +4,7 @@ use otp_core::crypto;
 #[test]
 fn test_encryption_decryption_roundtrip() {
     let plaintext = b"Hello, world!";
-    let pad = (0..plaintext.len()).map(|i| ((i * 7) % 256) as u8).collect::<Vec<u8>>();
+    let pad = (0..plaintext.len()).map(|i| u8::try_from((i * 7) % 256).unwrap()).collect::<Vec<u8>>();
 
     let ciphertext = crypto::xor(plaintext, &pad);
     let decrypted_plaintext = crypto::xor(&ciphertext, &pad);
@@ -22,7 +22,7 @@ fn test_file_encryption_decryption_simulation() {
     // 2. Simulate requesting and receiving a pad segment
     let pad_id = "test-pad-id".to_string();
     let start = 123;
-    let pad_segment = (0..length).map(|i| ((i * 3) % 256) as u8).collect::<Vec<u8>>();
+    let pad_segment = (0..length).map(|i| u8::try_from((i * 3) % 256).unwrap()).collect::<Vec<u8>>();
 
     // 3. Simulate client-side encryption
     let ciphertext = crypto::xor(&plaintext, &pad_segment);
@@ -33,8 +33,8 @@ fn test_file_encryption_decryption_simulation() {
     let received_metadata: serde_json::Value = serde_json::from_str(&metadata).unwrap();
     
     let received_pad_id = received_metadata["pad_id"].as_str().unwrap();
-    let received_start = received_metadata["start"].as_u64().unwrap() as usize;
-    let received_length = received_metadata["length"].as_u64().unwrap() as usize;
+    let received_start = received_metadata["start"].as_u64().and_then(|n| usize::try_from(n).ok()).unwrap();
+    let received_length = received_metadata["length"].as_u64().and_then(|n| usize::try_from(n).ok()).unwrap();
 
     assert_eq!(pad_id, received_pad_id);
     assert_eq!(start, received_start);
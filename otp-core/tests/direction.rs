@@ -0,0 +1,108 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Direction, Pad, UsedSegment};
+use tempfile::tempdir;
+
+fn pad_with(direction: Option<Direction>, used: &[(usize, usize)]) -> Pad {
+    let mut pad = Pad {
+        id: "pad-1".to_string(),
+        file_name: "pad-1.pad".to_string(),
+        size: 100,
+        direction,
+        ..Default::default()
+    };
+    for &(start, end) in used {
+        pad.record_use(UsedSegment::from(start..end));
+    }
+    pad
+}
+
+#[test]
+fn test_direction_policies_confine_allocation() {
+    assert_eq!(pad_with(Some(Direction::Front), &[(0, 10)]).find_available_segment(20), Some(10));
+    assert_eq!(pad_with(Some(Direction::Back), &[(90, 100)]).find_available_segment(20), Some(70));
+    assert_eq!(pad_with(Some(Direction::Back), &[(0, 95)]).find_available_segment(10), None);
+
+    let ranged = pad_with(Some(Direction::Range { start: 50, end: 80 }), &[]);
+    assert_eq!(ranged.find_available_segment(20), Some(50));
+    assert_eq!(ranged.find_available_segment(40), None);
+    assert!(ranged.is_range_allowed(60, 80));
+    assert!(!ranged.is_range_allowed(0, 10));
+
+    for direction in [Direction::Front, Direction::Back, Direction::Range { start: 3, end: 9 }] {
+        assert_eq!(direction.to_string().parse::<Direction>(), Ok(direction));
+    }
+    assert_eq!(Direction::Front.peer(), Some(Direction::Back));
+    assert_eq!(Direction::Range { start: 3, end: 9 }.peer(), None);
+}
+
+#[test]
+fn test_paired_partners_consume_opposite_ends() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let alice_vault = temp_dir.path().join("alice_vault");
+    let bob_vault = temp_dir.path().join("bob_vault");
+    for vault in [&alice_vault, &bob_vault] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("vault").arg("init")
+            .assert().success();
+    }
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice_vault)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+
+    // Printing with --pair makes Alice the initiator and tells Bob to be the responder
+    let printout = temp_dir.path().join("pad.txt");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice_vault)
+        .arg("pad").arg("print").arg(&pad_id)
+        .arg("--pair")
+        .arg("--output").arg(&printout)
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob_vault)
+        .arg("pad").arg("import")
+        .arg("--from-text").arg(&printout)
+        .assert().success();
+
+    let alice_state = state_manager::load_state(&alice_vault).expect("Failed to load Alice's state");
+    let bob_state = state_manager::load_state(&bob_vault).expect("Failed to load Bob's state");
+    assert_eq!(alice_state.pads[&pad_id].direction, Some(Direction::Front));
+    assert_eq!(bob_state.pads[&pad_id].direction, Some(Direction::Back));
+
+    // Both encrypt without talking to each other and never touch the same bytes
+    let plaintext = temp_dir.path().join("message.txt");
+    fs::write(&plaintext, "meet at noon").expect("Failed to write plaintext");
+    let mut used = Vec::new();
+    for vault in [&alice_vault, &bob_vault] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("encrypt").arg(&plaintext)
+            .arg("--pad-id").arg(&pad_id)
+            .arg("-o").arg(vault.join("message.enc"))
+            .assert().success();
+        let state = state_manager::load_state(vault).expect("Failed to load state");
+        let segment = &state.pads[&pad_id].used_segments[0];
+        used.push(segment.start..segment.end);
+    }
+    assert_eq!(used[0].start, 0);
+    assert_eq!(used[1].end, 1024 * 1024);
+
+    // With an assigned range, an explicit offset outside it is refused
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob_vault)
+        .arg("pad").arg("pair").arg(&pad_id)
+        .arg("--range").arg("4096..8192")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob_vault)
+        .arg("encrypt").arg(&plaintext)
+        .arg("--pad-id").arg(&pad_id)
+        .arg("--offset").arg("100")
+        .arg("-o").arg(temp_dir.path().join("refused.enc"))
+        .assert().failure();
+}
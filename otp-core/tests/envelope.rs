@@ -0,0 +1,88 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::io::Read;
+use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
+use assert_cmd::prelude::*;
+use otp_core::envelope::{self, FileHeader};
+use tempfile::tempdir;
+
+#[test]
+fn test_envelope_roundtrip_and_tampering() {
+    let header = FileHeader { name: Some("report.pdf".to_string()), size: Some(5), mtime: Some(1_700_000_000), mode: Some(0o640), ..FileHeader::default() };
+    let mut wrapped = Vec::new();
+    envelope::Wrap::new(&header, &b"hello"[..]).read_to_end(&mut wrapped).expect("Wrapping should succeed");
+    assert_eq!(wrapped.len(), envelope::wrapped_len(&header, 5));
+
+    let (parsed, payload) = envelope::unwrap(&wrapped).expect("Envelope should verify").expect("Envelope should be found");
+    assert_eq!(parsed, header);
+    assert_eq!(payload, b"hello");
+
+    // A flipped payload bit breaks the digest.
+    let payload_index = wrapped.len() - 33;
+    wrapped[payload_index] ^= 1;
+    assert!(envelope::unwrap(&wrapped).is_err());
+
+    // Legacy plaintext passes through untouched, and names with directories are not trusted.
+    assert!(envelope::unwrap(b"plain old data").expect("Legacy data is fine").is_none());
+    let sneaky = FileHeader { name: Some("../etc/passwd".to_string()), ..FileHeader::default() };
+    assert_eq!(sneaky.safe_file_name(), None);
+}
+
+#[test]
+fn test_decrypt_restores_original_name_and_mtime() {
+    // 1. Setup
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault_path = temp_dir.path().join("my_test_vault");
+    let input_path = temp_dir.path().join("holiday-plans.txt");
+    fs::write(&input_path, "Lisbon in May").expect("Failed to write input file");
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::File::options().write(true).open(&input_path).unwrap().set_modified(mtime).unwrap();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("vault").arg("init")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("pad").arg("generate")
+        .assert().success();
+
+    // 2. Encrypt under a name that says nothing about the content
+    let encrypted_path = temp_dir.path().join("blob.bin");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg(&input_path)
+        .arg("--output").arg(&encrypted_path)
+        .assert().success();
+    let ciphertext = fs::read(&encrypted_path).unwrap();
+    assert!(!ciphertext.windows(7).any(|w| w == b"holiday"), "The name must not leak");
+
+    // 3. Decrypt into a directory; the original name and mtime come back
+    let restore_dir = temp_dir.path().join("inbox");
+    fs::create_dir(&restore_dir).unwrap();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg(&encrypted_path)
+        .arg("--output").arg(&restore_dir)
+        .arg("--metadata").arg(temp_dir.path().join("blob.bin.metadata.json"))
+        .assert().success();
+    let restored = restore_dir.join("holiday-plans.txt");
+    assert_eq!(fs::read_to_string(&restored).unwrap(), "Lisbon in May");
+    assert_eq!(fs::metadata(&restored).unwrap().modified().unwrap(), mtime);
+
+    // 4. Without metadata, --length takes the file size, but no other length shorter than the ciphertext
+    let pad_id = fs::read_to_string(temp_dir.path().join("blob.bin.metadata.json")).unwrap();
+    let pad_id = serde_json::from_str::<serde_json::Value>(&pad_id).unwrap()["pad_id"].as_str().unwrap().to_string();
+    for (length, code) in [(13, 0), (12, 7)] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(&vault_path)
+            .arg("decrypt")
+            .arg("--input").arg(&encrypted_path)
+            .arg("--output").arg(temp_dir.path().join(format!("manual-{length}.txt")))
+            .arg("--pad-id").arg(&pad_id)
+            .arg("--length").arg(length.to_string())
+            .assert().code(code);
+    }
+    assert_eq!(fs::read_to_string(temp_dir.path().join("manual-13.txt")).unwrap(), "Lisbon in May");
+}
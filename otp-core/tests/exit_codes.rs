@@ -0,0 +1,115 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+fn generate(vault: &Path) -> String {
+    let output = otp(vault).arg("pad").arg("generate").arg("--size").arg("1").output().expect("Failed to generate pad");
+    String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string()
+}
+
+#[test]
+fn test_failures_exit_with_their_class() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(temp_dir.path().join("missing").as_path()).arg("pad").arg("list").assert().code(3);
+    otp(&vault).arg("vault").arg("init").assert().code(0);
+    otp(&vault).arg("pad").arg("frobnicate").assert().code(2);
+    otp(&vault).arg("pad").arg("show").arg("nope").assert().code(3);
+    otp(&vault).arg("pad").arg("delete").arg("--pad-id").arg("nope").assert().code(3);
+    otp(&vault).arg("encrypt").arg("--text").arg("hi").arg("--pad-id").arg("nope").arg("-o").arg(temp_dir.path().join("x.asc")).assert().code(3);
+    // No pad at all, then a retired one
+    otp(&vault).arg("encrypt").arg("--text").arg("hi").arg("-o").arg(temp_dir.path().join("x.asc")).assert().code(4);
+    let pad_id = generate(&vault);
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("0..10").arg("--reason").arg("test").assert().code(0);
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("0..10").arg("--reason").arg("test").assert().code(4);
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("0..99999999").arg("--reason").arg("test").assert().code(7);
+
+    // A tampered ciphertext fails its hash check instead of exiting 0
+    let message = temp_dir.path().join("message.bin");
+    otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("-o").arg(&message).assert().code(0);
+    let mut ciphertext = fs::read(&message).expect("Failed to read ciphertext");
+    ciphertext[0] ^= 1;
+    fs::write(&message, ciphertext).expect("Failed to write ciphertext");
+    otp(&vault)
+        .arg("decrypt").arg("--input").arg(&message).arg("--metadata").arg(temp_dir.path().join("message.bin.metadata.json"))
+        .arg("-o").arg(temp_dir.path().join("hello.txt"))
+        .assert().code(5);
+    assert!(!temp_dir.path().join("hello.txt").exists());
+
+    // A range ending past the largest offset is bad input, not a crash
+    let last = usize::MAX.to_string();
+    otp(&vault).arg("encrypt").arg("--text").arg("hi").arg("--pad-id").arg(&pad_id).arg("--offset").arg(&last).arg("-o").arg(temp_dir.path().join("z.bin")).assert().code(7);
+    otp(&vault)
+        .arg("decrypt").arg("--input").arg(&message).arg("--pad-id").arg(&pad_id).arg("--offset").arg(&last).arg("--length").arg("5")
+        .arg("-o").arg(temp_dir.path().join("hello.txt"))
+        .assert().code(7);
+
+    otp(&vault).arg("pad").arg("retire").arg(&pad_id).assert().code(0);
+    otp(&vault).arg("encrypt").arg("--text").arg("hi").arg("--pad-id").arg(&pad_id).arg("-o").arg(temp_dir.path().join("y.asc")).assert().code(4);
+}
+
+#[test]
+fn test_failed_state_save_leaves_no_ciphertext() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let pad_id = generate(&vault);
+    // A directory where the state's temporary file goes makes every save fail
+    fs::create_dir(vault.join("vault_state.json.tmp")).expect("Failed to block the state file");
+
+    let streamed = temp_dir.path().join("streamed.bin");
+    otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("-o").arg(&streamed).assert().code(6);
+    assert!(!streamed.exists() && !temp_dir.path().join("streamed.bin.metadata.json").exists());
+    let armored = temp_dir.path().join("buffered.asc");
+    otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("--encoding").arg("armor").arg("-o").arg(&armored).assert().code(6);
+    assert!(!armored.exists());
+
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads[&pad_id].total_used_bytes(), 0);
+}
+
+#[test]
+fn test_failed_state_save_leaves_no_plaintext() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    generate(&vault);
+    let message = temp_dir.path().join("message.asc");
+    otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("--encoding").arg("armor").arg("-o").arg(&message).assert().success();
+    let files = temp_dir.path().join("files");
+    fs::create_dir(&files).expect("Failed to create directory");
+    fs::write(files.join("a.txt"), "archived").expect("Failed to write file");
+    let archive = temp_dir.path().join("files.asc");
+    otp(&vault).arg("encrypt").arg(&files).arg("--recursive").arg("--encoding").arg("armor").arg("-o").arg(&archive).assert().success();
+    let used = state_manager::load_state(&vault).expect("Failed to load state").pads.values().map(state_manager::Pad::total_used_bytes).sum::<usize>();
+    fs::create_dir(vault.join("vault_state.json.tmp")).expect("Failed to block the state file");
+
+    let plain = temp_dir.path().join("plain.txt");
+    otp(&vault).arg("decrypt").arg("--input").arg(&message).arg("-o").arg(&plain).assert().code(6);
+    otp(&vault).arg("decrypt").arg("--input").arg(&message).arg("-o").arg("-").assert().code(6).stdout("");
+    let extracted = temp_dir.path().join("extracted");
+    otp(&vault).arg("decrypt").arg("--input").arg(&archive).arg("-o").arg(&extracted).assert().code(6);
+    let left: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert!(!plain.exists() && left.iter().all(|name| !name.to_string_lossy().contains("otp-partial")), "{left:?}");
+    assert!(!extracted.exists() || fs::read_dir(&extracted).unwrap().next().is_none());
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads.values().map(state_manager::Pad::total_used_bytes).sum::<usize>(), used);
+
+    // Once the state saves again, the same messages decrypt into place
+    fs::remove_dir(vault.join("vault_state.json.tmp")).expect("Failed to unblock the state file");
+    otp(&vault).arg("decrypt").arg("--input").arg(&message).arg("-o").arg(&plain).assert().success();
+    assert_eq!(fs::read_to_string(&plain).unwrap(), "hello");
+    otp(&vault).arg("decrypt").arg("--input").arg(&archive).arg("-o").arg(&extracted).assert().success();
+    assert_eq!(fs::read_to_string(extracted.join("a.txt")).unwrap(), "archived");
+    assert_eq!(fs::read_dir(&extracted).unwrap().count(), 1);
+}
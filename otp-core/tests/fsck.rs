@@ -0,0 +1,81 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used, clippy::reversed_empty_ranges)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::fsck::{self, Problem};
+use otp_core::state_manager;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_fsck_finds_and_repairs_drift() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    otp(&vault).arg("pad").arg("generate").arg("--count").arg("4").assert().success();
+    otp(&vault).arg("vault").arg("fsck").assert().success().stdout(predicate::str::contains("No problems found"));
+
+    let mut state = state_manager::load_state(&vault).expect("Failed to load state");
+    let mut ids: Vec<String> = state.pads.keys().cloned().collect();
+    ids.sort();
+    let size = state.pads[&ids[0]].size;
+    let available = vault.join("pads/available");
+
+    // Bookkeeping drift on the first pad
+    let pad = state.pads.get_mut(&ids[0]).expect("Pad exists");
+    pad.used_segments = vec![(0..10).into(), (0..10).into(), (size - 5..size + 5).into(), (30..20).into()];
+    pad.is_fully_used = true;
+    // Possible reuse on the second
+    state.pads.get_mut(&ids[1]).expect("Pad exists").used_segments = vec![(100..200).into(), (150..250).into()];
+    // A truncated file and a file in the wrong directory
+    let truncated = &state.pads[&ids[2]].file_name;
+    fs::write(available.join(truncated), b"short").expect("Failed to truncate pad");
+    let moved = state.pads[&ids[3]].file_name.clone();
+    fs::rename(available.join(&moved), vault.join("pads/used").join(&moved)).expect("Failed to move pad");
+    // A pad without a file and a file without a pad
+    state.add_pad("ghost".to_string(), "ghost.pad".to_string(), 64);
+    fs::write(available.join("stray.pad"), [0u8; 64]).expect("Failed to write stray file");
+    state_manager::save_state(&vault, &state).expect("Failed to save state");
+
+    let problems = fsck::check(&vault, &state).expect("Check runs");
+    for expected in [
+        Problem::Duplicate { pad_id: ids[0].clone(), segment: 0..10 },
+        Problem::OutOfBounds { pad_id: ids[0].clone(), segment: size - 5..size + 5 },
+        Problem::OutOfBounds { pad_id: ids[0].clone(), segment: 30..20 },
+        Problem::StaleFlag { pad_id: ids[0].clone(), recorded: true },
+        Problem::Overlap { pad_id: ids[1].clone(), bytes: 150..200 },
+        Problem::SizeMismatch { pad_id: ids[2].clone(), expected: size, actual: 5 },
+        Problem::WrongDirectory { pad_id: ids[3].clone(), path: vault.join("pads/used").join(&moved) },
+        Problem::MissingFile { pad_id: "ghost".to_string() },
+        Problem::OrphanFile { path: available.join("stray.pad") },
+    ] {
+        assert!(problems.contains(&expected), "missing {expected:?} in {problems:?}");
+    }
+    assert_eq!(problems.len(), 9);
+
+    otp(&vault).arg("vault").arg("fsck").assert().failure().stdout(predicate::str::contains("Found 9 problem(s)"));
+    otp(&vault).arg("vault").arg("fsck").arg("--repair").assert().success().stdout(predicate::str::contains("Quarantined pad 'ghost'"));
+
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    let first = &state.pads[&ids[0]];
+    let ranges: Vec<_> = first.used_segments.iter().map(|s| s.start..s.end).collect();
+    assert_eq!(ranges, vec![0..10, size - 5..size, 20..30]);
+    assert!(!first.is_fully_used && first.quarantined.is_none());
+    assert!(available.join(&moved).is_file());
+    assert!(vault.join(fsck::QUARANTINE_DIR).join("stray.pad").is_file());
+    for id in [&ids[1], &ids[2], &"ghost".to_string()] {
+        assert!(state.pads[id].quarantined.is_some(), "pad {id} should be quarantined");
+    }
+
+    // What is left can only be resolved by a person, and stays quarantined
+    let remaining = fsck::check(&vault, &state).expect("Check runs");
+    assert_eq!(remaining.len(), 3);
+    assert!(remaining.iter().all(|problem| !problem.is_repairable()));
+}
@@ -0,0 +1,159 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Group, GroupLayout, GroupMembership, Pad};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn members(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_group_layouts_partition_the_pad() {
+    let ranges = Group::new(GroupLayout::Ranges, members(&["alice", "bob", "carol"])).expect("Valid group");
+    assert_eq!(ranges.member_ranges(90, "bob"), vec![30..60]);
+    assert!(ranges.member_ranges(90, "mallory").is_empty());
+
+    let stripes = Group::new(GroupLayout::Stripes { width: 10 }, members(&["alice", "bob", "carol"])).expect("Valid group");
+    assert_eq!(stripes.member_ranges(75, "carol"), vec![20..30, 50..60]);
+    assert_eq!(stripes.member_ranges(75, "alice"), vec![0..10, 30..40, 60..70]);
+    assert_eq!(stripes.to_string(), "stripes/10:alice,bob,carol");
+    assert_eq!("stripes/10:alice,bob,carol".parse::<Group>(), Ok(stripes.clone()));
+
+    assert!(Group::new(GroupLayout::Ranges, members(&["alice"])).is_err());
+    assert!(Group::new(GroupLayout::Ranges, members(&["alice", "alice"])).is_err());
+    assert!("stripes/0:alice,bob".parse::<Group>().is_err());
+    assert!(format!("stripes/{}:alice,bob", usize::MAX / 2 + 1).parse::<Group>().is_err());
+
+    // Layouts that did not come through `new`, e.g. from a hand-edited state, do not overflow
+    let huge = Group { layout: GroupLayout::Stripes { width: usize::MAX }, members: members(&["alice", "bob"]) };
+    assert_eq!(huge.member_ranges(100, "alice"), vec![0..100]);
+    assert!(huge.member_ranges(100, "bob").is_empty());
+    let zero = Group { layout: GroupLayout::Stripes { width: 0 }, members: members(&["alice", "bob"]) };
+    assert!(zero.member_ranges(100, "alice").is_empty());
+    assert_eq!(ranges.member_ranges(usize::MAX, "carol"), vec![usize::MAX / 3 * 2..usize::MAX]);
+
+    // A member's allocator draws only from their own stripes
+    let mut pad = Pad {
+        id: "pad-1".to_string(),
+        file_name: "pad-1.pad".to_string(),
+        size: 75,
+        group: Some(GroupMembership { group: stripes, member: "bob".to_string() }),
+        ..Default::default()
+    };
+    assert_eq!(pad.find_available_segment(8), Some(10));
+    assert_eq!(pad.find_available_segment(11), None);
+    pad.record_use((10..18).into());
+    assert_eq!(pad.find_available_segment(8), Some(40));
+    assert!(!pad.is_range_allowed(15, 25));
+}
+
+#[test]
+fn test_group_members_import_and_encrypt_in_their_own_parts() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vaults: Vec<_> = ["alice", "bob", "carol"].iter().map(|name| temp_dir.path().join(name)).collect();
+    for vault in &vaults {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("vault").arg("init")
+            .assert().success();
+    }
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vaults[0])
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vaults[0])
+        .arg("pad").arg("group").arg(&pad_id)
+        .arg("--members").arg("alice,bob,carol")
+        .arg("--member").arg("alice")
+        .arg("--layout").arg("stripes")
+        .arg("--stripe-width").arg("4096")
+        .assert().success();
+    let printout = temp_dir.path().join("pad.txt");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vaults[0])
+        .arg("pad").arg("print").arg(&pad_id)
+        .arg("--output").arg(&printout)
+        .assert().success();
+    assert!(fs::read_to_string(&printout).expect("Failed to read printout").contains("group=stripes/4096:alice,bob,carol"));
+
+    // Importing a group pad requires saying who you are
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vaults[1])
+        .arg("pad").arg("import")
+        .arg("--from-text").arg(&printout)
+        .assert().failure()
+        .stderr(predicate::str::contains("--member"));
+    for (vault, name) in vaults[1..].iter().zip(["bob", "carol"]) {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("pad").arg("import")
+            .arg("--from-text").arg(&printout)
+            .arg("--member").arg(name)
+            .assert().success();
+    }
+
+    let plaintext = temp_dir.path().join("status.txt");
+    fs::write(&plaintext, "all quiet").expect("Failed to write plaintext");
+    let mut starts = Vec::new();
+    for vault in &vaults {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("encrypt").arg(&plaintext)
+            .arg("--pad-id").arg(&pad_id)
+            .arg("-o").arg(vault.join("status.enc"))
+            .assert().success();
+        let state = state_manager::load_state(vault).expect("Failed to load state");
+        starts.push(state.pads[&pad_id].used_segments[0].start);
+    }
+    assert_eq!(starts, vec![0, 4096, 8192]);
+
+    // Any member can read another member's stripe
+    let decrypted = temp_dir.path().join("from_carol.txt");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vaults[0])
+        .arg("decrypt")
+        .arg("-i").arg(vaults[2].join("status.enc"))
+        .arg("--metadata").arg(vaults[2].join("status.enc.metadata.json"))
+        .arg("-o").arg(&decrypted)
+        .assert().success();
+    assert_eq!(fs::read_to_string(&decrypted).expect("Failed to read decrypted file"), "all quiet");
+}
+
+#[test]
+fn test_svg_printout_escapes_member_names() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("vault").arg("init")
+        .assert().success();
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("group").arg(&pad_id)
+        .arg("--members").arg("a&b,<script>,\"q\"")
+        .arg("--member").arg("a&b")
+        .assert().success();
+
+    let printout = temp_dir.path().join("pad.svg");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("print").arg(&pad_id)
+        .arg("--range").arg("0..64")
+        .arg("--format").arg("svg")
+        .arg("--output").arg(&printout)
+        .assert().success();
+    let svg = fs::read_to_string(&printout).expect("Failed to read printout");
+    assert!(svg.contains("a&amp;b,&lt;script&gt;,&quot;q&quot;"));
+    assert!(!svg.contains("<script>"));
+}
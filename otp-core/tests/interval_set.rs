@@ -0,0 +1,205 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::ops::Range;
+use otp_core::state_manager::{Direction, IntervalSet, Origin, Pad, UsedSegment};
+use proptest::prelude::*;
+
+/// Free ranges as computed before the used bytes were kept in an `IntervalSet`.
+fn reference_free_ranges(used: &[Range<usize>], size: usize) -> Vec<Range<usize>> {
+    let mut sorted = used.to_vec();
+    sorted.sort_by_key(|r| r.start);
+    let mut free = Vec::new();
+    let mut cursor = 0;
+    for segment in &sorted {
+        if segment.start > cursor {
+            free.push(cursor..segment.start.min(size));
+        }
+        cursor = cursor.max(segment.end);
+    }
+    if cursor < size {
+        free.push(cursor..size);
+    }
+    free
+}
+
+/// Allocatable ranges as computed before, on top of `reference_free_ranges`.
+fn reference_allocatable(pad: &Pad, used: &[Range<usize>]) -> Vec<Range<usize>> {
+    let allowed = pad.allowed_ranges();
+    reference_free_ranges(used, pad.size)
+        .into_iter()
+        .flat_map(|free| allowed.iter().map(move |a| free.start.max(a.start)..free.end.min(a.end)))
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+/// Allocation as implemented before.
+fn reference_find(pad: &Pad, used: &[Range<usize>], length: usize) -> Option<usize> {
+    if used.iter().map(ExactSizeIterator::len).sum::<usize>() >= pad.size {
+        return None;
+    }
+    let mut fitting = reference_allocatable(pad, used).into_iter().filter(|r| r.len() >= length);
+    if pad.direction == Some(Direction::Back) {
+        fitting.next_back().map(|r| r.end - length)
+    } else {
+        fitting.next().map(|r| r.start)
+    }
+}
+
+fn pad_with(size: usize, direction: Option<Direction>, used: &[Range<usize>]) -> Pad {
+    let mut pad = Pad { id: "p".to_string(), size, direction, ..Pad::default() };
+    for range in used {
+        pad.record_use(UsedSegment::from(range.clone()));
+    }
+    pad
+}
+
+/// A pad size and possibly overlapping ranges inside it.
+fn overlapping() -> impl Strategy<Value = (usize, Vec<Range<usize>>)> {
+    (1..400usize).prop_flat_map(|size| {
+        let range = (0..size).prop_flat_map(move |start| (Just(start), start + 1..=size)).prop_map(|(start, end)| start..end);
+        (Just(size), prop::collection::vec(range, 0..40))
+    })
+}
+
+/// A pad size and disjoint ranges inside it in random order, as a consistent state holds.
+fn disjoint() -> impl Strategy<Value = (usize, Vec<Range<usize>>)> {
+    (1..400usize).prop_flat_map(|size| {
+        let cuts = prop::collection::btree_set(0..=size, 0..40).prop_map(|cuts| {
+            let cuts: Vec<usize> = cuts.into_iter().collect();
+            cuts.chunks_exact(2).map(|pair| pair[0]..pair[1]).collect::<Vec<_>>()
+        });
+        (Just(size), cuts.prop_shuffle())
+    })
+}
+
+fn direction(size: usize) -> impl Strategy<Value = Option<Direction>> {
+    prop_oneof![
+        Just(None),
+        Just(Some(Direction::Front)),
+        Just(Some(Direction::Back)),
+        (0..=size, 0..=size + 10).prop_map(|(start, end)| Some(Direction::Range { start, end })),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn test_set_stays_normalized((_, used) in overlapping()) {
+        let set: IntervalSet = used.iter().cloned().collect();
+        let ranges: Vec<_> = set.iter().collect();
+        prop_assert!(ranges.iter().all(|r| !r.is_empty()));
+        // Sorted, and never touching: touching ranges would have been merged
+        prop_assert!(ranges.windows(2).all(|pair| pair[0].end < pair[1].start));
+        prop_assert_eq!(set.covered(), ranges.iter().map(ExactSizeIterator::len).sum::<usize>());
+        prop_assert_eq!(set.len(), ranges.len());
+        // Every inserted offset is covered, whatever the insertion order
+        prop_assert!(used.iter().all(|r| set.overlaps(r) && ranges.iter().any(|c| c.start <= r.start && r.end <= c.end)));
+        prop_assert_eq!(&used.iter().rev().cloned().collect::<IntervalSet>(), &set);
+    }
+
+    #[test]
+    fn test_insert_reports_new_offsets((_, used) in overlapping(), extra in 0..450usize, length in 0..60usize) {
+        let mut set: IntervalSet = used.iter().cloned().collect();
+        let before = set.covered();
+        let added = set.insert(extra..extra + length);
+        prop_assert_eq!(set.covered(), before + added);
+        prop_assert_eq!(set.insert(extra..extra + length), 0);
+    }
+
+    #[test]
+    fn test_gaps_match_reference_from_both_ends((size, used) in overlapping(), start in 0..450usize, end in 0..450usize) {
+        let set: IntervalSet = used.iter().cloned().collect();
+        let expected: Vec<_> = reference_free_ranges(&used, size)
+            .into_iter()
+            .map(|free| free.start.max(start)..free.end.min(end))
+            .filter(|r| !r.is_empty())
+            .collect();
+        let window = start.min(size)..end.min(size);
+        prop_assert_eq!(&set.gaps(window.clone()).collect::<Vec<_>>(), &expected);
+        let mut backwards: Vec<_> = set.gaps(window.clone()).rev().collect();
+        backwards.reverse();
+        prop_assert_eq!(&backwards, &expected);
+        // Taking from alternating ends meets in the middle without losing a gap
+        let mut gaps = set.gaps(window);
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        while let Some(gap) = gaps.next() {
+            front.push(gap);
+            back.extend(gaps.next_back());
+        }
+        front.extend(back.into_iter().rev());
+        prop_assert_eq!(front, expected);
+    }
+
+    #[test]
+    fn test_pad_queries_match_reference((size, used) in overlapping(), start in 0..450usize, length in 0..60usize) {
+        let end = start + length;
+        let pad = pad_with(size, None, &used);
+        prop_assert_eq!(pad.free_ranges(), reference_free_ranges(&used, size));
+        let reference_available = end <= size && used.iter().all(|s| s.end <= start || s.start >= end);
+        prop_assert_eq!(pad.is_range_available(start, end), reference_available);
+        let free: usize = pad.free_ranges().iter().map(ExactSizeIterator::len).sum();
+        prop_assert_eq!(pad.total_used_bytes(), size - free);
+    }
+
+    #[test]
+    fn test_allocation_matches_reference((size, used) in disjoint(), direction in direction(400), length in 0..120usize) {
+        let pad = pad_with(size, direction, &used);
+        prop_assert_eq!(pad.total_used_bytes(), used.iter().map(ExactSizeIterator::len).sum::<usize>());
+        prop_assert_eq!(pad.find_available_segment(length), reference_find(&pad, &used, length));
+        prop_assert_eq!(pad.allocatable_ranges(), reference_allocatable(&pad, &used));
+    }
+
+    #[test]
+    fn test_compact_serialization_round_trips((_, used) in overlapping()) {
+        let set: IntervalSet = used.iter().cloned().collect();
+        let json = serde_json::to_value(&set).unwrap();
+        let pairs: Vec<[usize; 2]> = set.iter().map(|r| [r.start, r.end]).collect();
+        prop_assert_eq!(&json, &serde_json::json!(pairs));
+        prop_assert_eq!(serde_json::from_value::<IntervalSet>(json).unwrap(), set);
+    }
+}
+
+#[test]
+fn test_state_files_without_used_bytes_still_guard_the_history() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let pad = r#"{"id": "p", "file_name": "p.pad", "size": 100, "is_fully_used": false,
+        "used_segments": [{"start": 0, "end": 10}, {"start": 5, "end": 20}, {"start": 50, "end": 60}]}"#;
+    std::fs::write(temp_dir.path().join("vault_state.json"), format!(r#"{{"pads": {{"p": {pad}}}}}"#)).expect("Failed to write state");
+    let state = otp_core::state_manager::load_state(temp_dir.path()).expect("Failed to load state");
+    let pad = &state.pads["p"];
+    assert_eq!(pad.used.iter().collect::<Vec<_>>(), [0..20, 50..60]);
+    assert_eq!(pad.total_used_bytes(), 30);
+    assert_eq!(pad.find_available_segment(30), Some(20));
+    assert_eq!(pad.find_available_segment(31), Some(60));
+
+    let saved = serde_json::to_value(pad).expect("Failed to serialize pad");
+    assert_eq!(saved["used"], serde_json::json!([[0, 20], [50, 60]]));
+    assert!(serde_json::from_str::<IntervalSet>("[[5, 2]]").is_err());
+
+    // A backup's state goes through the same load path
+    let snapshot = otp_core::backup::snapshot_state(temp_dir.path()).expect("Failed to read state");
+    assert_eq!(snapshot.pads["p"].used, state.pads["p"].used);
+}
+
+#[test]
+fn test_history_is_kept_and_unexplained_used_bytes_are_reuse() {
+    let count = 1500;
+    let mut pad = Pad { id: "p".to_string(), size: 10 * count, ..Default::default() };
+    for index in 0..count {
+        pad.record_use(UsedSegment { origin: Some(Origin::Sent), ..(index * 10..index * 10 + 5).into() });
+    }
+    assert_eq!(pad.used_segments.len(), count);
+    assert_eq!(pad.total_used_bytes(), 5 * count);
+    assert_eq!(pad.find_available_segment(5), Some(5));
+    assert_eq!(pad.incoming_collisions(0, 14, true), vec![0..5, 10..14]);
+
+    // Older versions dropped the oldest segments but kept their bytes used
+    pad.used_segments.drain(..count - 1000);
+    let reloaded: Pad = serde_json::from_value(serde_json::to_value(&pad).unwrap()).unwrap();
+    assert_eq!(reloaded.used, pad.used);
+    pad.reindex();
+    assert_eq!(pad.used, reloaded.used);
+    assert!(!pad.is_range_available(0, 5));
+    assert_eq!(pad.incoming_collisions(0, 14, true), vec![0..5, 10..14]);
+    assert_eq!(pad.incoming_collisions(0, 14, false), vec![0..5, 10..14], "the sender of a dropped segment is unknown");
+    assert!(pad.incoming_collisions(5, 10, true).is_empty());
+    assert_eq!(otp_core::sync::used_ranges(&pad).len(), count);
+}
@@ -0,0 +1,91 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use serde_json::Value;
+use tempfile::tempdir;
+
+fn otp_json(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--output-format").arg("json").arg("--vault").arg(vault);
+    command
+}
+
+/// Runs `command`, checks its exit code and returns the single JSON document it printed.
+fn run(command: &mut Command, code: i32) -> Value {
+    let output = command.output().expect("Failed to run otp-cli");
+    assert_eq!(output.status.code(), Some(code), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).expect("stdout is one JSON document")
+}
+
+#[test]
+fn test_commands_report_results_as_json() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    let init = run(otp_json(&vault).arg("vault").arg("init"), 0);
+    assert_eq!(init["ok"], true);
+    assert_eq!(init["command"], "vault init");
+    assert_eq!(init["errors"], Value::Array(Vec::new()));
+
+    let generated = run(otp_json(&vault).arg("pad").arg("generate").arg("--size").arg("1").arg("--label").arg("work"), 0);
+    let pad_id = generated["result"]["pad_ids"][0].as_str().expect("A pad ID").to_string();
+    assert_eq!(generated["result"]["pads"][0]["labels"][0], "work");
+
+    let encrypted = run(otp_json(&vault).arg("encrypt").arg("--text").arg("hello"), 0);
+    assert_eq!(encrypted["result"]["pad_id"], pad_id.as_str());
+    assert_eq!(encrypted["result"]["auto_selected"], true);
+    assert_eq!(encrypted["result"]["start_byte"], 0);
+    let armored = encrypted["result"]["output"].as_str().expect("Armored ciphertext inline");
+    assert!(armored.starts_with("-----BEGIN OTP MESSAGE-----"));
+    let message = temp_dir.path().join("message.asc");
+    std::fs::write(&message, armored).expect("Failed to write message");
+
+    let decrypted = run(otp_json(&vault).arg("decrypt").arg("--input").arg(&message).arg("-o").arg("-"), 0);
+    assert_eq!(decrypted["result"]["output"], "hello");
+    assert_eq!(decrypted["result"]["seq"], 1);
+
+    let listed = run(otp_json(&vault).arg("pad").arg("list"), 0);
+    let pad = &listed["result"]["pads"][0];
+    assert_eq!(pad["status"], "active");
+    assert_eq!(pad["remaining_bytes"].as_u64().unwrap() + encrypted["result"]["length"].as_u64().unwrap(), 1024 * 1024);
+
+    let status = run(otp_json(&vault).arg("vault").arg("status"), 0);
+    assert_eq!(status["result"]["pads"]["total"], 1);
+    assert_eq!(status["result"]["material"]["remaining"], pad["remaining_bytes"]);
+
+    // The format can also follow the subcommand, next to a command's own --output file
+    let file = temp_dir.path().join("again.asc");
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(&vault).arg("encrypt").arg("--text").arg("again").arg("--output").arg(&file).arg("--output-format").arg("json");
+    assert_eq!(run(&mut command, 0)["command"], "encrypt");
+    assert!(file.is_file());
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(&vault).arg("pad").arg("frobnicate").arg("--output-format=json");
+    assert_eq!(run(&mut command, 2)["exit_code"], 2);
+}
+
+#[test]
+fn test_failures_report_errors_and_exit_codes() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    run(otp_json(&vault).arg("vault").arg("init"), 0);
+
+    let missing = run(otp_json(&vault).arg("pad").arg("show").arg("nope"), 3);
+    assert_eq!(missing["ok"], false);
+    assert_eq!(missing["exit_code"], 3);
+    assert!(missing["errors"][0].as_str().unwrap().contains("'nope' not found"));
+
+    let no_pads = run(otp_json(&vault).arg("encrypt").arg("--text").arg("hello"), 4);
+    assert!(!no_pads["errors"].as_array().unwrap().is_empty());
+    assert_eq!(no_pads["result"]["output"], Value::Null);
+
+    let usage = run(otp_json(&vault).arg("pad").arg("frobnicate"), 2);
+    assert_eq!(usage["ok"], false);
+    assert!(usage["errors"][0].as_str().unwrap().contains("frobnicate"));
+
+    // Low material shows up as a warning rather than on stderr
+    let output = otp_json(&vault).arg("vault").arg("thresholds").arg("--warn-remaining").arg("1MB").output().expect("Failed to run otp-cli");
+    assert!(output.status.success() && output.stderr.is_empty());
+    let set: Value = serde_json::from_slice(&output.stdout).expect("stdout is one JSON document");
+    assert!(set["warnings"][0].as_str().unwrap().contains("pad material left"));
+}
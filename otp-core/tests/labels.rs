@@ -0,0 +1,85 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, PadQuery, PadSort, VaultState};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+fn ids(pads: &[&state_manager::Pad]) -> Vec<String> {
+    pads.iter().map(|pad| pad.id.clone()).collect()
+}
+
+#[test]
+fn test_query_filters_and_sorts_pads() {
+    let mut state = VaultState::default();
+    state.add_pad("b".to_string(), "b.pad".to_string(), 300);
+    state.import_pad("a".to_string(), "a.pad".to_string(), 200);
+    state.add_pad("c".to_string(), "c.pad".to_string(), 100);
+    for (id, used) in [("a", 0..10), ("b", 0..250), ("c", 0..100)] {
+        let pad = state.pads.get_mut(id).unwrap();
+        pad.record_use(used.into());
+        pad.is_fully_used = pad.is_fully_used();
+    }
+    state.pads.get_mut("a").unwrap().add_label("project-x").expect("Valid label");
+    state.pads.get_mut("c").unwrap().add_label("project-x").expect("Valid label");
+    assert!(state.pads["a"].imported_at.is_some() && state.pads["a"].created_at.is_none());
+
+    assert_eq!(ids(&state.query(&PadQuery::default())), ["a", "b", "c"]);
+    let remaining = PadQuery { sort: PadSort::Remaining, ..PadQuery::default() };
+    assert_eq!(ids(&state.query(&remaining)), ["a", "b", "c"]);
+    assert_eq!(ids(&state.query(&PadQuery { sort: PadSort::Size, ..PadQuery::default() })), ["b", "a", "c"]);
+    let project = PadQuery { label: Some("project-x".to_string()), available_only: true, sort: PadSort::Id };
+    assert_eq!(ids(&state.query(&project)), ["a"]);
+
+    let pad = state.pads.get_mut("a").unwrap();
+    assert!(pad.add_label("has space").is_err() && pad.add_label("a,b").is_err());
+    pad.add_label("alpha").expect("Valid label");
+    pad.add_label("alpha").expect("Valid label");
+    assert_eq!(pad.labels, ["alpha", "project-x"]);
+    assert!(pad.remove_label("alpha") && !pad.remove_label("alpha"));
+}
+
+#[test]
+fn test_cli_labels_notes_and_list_filters() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").arg("--label").arg("project-x").arg("--note").arg("copy in the safe").output().expect("Failed to generate pad");
+    let tagged = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let output = otp(&vault).arg("pad").arg("generate").arg("--size").arg("2").output().expect("Failed to generate pad");
+    let other = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    otp(&vault).arg("pad").arg("generate").arg("--label").arg("bad label").assert().failure();
+
+    otp(&vault)
+        .arg("pad").arg("list").arg("--label").arg("project-x")
+        .assert().success()
+        .stdout(predicate::str::contains(&tagged).and(predicate::str::contains(&other).not()));
+    let output = otp(&vault).arg("pad").arg("list").arg("--sort").arg("remaining").output().expect("Failed to list pads");
+    let listing = String::from_utf8(output.stdout).expect("Listing is UTF-8");
+    assert!(listing.find(&other) < listing.find(&tagged), "the larger pad comes first:\n{listing}");
+
+    otp(&vault).arg("pad").arg("label").arg(&other).arg("--add").arg("project-x").arg("--add").arg("spare").assert().success();
+    otp(&vault).arg("pad").arg("label").arg(&tagged).arg("--remove").arg("project-x").assert().success().stdout(predicate::str::contains("none"));
+    otp(&vault).arg("pad").arg("label").arg(&tagged).assert().failure();
+    otp(&vault).arg("pad").arg("retire").arg(&other).assert().success();
+    otp(&vault)
+        .arg("pad").arg("list").arg("--label").arg("project-x").arg("--available-only")
+        .assert().success()
+        .stdout(predicate::str::contains("match the filter"));
+
+    otp(&vault)
+        .arg("pad").arg("show").arg(&tagged)
+        .assert().success()
+        .stdout(predicate::str::contains("Note:        copy in the safe").and(predicate::str::contains("Created:")));
+    otp(&vault).arg("pad").arg("note").arg(&tagged).assert().success();
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads[&tagged].note, None);
+    assert_eq!(state.pads[&other].labels, ["project-x", "spare"]);
+}
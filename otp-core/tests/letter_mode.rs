@@ -0,0 +1,81 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::letters::{self, Table};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_letter_tables_roundtrip() {
+    let pad_bytes: Vec<u8> = (0..=255u8).cycle().take(2000).collect();
+
+    // DIANA is reciprocal and spells out digits.
+    let symbols = letters::encode_plaintext(Table::Diana, "Meet at 9").expect("Text should encode");
+    let (key, consumed) = letters::key_stream(Table::Diana, &pad_bytes, symbols.len()).expect("Enough pad");
+    assert_eq!(consumed, symbols.len(), "Bytes below 234 are never rejected");
+    let ciphertext = letters::encrypt(Table::Diana, &symbols, &key);
+    let decrypted = letters::decrypt(Table::Diana, &ciphertext, &key);
+    assert_eq!(letters::decode_plaintext(Table::Diana, &decrypted), "MEETXATXNINE");
+
+    // The checkerboard keeps spaces and figures.
+    let symbols = letters::encode_plaintext(Table::Checkerboard, "Meet at 9").expect("Text should encode");
+    let (key, _) = letters::key_stream(Table::Checkerboard, &pad_bytes, symbols.len()).expect("Enough pad");
+    let grouped = letters::format_groups(Table::Checkerboard, &letters::encrypt(Table::Checkerboard, &symbols, &key));
+    let parsed = letters::parse_groups(Table::Checkerboard, &grouped).expect("Groups should parse");
+    let decrypted = letters::decrypt(Table::Checkerboard, &parsed, &key);
+    assert_eq!(letters::decode_plaintext(Table::Checkerboard, &decrypted), "MEET AT 9");
+}
+
+#[test]
+fn test_key_stream_rejects_biased_bytes() {
+    // 234..=255 would bias A-V for DIANA; 250..=255 would bias 0-5 for digits.
+    let pad_bytes = [233, 234, 255, 25, 100, 250];
+    let (key, consumed) = letters::key_stream(Table::Diana, &pad_bytes, 3).expect("Enough pad");
+    assert_eq!(key, vec![233 % 26, 25, 100 % 26]);
+    assert_eq!(consumed, 5);
+    let (key, consumed) = letters::key_stream(Table::Checkerboard, &pad_bytes, 3).expect("Enough pad");
+    assert_eq!(key, vec![3, 4, 5]);
+    assert_eq!(consumed, 4);
+}
+
+#[test]
+fn test_letter_mode_cli_roundtrip() {
+    // 1. Setup
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault_path = temp_dir.path().join("my_test_vault");
+    let input_path = temp_dir.path().join("letter.txt");
+    fs::write(&input_path, "attack at dawn\n").expect("Failed to write input file");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("vault").arg("init")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("pad").arg("generate")
+        .assert().success();
+
+    // 2. Encrypt into five-letter groups
+    let encrypted_path = temp_dir.path().join("letter.enc");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg(&input_path)
+        .arg("--output").arg(&encrypted_path)
+        .arg("--mode").arg("letters")
+        .assert().success()
+        .stdout(predicate::str::contains("--key-sheet letters"));
+    let ciphertext = fs::read_to_string(&encrypted_path).expect("Failed to read ciphertext");
+    assert!(ciphertext.trim().split(' ').all(|g| g.len() <= 5 && g.chars().all(|c| c.is_ascii_uppercase())));
+
+    // 3. Decrypt using the metadata
+    let decrypted_path = temp_dir.path().join("letter.dec");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg(&encrypted_path)
+        .arg("--output").arg(&decrypted_path)
+        .arg("--metadata").arg(temp_dir.path().join("letter.enc.metadata.json"))
+        .assert().success();
+    let decrypted = fs::read_to_string(&decrypted_path).expect("Failed to read decrypted file");
+    assert_eq!(decrypted.trim(), "ATTACKXATXDAWN");
+}
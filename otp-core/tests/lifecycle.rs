@@ -0,0 +1,109 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Pad, PadStatus, Retirement, EXPIRY_WARNING};
+use otp_core::{audit, timestamp};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+fn generate(vault: &Path, extra: &[&str]) -> String {
+    let output = otp(vault).arg("pad").arg("generate").args(extra).output().expect("Failed to generate pad");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string()
+}
+
+#[test]
+fn test_status_follows_expiry_and_retirement() {
+    let now = 1_000_000_000;
+    let mut pad = Pad { id: "p".to_string(), size: 100, ..Pad::default() };
+    assert_eq!(pad.status_at(now), PadStatus::Active);
+
+    pad.expires_at = Some(now + EXPIRY_WARNING + 1);
+    assert_eq!(pad.status_at(now), PadStatus::Active);
+    pad.expires_at = Some(now + EXPIRY_WARNING);
+    assert_eq!(pad.status_at(now), PadStatus::Expiring);
+    assert!(pad.status_at(now).can_encrypt() && pad.encrypt_refusal(now).is_none());
+    pad.expires_at = Some(now);
+    assert_eq!(pad.status_at(now), PadStatus::Retired);
+    assert!(pad.encrypt_refusal(now).expect("Expired").contains("expired"));
+
+    pad.expires_at = None;
+    pad.retirement = Some(Retirement { at: now, compromised: false, reason: Some("rotated".to_string()) });
+    assert_eq!(pad.status_at(now), PadStatus::Retired);
+    pad.retirement = Some(Retirement { at: now, compromised: true, reason: None });
+    assert_eq!(pad.status_at(now), PadStatus::Compromised);
+    assert!(!pad.status_at(now).can_encrypt());
+
+    pad.retirement = None;
+    pad.quarantined = Some("reuse".to_string());
+    assert_eq!(pad.status_at(now), PadStatus::Compromised);
+}
+
+#[test]
+fn test_retired_and_revoked_pads_only_decrypt() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let pad_id = generate(&vault, &[]);
+    let message = temp_dir.path().join("message.asc");
+    otp(&vault).arg("encrypt").arg("--text").arg("before").arg("-o").arg(&message).arg("--encoding").arg("armor").assert().success();
+
+    otp(&vault).arg("pad").arg("retire").arg(&pad_id).arg("--reason").arg("rotated").assert().success();
+    otp(&vault)
+        .arg("encrypt").arg("--text").arg("after").arg("--pad-id").arg(&pad_id).arg("-o").arg(temp_dir.path().join("after.bin"))
+        .assert().failure()
+        .stderr(predicate::str::contains("retired").and(predicate::str::contains("rotated")));
+    // Automatic selection skips it too
+    otp(&vault).arg("encrypt").arg("--text").arg("after").arg("-o").arg(temp_dir.path().join("after.bin")).assert().failure();
+    otp(&vault)
+        .arg("decrypt").arg("--input").arg(&message).arg("-o").arg(temp_dir.path().join("before.txt"))
+        .assert().success()
+        .stdout(predicate::str::contains("WARNING").and(predicate::str::contains("is retired")));
+
+    otp(&vault).arg("pad").arg("revoke").arg(&pad_id).assert().failure().stderr(predicate::str::contains("--reason"));
+    otp(&vault).arg("pad").arg("revoke").arg(&pad_id).arg("--reason").arg("laptop stolen").assert().success();
+    otp(&vault)
+        .arg("decrypt").arg("--input").arg(&message).arg("-o").arg(temp_dir.path().join("again.txt")).arg("--allow-replay")
+        .assert().success()
+        .stdout(predicate::str::contains("is compromised").and(predicate::str::contains("laptop stolen")));
+    otp(&vault).arg("pad").arg("retire").arg(&pad_id).assert().failure().stderr(predicate::str::contains("already revoked"));
+    otp(&vault).arg("pad").arg("list").assert().success().stdout(predicate::str::contains("compromised"));
+
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads[&pad_id].status(), PadStatus::Compromised);
+    let ops: Vec<_> = audit::read(&vault).expect("Failed to read log").into_iter().map(|entry| entry.record.op).collect();
+    assert!(ops.contains(&audit::Operation::Retire) && ops.contains(&audit::Operation::Revoke));
+}
+
+#[test]
+fn test_expiry_dates_warn_then_stop_encryption() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let soon = timestamp::format_utc(timestamp::now() + 2 * 24 * 60 * 60);
+    let pad_id = generate(&vault, &["--expires", &soon[..10]]);
+    otp(&vault)
+        .arg("encrypt").arg("--text").arg("hello").arg("-o").arg(temp_dir.path().join("hello.bin"))
+        .assert().success()
+        .stderr(predicate::str::contains(format!("pad '{pad_id}' expires at")));
+
+    otp(&vault).arg("pad").arg("retire").arg(&pad_id).arg("--on").arg("2000-01-01").assert().success();
+    otp(&vault)
+        .arg("encrypt").arg("--text").arg("hello").arg("--pad-id").arg(&pad_id).arg("-o").arg(temp_dir.path().join("late.bin"))
+        .assert().failure()
+        .stderr(predicate::str::contains("expired at 2000-01-01"));
+    otp(&vault).arg("pad").arg("show").arg(&pad_id).assert().success().stdout(predicate::str::contains("Status:      retired"));
+
+    // Dates too far out are usage errors, not crashes
+    let far = "999999999999999-01-01";
+    otp(&vault).arg("pad").arg("generate").arg("--expires").arg(far).assert().code(2).stderr(predicate::str::contains("between 1970 and 9999"));
+    otp(&vault).arg("pad").arg("retire").arg(&pad_id).arg("--on").arg(far).assert().code(2);
+    otp(&vault).arg("vault").arg("log").arg("--since").arg(far).assert().code(2);
+}
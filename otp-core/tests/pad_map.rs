@@ -0,0 +1,60 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::pad_map::{BarStyle, PadMap};
+use otp_core::state_manager::{Direction, Pad};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_map_lists_free_ranges_and_draws_them() {
+    let mut pad = Pad { id: "p".to_string(), size: 100, ..Pad::default() };
+    for used in [0..10, 40..45, 42..60, 90..100] {
+        pad.record_use(used.into());
+    }
+    let map = PadMap::of(&pad);
+    assert_eq!(map.free_ranges, [10..40, 60..90]);
+    assert_eq!((map.used_bytes, map.free_bytes, map.largest_gap), (40, 60, 30));
+    // Ties go to the earlier range
+    assert_eq!(map.largest_free_range(), Some(10..40));
+    assert_eq!(map.usable_ranges, map.free_ranges);
+
+    assert_eq!(map.bar(10, BarStyle::Ascii), "#...##...#");
+    assert_eq!(map.bar(4, BarStyle::Ascii), "++++");
+    assert_eq!(map.bar(4, BarStyle::Unicode), "▒▒▒▒");
+    // Never more cells than bytes
+    assert_eq!(map.bar(1000, BarStyle::Ascii).len(), 100);
+
+    pad.direction = Some(Direction::Range { start: 0, end: 50 });
+    assert_eq!(PadMap::of(&pad).usable_ranges, std::iter::once(10..40).collect::<Vec<_>>());
+    assert_eq!(PadMap::of(&Pad { size: 8, ..Pad::default() }).bar(4, BarStyle::Ascii), "....");
+}
+
+#[test]
+fn test_cli_pad_map_and_largest_gap_column() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("0..524288").arg("--reason").arg("test").assert().success();
+
+    otp(&vault)
+        .arg("pad").arg("map").arg(&pad_id).arg("--width").arg("8").arg("--ascii")
+        .assert().success()
+        .stdout(predicate::str::contains("[####....]").and(predicate::str::contains("Largest gap: 524288 bytes at 524288..1048576")));
+    otp(&vault).arg("pad").arg("list").assert().success().stdout(predicate::str::contains("Largest Gap"));
+    otp(&vault).arg("pad").arg("map").arg("nope").assert().code(3);
+
+    let output = otp(&vault).arg("--output-format").arg("json").arg("pad").arg("map").arg(&pad_id).output().expect("Failed to map pad");
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).expect("stdout is one JSON document");
+    assert_eq!(document["result"]["map"]["free_ranges"], serde_json::json!([{ "start": 524_288, "end": 1_048_576 }]));
+    assert_eq!(document["result"]["map"]["largest_gap"], 524_288);
+}
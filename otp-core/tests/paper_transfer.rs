@@ -0,0 +1,135 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::paper::{self, Encoding, Sharing};
+use tempfile::tempdir;
+
+#[test]
+fn test_paper_roundtrip_and_typo_detection() {
+    let data: Vec<u8> = (0..100u8).collect();
+
+    for encoding in [Encoding::Hex, Encoding::Base32] {
+        let text = paper::render_section("pad-1", 100, 0, &data, encoding, &Sharing::default()).join("\n");
+        let sections = paper::parse_sections(&text).expect("Clean text should parse");
+        let (pad_id, pad) = paper::assemble_pad(&sections).expect("Sections should cover the pad");
+        assert_eq!(pad_id, "pad-1");
+        assert_eq!(pad, data);
+    }
+
+    // Corrupt the first data character on the third data line (line 4 of the text).
+    let mut lines = paper::render_section("pad-1", 100, 0, &data, Encoding::Hex, &Sharing::default());
+    let typo = if lines[3].as_bytes()[14] == b'f' { "e" } else { "f" };
+    lines[3].replace_range(14..15, typo);
+    let errors = paper::parse_sections(&lines.join("\n")).expect_err("Typo should be detected");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line_number, 4);
+    assert!(errors[0].message.contains("checksum mismatch"));
+
+    // A character outside the alphabet is pinned to its column.
+    let mut lines = paper::render_section("pad-1", 100, 0, &data, Encoding::Hex, &Sharing::default());
+    lines[2].replace_range(15..16, "g");
+    let errors = paper::parse_sections(&lines.join("\n")).expect_err("Bad character should be detected");
+    assert_eq!(errors[0].line_number, 3);
+    assert!(errors[0].message.contains("column 16: 'g' is not a valid hex character"));
+
+    // Sizes claimed by the text are checked against the data before anything is allocated
+    let errors = paper::parse_sections("# OTP-PAD v1 id=x size=999999999999 range=0..999999999999 encoding=hex\n# END sha256=00")
+        .expect_err("Oversized range should be refused");
+    assert!(errors[0].message.contains("longer than the text"));
+    let section = paper::Section { pad_id: "x".to_string(), pad_size: usize::MAX, start: 0, data, sharing: Sharing::default() };
+    let error = paper::assemble_pad(&[section]).expect_err("Partial pad should be refused");
+    assert!(error.contains("bytes 100.."));
+}
+
+#[test]
+fn test_print_and_import_pad_between_vaults() {
+    // 1. Setup two vaults
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let sender_vault = temp_dir.path().join("sender_vault");
+    let receiver_vault = temp_dir.path().join("receiver_vault");
+    for vault in [&sender_vault, &receiver_vault] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("vault").arg("init")
+            .assert().success();
+    }
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&sender_vault)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+
+    // 2. Print the pad in QR-sized sections and import them on the other side
+    let qr_dir = temp_dir.path().join("qr");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&sender_vault)
+        .arg("pad").arg("qr").arg(&pad_id)
+        .arg("--range").arg("0..64")
+        .arg("--out-dir").arg(&qr_dir)
+        .assert().success();
+    assert_eq!(fs::read_dir(&qr_dir).expect("Failed to read QR dir").count(), 1);
+
+    let printout = temp_dir.path().join("pad.txt");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&sender_vault)
+        .arg("pad").arg("print").arg(&pad_id)
+        .arg("--output").arg(&printout)
+        .assert().success();
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&receiver_vault)
+        .arg("pad").arg("import")
+        .arg("--from-text").arg(&printout)
+        .assert().success();
+
+    // 3. Both vaults hold identical pad bytes
+    let file_name = format!("{pad_id}.pad");
+    let sent = fs::read(sender_vault.join("pads/available").join(&file_name)).expect("Failed to read sender pad");
+    let received = fs::read(receiver_vault.join("pads/available").join(&file_name)).expect("Failed to read receiver pad");
+    assert_eq!(sent, received);
+}
+
+#[test]
+fn test_import_pad_from_qr_images() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("vault").arg("init")
+        .assert().success();
+
+    // Images of frames rendered the way `pad qr` renders them, handed over out of order
+    let pad: Vec<u8> = (0..1000u32).map(|i| u8::try_from(i * 7 % 251).unwrap()).collect();
+    let frames = paper::render_qr_frames("qr-pad", pad.len(), 0, &pad, Encoding::Base32, 8, &Sharing::default());
+    let mut images = Vec::new();
+    for (index, frame) in frames.iter().enumerate().rev() {
+        let code = qrcode::QrCode::new(frame.as_bytes()).expect("Frame should fit a QR code");
+        let path = temp_dir.path().join(format!("frame-{index}.png"));
+        code.render::<image::Luma<u8>>().min_dimensions(400, 400).build().save(&path).expect("Failed to write PNG");
+        images.push(path);
+    }
+
+    // A code covering part of the pad is refused rather than imported with a hole
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("import")
+        .arg("--from-qr-images").arg(&images[0])
+        .assert().code(7).stderr(predicates::str::contains("A printout of part of a pad cannot be imported"));
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("import")
+        .arg("--from-qr-images").args(&images)
+        .assert().success().stdout("qr-pad\n");
+    assert_eq!(fs::read(vault.join("pads/available/qr-pad.pad")).expect("Failed to read pad"), pad);
+
+    // An image without a QR code is named, and nothing is imported
+    let blank = temp_dir.path().join("blank.png");
+    image::GrayImage::from_pixel(200, 200, image::Luma([255])).save(&blank).expect("Failed to write PNG");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault)
+        .arg("pad").arg("import")
+        .arg("--from-qr-images").arg(&blank)
+        .assert().code(7).stderr(predicates::str::contains("blank.png: no QR code found"));
+}
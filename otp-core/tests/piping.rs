@@ -0,0 +1,104 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::armor;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_armor_roundtrip() {
+    let body: Vec<u8> = (0..=255u8).collect();
+    let text = armor::armor(&[("Pad-Id", "pad-1".to_string())], &body);
+    assert!(armor::is_armored(text.as_bytes()));
+    let (headers, decoded) = armor::dearmor(&format!("> quoted reply\n{text}\n-- \nsignature")).expect("Armor should parse");
+    assert_eq!(headers, vec![("Pad-Id".to_string(), "pad-1".to_string())]);
+    assert_eq!(decoded, body);
+}
+
+#[test]
+fn test_stdin_to_armored_stdout_roundtrip() {
+    // 1. Setup
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault_path = temp_dir.path().join("my_test_vault");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("vault").arg("init")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("pad").arg("generate")
+        .assert().success();
+
+    // 2. Encrypt piped input; only the armored message goes to stdout
+    let encrypted = assert_cmd::Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg("-")
+        .write_stdin("piped secret\n")
+        .output().expect("Failed to run encrypt");
+    assert!(encrypted.status.success());
+    assert!(armor::is_armored(&encrypted.stdout));
+
+    // 3. A second message never reuses the same pad bytes
+    let second = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg("--text").arg("another")
+        .output().expect("Failed to run encrypt");
+    let start_of = |message: &[u8]| {
+        let (headers, _) = armor::dearmor(&String::from_utf8_lossy(message)).expect("Armor should parse");
+        headers.into_iter().find(|(key, _)| key == "Start-Byte").map(|(_, value)| value).expect("Start-Byte header")
+    };
+    assert_ne!(start_of(&encrypted.stdout), start_of(&second.stdout));
+
+    // 4. Decrypt from stdin to stdout without a metadata file
+    assert_cmd::Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg("-")
+        .arg("--output").arg("-")
+        .write_stdin(encrypted.stdout)
+        .assert().success()
+        .stdout(predicate::eq("piped secret\n"));
+}
+
+#[test]
+fn test_text_input_with_hex_output() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault_path = temp_dir.path().join("my_test_vault");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("vault").arg("init")
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("pad").arg("generate")
+        .assert().success();
+
+    // Non-armored output to stdout needs somewhere to put the metadata.
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg("--text").arg("hello")
+        .arg("--encoding").arg("hex")
+        .assert().failure();
+
+    let encrypted_path = temp_dir.path().join("hello.hex");
+    let metadata_path = temp_dir.path().join("hello.json");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("encrypt").arg("--text").arg("hello")
+        .arg("--encoding").arg("hex")
+        .arg("--output").arg(&encrypted_path)
+        .arg("--metadata-out").arg(&metadata_path)
+        .assert().success();
+    let hex = fs::read_to_string(&encrypted_path).expect("Failed to read ciphertext");
+    assert!(hex.trim().chars().all(|c| c.is_ascii_hexdigit()));
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&vault_path)
+        .arg("decrypt")
+        .arg("--input").arg(&encrypted_path)
+        .arg("--output").arg("-")
+        .arg("--metadata").arg(&metadata_path)
+        .assert().success()
+        .stdout(predicate::eq("hello"));
+}
@@ -0,0 +1,70 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Origin, SegmentOperation, UsedSegment};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_old_segments_load_without_provenance() {
+    let segment: UsedSegment = serde_json::from_str(r#"{"start":0,"end":10}"#).expect("Old segment parses");
+    assert_eq!((segment.start, segment.end), (0, 10));
+    assert!(segment.used_at.is_none() && segment.operation.is_none() && segment.digest.is_none());
+    assert_eq!(serde_json::to_string(&segment).expect("Serializes"), r#"{"start":0,"end":10}"#);
+
+    let burned = UsedSegment::consumed(10..20, SegmentOperation::Burn);
+    assert!(burned.used_at.is_some() && burned.origin.is_none());
+    assert_eq!(UsedSegment::consumed(0..1, SegmentOperation::Decrypt).origin, Some(Origin::Received));
+}
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_segments_record_operation_message_and_counterparty() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let alice = temp_dir.path().join("alice");
+    let bob = temp_dir.path().join("bob");
+    for vault in [&alice, &bob] {
+        otp(vault).arg("vault").arg("init").assert().success();
+    }
+    let output = otp(&alice).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let printout = temp_dir.path().join("pad.txt");
+    otp(&alice).arg("pad").arg("print").arg(&pad_id).arg("--output").arg(&printout).assert().success();
+    otp(&bob).arg("pad").arg("import").arg("--from-text").arg(&printout).assert().success();
+    otp(&alice).arg("contact").arg("add").arg("bob").arg("--pad").arg(&pad_id).assert().success();
+
+    let message = temp_dir.path().join("message.asc");
+    otp(&alice).arg("encrypt").arg("--text").arg("hi bob").arg("--to").arg("bob").arg("-o").arg(&message).arg("--encoding").arg("armor").assert().success();
+    otp(&bob).arg("decrypt").arg("-i").arg(&message).arg("-o").arg(temp_dir.path().join("plain.txt")).assert().success();
+
+    let sent = state_manager::load_state(&alice).expect("Failed to load state").pads[&pad_id].used_segments[0].clone();
+    let received = state_manager::load_state(&bob).expect("Failed to load state").pads[&pad_id].used_segments[0].clone();
+    assert_eq!((sent.operation, received.operation), (Some(SegmentOperation::Encrypt), Some(SegmentOperation::Decrypt)));
+    assert_eq!((sent.seq, received.seq), (Some(1), Some(1)));
+    assert!(sent.digest.is_some());
+    assert_eq!(sent.digest, received.digest);
+    assert_eq!(sent.counterparty.as_deref(), Some("bob"));
+    let alice_id = state_manager::load_state(&alice).expect("Failed to load state").vault_id;
+    assert_eq!(received.counterparty, Some(alice_id));
+
+    otp(&alice)
+        .arg("pad").arg("show").arg(&pad_id)
+        .assert().success()
+        .stdout(predicate::str::contains("Contact:     bob"))
+        .stdout(predicate::str::contains("encrypt").and(predicate::str::contains("message 1 with bob sha256")));
+    otp(&alice).arg("pad").arg("show").arg("no-such-pad").assert().failure();
+
+    // More used bytes than the pad holds, e.g. from a bad sync, show as nothing remaining
+    let mut state = state_manager::load_state(&bob).expect("Failed to load state");
+    let pad = state.pads.get_mut(&pad_id).unwrap();
+    let size = pad.size;
+    pad.record_use((0..size + 100).into());
+    state_manager::save_state(&bob, &state).expect("Failed to save state");
+    otp(&bob).arg("pad").arg("show").arg(&pad_id).assert().success().stdout(predicate::str::contains("Remaining:   0 bytes"));
+}
@@ -0,0 +1,89 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::envelope::{self, FileHeader};
+use otp_core::sequence::{Delivery, SequenceWindow, WINDOW};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_window_classifies_replays_gaps_and_reordering() {
+    let mut window = SequenceWindow::default();
+    assert_eq!(window.check(1), Delivery::InOrder);
+    window.accept(1);
+    assert_eq!(window.check(1), Delivery::Replay);
+    assert_eq!(window.check(4), Delivery::Gap { missing: 2..4 });
+    window.accept(4);
+    assert_eq!(window.check(3), Delivery::OutOfOrder);
+    window.accept(3);
+    assert_eq!(window.check(3), Delivery::Replay);
+    assert_eq!(window.check(2), Delivery::OutOfOrder);
+    assert_eq!(window.check(0), Delivery::Stale);
+
+    window.accept(4 + WINDOW);
+    assert_eq!(window.check(4), Delivery::Stale);
+    assert!(window.check(4).is_suspicious());
+    assert!(!window.check(5 + WINDOW).is_suspicious());
+}
+
+#[test]
+fn test_sequence_number_is_covered_by_the_digest() {
+    let header = FileHeader { sender: Some("vault-a".to_string()), seq: Some(7), ..FileHeader::default() };
+    let mut wrapped = Vec::new();
+    envelope::Wrap::new(&header, &b"hello"[..]).read_to_end(&mut wrapped).expect("Reading from memory succeeds");
+    let (unwrapped, payload) = envelope::unwrap(&wrapped).expect("Valid envelope").expect("Has envelope");
+    assert_eq!((unwrapped.seq, payload), (Some(7), &b"hello"[..]));
+
+    // Flipping the number without knowing the digest is detected
+    let position = wrapped.windows(7).position(|w| w == b"\"seq\":7").expect("Header holds the number") + 6;
+    wrapped[position] = b'8';
+    assert!(envelope::unwrap(&wrapped).is_err());
+}
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_receiver_flags_replayed_and_reordered_messages() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let sender = temp_dir.path().join("sender");
+    let receiver = temp_dir.path().join("receiver");
+    for vault in [&sender, &receiver] {
+        otp(vault).arg("vault").arg("init").assert().success();
+    }
+    let output = otp(&sender).arg("pad").arg("generate").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+    let printout = temp_dir.path().join("pad.txt");
+    otp(&sender).arg("pad").arg("print").arg(&pad_id).arg("--output").arg(&printout).assert().success();
+    otp(&receiver).arg("pad").arg("import").arg("--from-text").arg(&printout).assert().success();
+
+    let messages: Vec<_> = (1..=3)
+        .map(|n| {
+            let message = temp_dir.path().join(format!("message{n}.asc"));
+            otp(&sender).arg("encrypt").arg("--text").arg(format!("message {n}")).arg("-o").arg(&message).arg("--encoding").arg("armor").assert().success();
+            message
+        })
+        .collect();
+    let decrypt = |message: &Path| {
+        let mut command = otp(&receiver);
+        command.arg("decrypt").arg("-i").arg(message).arg("-o").arg(temp_dir.path().join("out.txt"));
+        command
+    };
+
+    decrypt(&messages[0]).assert().success().stdout(predicate::str::contains("WARNING").not());
+    decrypt(&messages[0]).assert().failure().stderr(predicate::str::contains("REPLAY DETECTED"));
+    decrypt(&messages[0]).arg("--allow-replay").assert().success();
+    decrypt(&messages[2]).assert().success().stdout(predicate::str::contains("before message(s) 2..=2"));
+    decrypt(&messages[1]).assert().success().stdout(predicate::str::contains("out of order"));
+    assert_eq!(fs::read_to_string(temp_dir.path().join("out.txt")).expect("Failed to read output"), "message 2");
+
+    // The sender reading its own messages again is not a replay
+    otp(&sender).arg("decrypt").arg("-i").arg(&messages[0]).arg("-o").arg(temp_dir.path().join("own.txt")).assert().success();
+    otp(&sender).arg("decrypt").arg("-i").arg(&messages[0]).arg("-o").arg(temp_dir.path().join("own2.txt")).assert().success();
+}
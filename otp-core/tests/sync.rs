@@ -0,0 +1,218 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, Origin, Pad, UsedSegment};
+use otp_core::sync::{self, UsageReceipt};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn pad_with(used: &[(usize, usize)]) -> Pad {
+    let mut pad = Pad { id: "pad-1".to_string(), file_name: "pad-1.pad".to_string(), size: 100, ..Default::default() };
+    for &(start, end) in used {
+        pad.record_use(UsedSegment::from(start..end));
+    }
+    pad
+}
+
+fn segments(ranges: &[std::ops::Range<usize>]) -> Vec<UsedSegment> {
+    ranges.iter().cloned().map(UsedSegment::from).collect()
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_merge_reports_overlaps_and_receipts_are_authenticated() {
+    // The receiver decrypted 0..10 (same message) and independently used 40..50.
+    let mut pad = pad_with(&[(0, 10), (40, 50)]);
+    let outcome = sync::merge(&mut pad, &segments(&[0..10, 20..30, 45..60])).expect("Ranges are inside the pad");
+    assert_eq!(outcome.added, vec![20..30, 50..60]);
+    assert_eq!(outcome.conflicts, vec![45..50]);
+    assert_eq!(pad.total_used_bytes(), 40);
+
+    // Only the local bytes the partner has not heard of remain to be exported.
+    assert_eq!(sync::pending_ranges(&pad), vec![40..45]);
+    assert!(sync::merge(&mut pad, &segments(&[60..70, 90..101])).is_err());
+
+    let key = sync::mac_key(b"shared pad bytes");
+    let mut receipt = UsageReceipt::new("pad-1", &segments(&[5..9, 0..5, 5..9]), &key);
+    assert_eq!(receipt.byte_ranges(), vec![0..5, 5..9], "messages keep their boundaries");
+    assert_eq!(receipt.used_bytes(), 9);
+    assert!(receipt.verify(&key));
+    assert!(!receipt.verify(&sync::mac_key(b"another pad")));
+    receipt.ranges[0].end = 8;
+    assert!(!receipt.verify(&key));
+    let mut receipt = UsageReceipt::new("pad-1", &[UsedSegment { digest: Some("ab".to_string()), ..(0..5).into() }], &key);
+    assert!(receipt.verify(&key));
+    receipt.ranges[0].digest = Some("cd".to_string());
+    assert!(!receipt.verify(&key));
+}
+
+#[test]
+fn test_merge_accepts_messages_received_from_the_partner() {
+    let message = |range: std::ops::Range<usize>, digest: &str| UsedSegment { digest: Some(digest.to_string()), ..range.into() };
+    let received = |range, digest| UsedSegment { origin: Some(Origin::Received), ..message(range, digest) };
+
+    // The partner sent two messages and this vault decrypted only the first
+    let mut pad = pad_with(&[]);
+    pad.record_use(received(0..30, "first"));
+    let outcome = sync::merge(&mut pad, &[message(0..30, "first"), message(30..60, "second")]).expect("Ranges are inside the pad");
+    assert!(outcome.conflicts.is_empty());
+    assert_eq!(outcome.added, vec![30..60]);
+    assert_eq!(pad.used_segments.last().and_then(|s| s.digest.as_deref()), Some("second"));
+
+    // Older receipts merged the two messages into one range
+    let mut pad = pad_with(&[]);
+    pad.record_use(received(0..30, "first"));
+    let outcome = sync::merge(&mut pad, &[(0..60).into()]).expect("Ranges are inside the pad");
+    assert!(outcome.conflicts.is_empty());
+    assert_eq!(outcome.added, vec![30..60]);
+
+    // A message this vault sent, or one with another digest, is still a conflict
+    let mut pad = pad_with(&[]);
+    pad.record_use(UsedSegment { origin: Some(Origin::Sent), ..message(0..30, "mine") });
+    pad.record_use(received(40..50, "third"));
+    let outcome = sync::merge(&mut pad, &[message(0..60, "theirs"), message(40..50, "other")]).expect("Ranges are inside the pad");
+    assert_eq!(outcome.conflicts, vec![0..30, 40..50]);
+
+    // Export sends one range per message, with its digest
+    let mut pad = pad_with(&[]);
+    pad.record_use(message(0..30, "first"));
+    pad.record_use(message(30..60, "second"));
+    pad.used.insert(80..90);
+    let pending = sync::pending_segments(&pad);
+    assert_eq!(pending.iter().map(|s| (s.start..s.end, s.digest.as_deref())).collect::<Vec<_>>(), vec![(0..30, Some("first")), (30..60, Some("second")), (80..90, None)]);
+    sync::mark_synced(&mut pad, std::slice::from_ref(&(0..30)));
+    assert_eq!(sync::pending_segments(&pad).len(), 2);
+    assert_eq!(sync::used_segments(&pad).len(), 3);
+}
+
+#[test]
+fn test_sync_between_two_vaults() {
+    // 1. Alice and Bob hold copies of the same pad
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let alice = temp_dir.path().join("alice");
+    let bob = temp_dir.path().join("bob");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("vault").arg("init")
+        .assert().success();
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).unwrap().trim().to_string();
+    copy_dir(&alice, &bob);
+
+    // 2. Alice sends a message that never arrives; Bob learns about it through sync
+    let input_path = temp_dir.path().join("lost.txt");
+    fs::write(&input_path, "lost in transit").unwrap();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("encrypt").arg(&input_path).arg("--pad-id").arg(&pad_id)
+        .assert().success();
+    let bundle = temp_dir.path().join("alice-usage.json");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("vault").arg("sync").arg("export").arg("--out").arg(&bundle)
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob)
+        .arg("vault").arg("sync").arg("import").arg(&bundle)
+        .assert().success();
+    let alice_state = state_manager::load_state(&alice).unwrap();
+    let bob_state = state_manager::load_state(&bob).unwrap();
+    assert_eq!(alice_state.pads[&pad_id].total_used_bytes(), bob_state.pads[&pad_id].total_used_bytes());
+
+    // 3. A tampered bundle is rejected
+    let tampered = fs::read_to_string(&bundle).unwrap().replacen("\"start\": 0", "\"start\": 1", 1);
+    fs::write(&bundle, tampered).unwrap();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob)
+        .arg("vault").arg("sync").arg("import").arg(&bundle)
+        .assert().failure()
+        .stderr(predicate::str::contains("failed authentication"));
+
+    // 4. Both encrypt at the same offset before syncing: the overlap is reported
+    let offset = alice_state.pads[&pad_id].total_used_bytes().to_string();
+    for (vault, text) in [(&alice, "from alice"), (&bob, "from bob, longer")] {
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(vault)
+            .arg("encrypt").arg("--text").arg(text)
+            .arg("--pad-id").arg(&pad_id).arg("--offset").arg(&offset)
+            .arg("--output").arg(temp_dir.path().join("msg.enc"))
+            .assert().success();
+    }
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("vault").arg("sync").arg("export").arg("--out").arg(&bundle)
+        .assert().success();
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob)
+        .arg("vault").arg("sync").arg("import").arg(&bundle)
+        .assert().failure()
+        .stderr(predicate::str::contains("PAD REUSE DETECTED"));
+}
+
+#[test]
+fn test_sync_after_decrypting_part_of_the_partners_messages() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let alice = temp_dir.path().join("alice");
+    let bob = temp_dir.path().join("bob");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("vault").arg("init")
+        .assert().success();
+    let generate_output = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("pad").arg("generate")
+        .output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(generate_output.stdout).unwrap().trim().to_string();
+    copy_dir(&alice, &bob);
+
+    // Alice sends two messages; Bob has decrypted only the first when her receipt arrives
+    let mut messages = Vec::new();
+    for text in ["first message", "second message"] {
+        let message = temp_dir.path().join(format!("{}.asc", messages.len()));
+        Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+            .arg("--vault").arg(&alice)
+            .arg("encrypt").arg("--text").arg(text)
+            .arg("--pad-id").arg(&pad_id)
+            .arg("--output").arg(&message).arg("--encoding").arg("armor")
+            .assert().success();
+        messages.push(message);
+    }
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob)
+        .arg("decrypt").arg("--input").arg(&messages[0])
+        .arg("-o").arg(temp_dir.path().join("first.txt"))
+        .assert().success();
+    let bundle = temp_dir.path().join("alice-usage.json");
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&alice)
+        .arg("vault").arg("sync").arg("export").arg("--out").arg(&bundle)
+        .assert().success();
+    let receipt: serde_json::Value = serde_json::from_slice(&fs::read(&bundle).unwrap()).unwrap();
+    assert_eq!(receipt["receipts"][0]["ranges"].as_array().map(Vec::len), Some(2), "one range per message");
+
+    Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary")
+        .arg("--vault").arg(&bob)
+        .arg("vault").arg("sync").arg("import").arg(&bundle)
+        .assert().success()
+        .stderr(predicate::str::contains("PAD REUSE").not());
+    let alice_state = state_manager::load_state(&alice).unwrap();
+    let bob_state = state_manager::load_state(&bob).unwrap();
+    assert_eq!(alice_state.pads[&pad_id].total_used_bytes(), bob_state.pads[&pad_id].total_used_bytes());
+    assert!(bob_state.pads[&pad_id].quarantined.is_none());
+}
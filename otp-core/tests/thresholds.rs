@@ -0,0 +1,96 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::thresholds::{self, Material, Thresholds, Warning};
+use otp_core::{audit, state_manager};
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_sizes_parse_and_thresholds_check() {
+    assert_eq!(thresholds::parse_size("10MB"), Ok(10 << 20));
+    assert_eq!(thresholds::parse_size("512 kb"), Ok(512 << 10));
+    assert_eq!(thresholds::parse_size("1G"), Ok(1 << 30));
+    assert_eq!(thresholds::parse_size("4096"), Ok(4096));
+    assert!(thresholds::parse_size("10 parsecs").is_err());
+    assert!(thresholds::parse_size("MB").is_err());
+    assert_eq!(thresholds::format_size(1536), "1.50 KB");
+    assert_eq!(thresholds::format_size(12), "12 bytes");
+
+    let limits = Thresholds { min_remaining: Some(100), min_largest_gap: Some(50), auto_generate: None };
+    assert!(limits.check(Material { remaining: 100, largest_gap: 50 }).is_empty());
+    assert_eq!(
+        limits.check(Material { remaining: 99, largest_gap: 10 }),
+        vec![Warning::Remaining { remaining: 99, threshold: 100 }, Warning::LargestGap { largest_gap: 10, threshold: 50 }]
+    );
+    assert!(Thresholds::default().check(Material::default()).is_empty());
+}
+
+#[test]
+fn test_local_pads_are_replenished_after_use() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    otp(&vault)
+        .arg("vault").arg("thresholds").arg("--warn-remaining").arg("2MB").arg("--auto-generate").arg("1MB")
+        .assert().success()
+        .stdout(predicate::str::contains("Auto-generate local pads: 1.00 MB"));
+
+    // The settings command itself already tops the empty vault up to the threshold
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads.len(), 2);
+    assert!(state.pads.values().all(|pad| pad.local && pad.size == 1 << 20));
+    let log = audit::read(&vault).expect("Failed to read log");
+    assert_eq!(log.iter().filter(|entry| entry.record.detail.as_deref() == Some("auto-generated")).count(), 2);
+
+    // Commands that only read the vault never generate pads, even with the thresholds crossed
+    let mut raised = state_manager::load_state(&vault).expect("Failed to load state");
+    raised.thresholds.min_remaining = Some(3 << 20);
+    state_manager::save_state(&vault, &raised).expect("Failed to save state");
+    otp(&vault).arg("pad").arg("list").assert().success().stderr(predicate::str::contains("Generated local pad").not());
+    otp(&vault).arg("vault").arg("status").assert().success().stdout(predicate::str::contains("WARNING: only 2.00 MB"));
+    otp(&vault).arg("vault").arg("log").assert().success();
+    assert_eq!(state_manager::load_state(&vault).expect("Failed to load state").pads.len(), 2);
+    state_manager::save_state(&vault, &state).expect("Failed to save state");
+
+    // Consuming local material generates a replacement; other pads never count as local
+    let local_pad = state.pads.keys().next().expect("A local pad").clone();
+    otp(&vault).arg("pad").arg("generate").assert().success();
+    otp(&vault)
+        .arg("encrypt").arg("--text").arg("dear diary").arg("--pad-id").arg(&local_pad).arg("-o").arg(temp_dir.path().join("diary.bin"))
+        .assert().success()
+        .stderr(predicate::str::contains("Generated local pad"));
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    assert_eq!(state.pads.values().filter(|pad| pad.local).count(), 3);
+    assert_eq!(state.pads.values().filter(|pad| !pad.local).count(), 1);
+}
+
+#[test]
+fn test_cli_warns_when_material_runs_low() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    otp(&vault).arg("pad").arg("generate").assert().success();
+    otp(&vault).arg("pad").arg("list").assert().success().stderr(predicate::str::contains("WARNING").not());
+
+    otp(&vault)
+        .arg("vault").arg("thresholds").arg("--warn-remaining").arg("4MB").arg("--warn-gap").arg("512KB")
+        .assert().success()
+        .stderr(predicate::str::contains("WARNING: only 1.00 MB of pad material left"));
+    otp(&vault).arg("pad").arg("list").assert().success().stderr(predicate::str::contains("WARNING").not());
+    otp(&vault)
+        .arg("vault").arg("status")
+        .assert().success()
+        .stdout(predicate::str::contains("Largest Message: 1.00 MB").and(predicate::str::contains("WARNING: only")));
+
+    otp(&vault).arg("vault").arg("thresholds").arg("--clear").assert().success().stdout(predicate::str::contains("Warn below remaining: -"));
+    assert!(state_manager::load_state(&vault).expect("Failed to load state").thresholds.is_empty());
+    otp(&vault).arg("pad").arg("list").assert().success().stderr(predicate::str::contains("WARNING").not());
+}
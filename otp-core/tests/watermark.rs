@@ -0,0 +1,115 @@
+#![allow(missing_docs, clippy::expect_used, clippy::unwrap_used)]
+use std::path::Path;
+use std::process::Command;
+use assert_cmd::prelude::*;
+use otp_core::state_manager::{self, AllocationMode, Direction, Pad};
+use otp_core::sync;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+fn otp(vault: &Path) -> Command {
+    let mut command = Command::cargo_bin("otp-cli").expect("Failed to find otp-cli binary");
+    command.arg("--vault").arg(vault);
+    command
+}
+
+#[test]
+fn test_watermark_never_allocates_below_itself() {
+    let mut pad = Pad { id: "p".to_string(), size: 100, ..Pad::default() };
+    pad.record_use((0..10).into());
+    pad.record_use((20..30).into());
+    // The gap before the last used byte is given up
+    assert_eq!(pad.convert(AllocationMode::Watermark), Ok(10));
+    assert_eq!((pad.next_offset, pad.total_used_bytes()), (Some(30), 30));
+    assert_eq!(pad.find_available_segment(5), Some(30));
+    assert!(!pad.is_range_available(10, 15));
+    assert_eq!(pad.free_ranges(), std::iter::once(30..100).collect::<Vec<_>>());
+
+    // A use above the watermark gives up the free bytes before it, and says which
+    assert_eq!(pad.record_use((40..50).into()), 30..40);
+    assert_eq!(pad.next_offset, Some(50));
+    assert_eq!(pad.used_segments.len(), 3, "uses are still recorded one by one");
+    assert!(pad.record_use((0..5).into()).is_empty());
+    assert_eq!(pad.next_offset, Some(50), "the watermark never moves down");
+    // The history keeps reuse detection working below the watermark
+    pad.used_segments[2].origin = Some(state_manager::Origin::Sent);
+    assert_eq!(pad.incoming_collisions(45, 60, true), vec![45..50]);
+    assert!(pad.incoming_collisions(30, 40, true).is_empty());
+    assert_eq!(sync::pending_ranges(&pad), std::iter::once(0..50).collect::<Vec<_>>());
+
+    // Switching back loses nothing and frees nothing
+    assert_eq!(pad.convert(AllocationMode::FreeRanges), Ok(0));
+    assert_eq!(pad.allocation_mode(), AllocationMode::FreeRanges);
+    assert_eq!(pad.free_ranges(), std::iter::once(50..100).collect::<Vec<_>>());
+    assert_eq!(pad.convert(AllocationMode::FreeRanges), Ok(0));
+
+    pad.direction = Some(Direction::Front);
+    assert!(pad.convert(AllocationMode::Watermark).is_err());
+}
+
+#[test]
+fn test_sync_merge_finds_conflicts_below_the_watermark() {
+    let mut pad = Pad { id: "p".to_string(), size: 100, next_offset: Some(0), ..Pad::default() };
+    pad.record_use((0..10).into());
+    let outcome = sync::merge(&mut pad, &[(0..10).into(), (5..15).into(), (30..40).into()]).expect("Ranges lie inside the pad");
+    assert_eq!(outcome.conflicts, vec![5..10]);
+    assert_eq!(outcome.skipped, vec![15..30]);
+    assert_eq!(pad.next_offset, Some(40));
+}
+
+#[test]
+fn test_loading_raises_the_watermark_over_the_history() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let pad = r#"{"id": "p", "file_name": "p.pad", "size": 100, "is_fully_used": false, "next_offset": 10,
+        "used": [[60, 70]], "used_segments": [{"start": 30, "end": 40}]}"#;
+    std::fs::write(temp_dir.path().join("vault_state.json"), format!(r#"{{"pads": {{"p": {pad}}}}}"#)).expect("Failed to write state");
+    let state = state_manager::load_state(temp_dir.path()).expect("Failed to load state");
+    let pad = &state.pads["p"];
+    assert_eq!(pad.next_offset, Some(70));
+    assert!(pad.used.is_empty());
+    assert_eq!(pad.find_available_segment(1), Some(70));
+}
+
+#[test]
+fn test_cli_watermark_pads() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let vault = temp_dir.path().join("vault");
+    otp(&vault).arg("vault").arg("init").assert().success();
+    let output = otp(&vault).arg("pad").arg("generate").arg("--size").arg("1").arg("--watermark").output().expect("Failed to generate pad");
+    let pad_id = String::from_utf8(output.stdout).expect("Failed to read pad id from stdout").trim().to_string();
+
+    for name in ["first.bin", "second.bin"] {
+        otp(&vault).arg("encrypt").arg("--text").arg("hello").arg("-o").arg(temp_dir.path().join(name)).assert().success();
+    }
+    let state = state_manager::load_state(&vault).expect("Failed to load state");
+    let pad = &state.pads[&pad_id];
+    assert!(pad.used_segments.len() == 2 && pad.next_offset.is_some_and(|watermark| watermark > 0));
+    // Both messages still decrypt
+    otp(&vault)
+        .arg("decrypt").arg("--input").arg(temp_dir.path().join("first.bin"))
+        .arg("--metadata").arg(temp_dir.path().join("first.bin.metadata.json")).arg("-o").arg("-")
+        .assert().success().stdout("hello");
+
+    otp(&vault)
+        .arg("pad").arg("show").arg(&pad_id)
+        .assert().success()
+        .stdout(predicate::str::contains("Allocation:  watermark at byte").and(predicate::str::contains("encrypt")));
+    // Burning above the watermark warns about and logs the free bytes it skips
+    let watermark = pad.next_offset.unwrap();
+    otp(&vault)
+        .arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("500..510").arg("--reason").arg("test")
+        .assert().success()
+        .stderr(predicate::str::contains(format!("{} free byte(s) in {watermark}..500", 500 - watermark)));
+    let log = otp_core::audit::read(&vault).expect("Failed to read log");
+    assert!(log.iter().any(|entry| entry.record.detail.as_deref() == Some(format!("watermark skipped {} free byte(s)", 500 - watermark).as_str())));
+    otp(&vault).arg("pad").arg("pair").arg(&pad_id).arg("--role").arg("initiator").assert().code(7);
+    otp(&vault).arg("pad").arg("mode").arg(&pad_id).arg("free-ranges").assert().success();
+    otp(&vault).arg("pad").arg("burn").arg(&pad_id).arg("--range").arg("1000..2000").arg("--reason").arg("test").assert().success();
+
+    let output = otp(&vault).arg("--output-format").arg("json").arg("pad").arg("mode").arg(&pad_id).arg("watermark").output().expect("Failed to switch mode");
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).expect("stdout is one JSON document");
+    assert_eq!(document["result"]["pad"]["next_offset"], 2000);
+    // Everything up to the burned 500..510 was used or skipped, so 510..1000 is given up
+    assert_eq!(document["result"]["forfeited_bytes"], 1000 - 510);
+    otp(&vault).arg("pad").arg("mode").arg("nope").arg("watermark").assert().code(3);
+}
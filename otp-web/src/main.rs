@@ -13,17 +13,16 @@
 
 use axum::{
     body::Body,
-    extract::{Multipart, State},
+    extract::{Multipart, Query, State},
     http::{header, StatusCode, Uri},
     response::{IntoResponse, Json, Redirect},
     routing::{delete, get, post},
     Router,
 };
 use local_ip_address::local_ip;
-use otp_core::{pad_generator, state_manager};
+use otp_core::{audit, config, pad_generator, pad_map, state_manager, thresholds};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::env;
 use std::fs;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -40,11 +39,14 @@ struct Asset;
 #[derive(Clone)]
 struct AppState {
     vault_path: PathBuf,
+    /// The pad size in megabytes used when a request does not name one.
+    pad_size: usize,
 }
 
 #[derive(Deserialize)]
 struct GeneratePadRequest {
-    size: usize,
+    #[serde(default)]
+    size: Option<usize>,
     count: u32,
 }
 
@@ -72,9 +74,12 @@ struct MarkUsedRequest {
 #[tokio::main]
 async fn main() {
     let port = 3000;
-    // Set up the vault path from an environment variable or use a default.
-    let vault_path =
-        env::var("OTP_VAULT_PATH").map_or_else(|_| PathBuf::from("./.otp_vault"), PathBuf::from);
+    // The vault and pad size come from OTP_VAULT_PATH, OTP_PROFILE and the config file shared with otp-cli.
+    let config = config::load(None, config::Settings::default()).unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    });
+    let vault_path = config.vault;
 
     println!("Using vault at: {}", vault_path.display());
 
@@ -101,7 +106,7 @@ async fn main() {
         println!("Vault initialized successfully.");
     }
 
-    let app_state = Arc::new(AppState { vault_path });
+    let app_state = Arc::new(AppState { vault_path, pad_size: config.pad_size });
 
     // Build the Axum router.
     let app = Router::new()
@@ -111,6 +116,7 @@ async fn main() {
         .route("/api/pads/generate", post(generate_pads_handler))
         .route("/api/pads/upload", post(upload_pads_handler))
         .route("/api/pads/:pad_id/download", get(download_pad_handler))
+        .route("/api/pads/:pad_id/map", get(pad_map_handler))
         .route("/api/pads/request_segment", post(request_segment_handler))
         .route("/api/pads/mark_used", post(mark_used_handler))
         .route("/api/vault/clear", post(clear_vault_handler))
@@ -166,6 +172,17 @@ async fn get_vault_status(
     let total_storage_bytes: usize = vault_state.pads.values().map(|p| p.size).sum();
     let total_used_bytes: usize = vault_state.pads.values().map(state_manager::Pad::total_used_bytes).sum();
     let remaining_bytes = total_storage_bytes.saturating_sub(total_used_bytes);
+    let material = thresholds::Material::of(&vault_state, |_| true);
+    let warnings: Vec<Value> = vault_state
+        .thresholds
+        .check(material)
+        .iter()
+        .map(|warning| {
+            let mut value = json!(warning);
+            value["message"] = json!(warning.to_string());
+            value
+        })
+        .collect();
 
     let response = json!({
         "vault_path": state.vault_path,
@@ -175,6 +192,9 @@ async fn get_vault_status(
         "total_storage_bytes": total_storage_bytes,
         "total_used_bytes": total_used_bytes,
         "remaining_bytes": remaining_bytes,
+        "material": material,
+        "thresholds": vault_state.thresholds,
+        "warnings": warnings,
     });
 
     (StatusCode::OK, Json(response))
@@ -199,7 +219,7 @@ async fn generate_pads_handler(
         let pad_id = Uuid::new_v4().to_string();
         let file_name = format!("{pad_id}.pad");
         let pad_path = state.vault_path.join("pads/available").join(&file_name);
-        let size_in_bytes = payload.size * 1024 * 1024;
+        let size_in_bytes = payload.size.unwrap_or(state.pad_size) * 1024 * 1024;
 
         let Some(pad_path_str) = pad_path.to_str() else {
             return (
@@ -210,6 +230,7 @@ async fn generate_pads_handler(
         match pad_generator::generate_pad(pad_path_str, size_in_bytes) {
             Ok(()) => {
                 vault_state.add_pad(pad_id.clone(), file_name, size_in_bytes);
+                audit_log(&state.vault_path, audit_record(audit::Operation::Generate).pad(&pad_id).range(0..size_in_bytes));
                 new_pad_ids.push(pad_id);
             }
             Err(e) => {
@@ -227,11 +248,15 @@ async fn generate_pads_handler(
             Json(json!({ "error": format!("Failed to save state: {}", e) })),
         );
     }
+    replenish(&state.vault_path);
     (StatusCode::CREATED, Json(json!({ "pad_ids": new_pad_ids })))
 }
 
+/// Lists the pads, filtered and sorted by the `label`, `available_only` and `sort`
+/// query parameters.
 async fn list_pads_handler(
     State(state): State<Arc<AppState>>,
+    Query(query): Query<state_manager::PadQuery>,
 ) -> (StatusCode, Json<Value>) {
     let vault_state = match state_manager::load_state(&state.vault_path) {
         Ok(vs) => vs,
@@ -242,7 +267,15 @@ async fn list_pads_handler(
             );
         }
     };
-    let pads: Vec<&state_manager::Pad> = vault_state.pads.values().collect();
+    let pads: Vec<Value> = vault_state
+        .query(&query)
+        .into_iter()
+        .map(|pad| {
+            let mut value = json!(pad);
+            value["status"] = json!(pad.status());
+            value
+        })
+        .collect();
     (StatusCode::OK, Json(json!(pads)))
 }
 
@@ -273,6 +306,8 @@ async fn delete_pad_handler(
                         Json(json!({ "error": format!("Failed to save state: {}", e) })),
                     );
                 }
+                audit_log(&state.vault_path, audit_record(audit::Operation::Delete).pad(&pad_id));
+                replenish(&state.vault_path);
                 (StatusCode::OK, Json(json!({ "message": "Pad deleted successfully" })))
             }
             Err(e) => {
@@ -284,7 +319,9 @@ async fn delete_pad_handler(
                             Json(json!({ "error": format!("Failed to save state: {}", e) })),
                         );
                     }
-                     (StatusCode::OK, Json(json!({ "message": "Pad file not found, but removed from state" })))
+                    audit_log(&state.vault_path, audit_record(audit::Operation::Delete).pad(&pad_id).detail("pad file was missing"));
+                    replenish(&state.vault_path);
+                    (StatusCode::OK, Json(json!({ "message": "Pad file not found, but removed from state" })))
                 } else {
                     (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": format!("Failed to delete pad file: {}", e) })))
                 }
@@ -320,6 +357,9 @@ async fn request_segment_handler(
     };
 
     if let Some(pad) = vault_state.pads.get(&pad_id_to_use) {
+        if let Some(reason) = pad.encrypt_refusal(otp_core::timestamp::now()) {
+            return (StatusCode::CONFLICT, Json(json!({ "error": format!("Pad cannot encrypt because {reason}") })));
+        }
         if let Some(start) = pad.find_available_segment(payload.length) {
             let pad_dir = if pad.is_fully_used { "used" } else { "available" };
             let pad_path = state.vault_path.join("pads").join(pad_dir).join(&pad.file_name);
@@ -361,8 +401,9 @@ async fn mark_used_handler(
             );
         }
     };
+    let counterparty = vault_state.contact_for_pad(&payload.pad_id).map(|contact| contact.name.clone());
     if let Some(pad) = vault_state.pads.get_mut(&payload.pad_id) {
-        pad.used_segments.push(state_manager::UsedSegment { start: payload.start, end: payload.end });
+        let skipped = pad.record_use(state_manager::UsedSegment::consumed(payload.start..payload.end, state_manager::SegmentOperation::Encrypt).counterparty(counterparty));
         pad.is_fully_used = pad.total_used_bytes() >= pad.size;
         let is_full = pad.is_fully_used;
         let file_name_clone = pad.file_name.clone();
@@ -374,24 +415,56 @@ async fn mark_used_handler(
             );
         }
 
+        audit_log(
+            &state.vault_path,
+            audit_record(audit::Operation::Encrypt).pad(&payload.pad_id).range(payload.start..payload.end),
+        );
+        if !skipped.is_empty() {
+            audit_log(
+                &state.vault_path,
+                audit_record(audit::Operation::Burn).pad(&payload.pad_id).detail(format!("watermark skipped {} free byte(s)", skipped.len())).range(skipped),
+            );
+        }
+
         if is_full {
-             let old_pad_path = state.vault_path.join("pads/available").join(&file_name_clone);
+            let old_pad_path = state.vault_path.join("pads/available").join(&file_name_clone);
             let used_pad_path = state.vault_path.join("pads/used").join(&file_name_clone);
-            if old_pad_path.exists() {
-                if let Err(e) = fs::rename(old_pad_path, used_pad_path) {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(json!({ "error": format!("Failed to move used pad: {e}") })),
-                    );
-                }
+            if old_pad_path.exists()
+                && let Err(e) = fs::rename(old_pad_path, used_pad_path)
+            {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "error": format!("Failed to move used pad: {e}") })),
+                );
             }
         }
+        replenish(&state.vault_path);
         (StatusCode::OK, Json(json!({ "message": "Pad segment marked as used" })))
     } else {
         (StatusCode::NOT_FOUND, Json(json!({ "error": "Pad not found" })))
     }
 }
 
+/// Returns where a pad's free bytes are: its free and usable ranges and largest gap.
+async fn pad_map_handler(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(pad_id): axum::extract::Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let vault_state = match state_manager::load_state(&state.vault_path) {
+        Ok(vs) => vs,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": format!("Failed to load vault state: {}", e) })),
+            );
+        }
+    };
+    vault_state.pads.get(&pad_id).map_or_else(
+        || (StatusCode::NOT_FOUND, Json(json!({ "error": "Pad not found" }))),
+        |pad| (StatusCode::OK, Json(json!(pad_map::PadMap::of(pad)))),
+    )
+}
+
 async fn download_pad_handler(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(pad_id): axum::extract::Path<String>,
@@ -410,6 +483,10 @@ async fn download_pad_handler(
             fs::read(&pad_path).map_or_else(
                 |_err| (StatusCode::NOT_FOUND, "Pad file not found").into_response(),
                 |data| {
+                    audit_log(
+                        &state.vault_path,
+                        audit_record(audit::Operation::Export).pad(&pad.id).range(0..data.len()).file(std::path::Path::new(&pad.file_name)).detail("download"),
+                    );
                     let headers = [
                         (header::CONTENT_TYPE, "application/octet-stream".to_string()),
                         (
@@ -470,7 +547,11 @@ async fn upload_pads_handler(
 
         let pad_path = state.vault_path.join("pads/available").join(&file_name);
         if fs::write(&pad_path, &data).is_ok() {
-            vault_state.add_pad(pad_id.clone(), file_name, size_in_bytes);
+            audit_log(
+                &state.vault_path,
+                audit_record(audit::Operation::Import).pad(&pad_id).range(0..size_in_bytes).file(&pad_path).detail("upload"),
+            );
+            vault_state.import_pad(pad_id.clone(), file_name, size_in_bytes);
             imported_pads.push(pad_id);
         }
     }
@@ -481,6 +562,7 @@ async fn upload_pads_handler(
             Json(json!({ "error": format!("Failed to save state: {}", e) })),
         );
     }
+    replenish(&state.vault_path);
     (StatusCode::OK, Json(json!({ "imported_pads": imported_pads })))
 }
 
@@ -513,6 +595,9 @@ async fn static_path(uri: Uri) -> impl IntoResponse {
 async fn clear_vault_handler(
     State(state): State<Arc<AppState>>,
 ) -> (StatusCode, Json<Value>) {
+    // The audit log outlives the vault contents it describes
+    let log_path = state.vault_path.join(audit::LOG_FILE);
+    let log = fs::read(&log_path).ok();
     if let Err(e) = fs::remove_dir_all(&state.vault_path) {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -544,6 +629,37 @@ async fn clear_vault_handler(
             Json(json!({ "error": format!("Failed to save initial state: {}", e) })),
         );
     }
+    if let Some(log) = log
+        && let Err(e) = fs::write(&log_path, log)
+    {
+        eprintln!("Failed to restore the audit log: {e}");
+    }
+    audit_log(&state.vault_path, audit_record(audit::Operation::Clear));
     (StatusCode::OK, Json(json!({ "message": "Vault cleared successfully" })))
 }
 
+/// Starts an audit record of an operation performed through the web interface.
+fn audit_record(op: audit::Operation) -> audit::Record {
+    audit::Record::new(audit::Interface::Web, op)
+}
+
+/// Appends to the vault's audit log. A failure is reported but does not undo the operation.
+fn audit_log(vault_path: &std::path::Path, record: audit::Record) {
+    if let Err(e) = audit::append(vault_path, record) {
+        eprintln!("Failed to write the audit log: {e}");
+    }
+}
+
+/// Tops up the vault's local pads if its replenishment policy asks for it.
+fn replenish(vault_path: &std::path::Path) {
+    let result = state_manager::load_state(vault_path)
+        .and_then(|mut vault_state| thresholds::after_operation(vault_path, &mut vault_state, audit::Interface::Web));
+    match result {
+        Ok(report) => {
+            for pad_id in report.generated {
+                println!("Generated local pad '{pad_id}' because pad material ran low.");
+            }
+        }
+        Err(e) => eprintln!("Failed to replenish pads: {e}"),
+    }
+}
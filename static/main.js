@@ -18,6 +18,46 @@ const notify = (message, type = 'info') => {
     setTimeout(() => notification.remove(), 3000);
 };
 
+
+// --- Payload Envelope ---
+// Layout shared with otp-core's envelope module:
+// "OTPFILE1" | header length (u32, big-endian) | header JSON | payload | SHA-256(payload)
+const ENVELOPE_MAGIC = new TextEncoder().encode('OTPFILE1');
+
+async function wrapPayload(payload, header) {
+    const headerBytes = new TextEncoder().encode(JSON.stringify(header));
+    const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', payload));
+    const wrapped = new Uint8Array(ENVELOPE_MAGIC.length + 4 + headerBytes.length + payload.length + digest.length);
+    let offset = 0;
+    for (const part of [ENVELOPE_MAGIC, new Uint8Array(4), headerBytes, payload, digest]) {
+        wrapped.set(part, offset);
+        offset += part.length;
+    }
+    new DataView(wrapped.buffer).setUint32(ENVELOPE_MAGIC.length, headerBytes.length);
+    return wrapped;
+}
+
+// Returns { header, payload }; data encrypted without an envelope comes back with a null header.
+async function unwrapPayload(data) {
+    if (data.length < ENVELOPE_MAGIC.length || !ENVELOPE_MAGIC.every((b, i) => data[i] === b)) {
+        return { header: null, payload: data };
+    }
+    const headerLength = new DataView(data.buffer, data.byteOffset).getUint32(ENVELOPE_MAGIC.length);
+    const headerStart = ENVELOPE_MAGIC.length + 4;
+    const payloadStart = headerStart + headerLength;
+    const payloadEnd = data.length - 32;
+    if (payloadEnd < payloadStart) throw new Error("Inner header is truncated.");
+    const header = JSON.parse(new TextDecoder().decode(data.subarray(headerStart, payloadStart)));
+    const payload = data.subarray(payloadStart, payloadEnd);
+    // A sequence-numbered header is covered by the digest together with the payload.
+    const digested = header.seq == null ? payload : data.subarray(headerStart, payloadEnd);
+    const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', digested));
+    if (!digest.every((b, i) => data[payloadEnd + i] === b)) {
+        throw new Error("Plaintext SHA-256 does not match; the message is corrupt or the wrong pad bytes were used.");
+    }
+    return { header, payload };
+}
+
 // --- API Functions ---
 async function getVaultStatus() {
     try {
@@ -56,6 +96,7 @@ async function listPads() {
                     <td>${(pad.size / 1024 / 1024).toFixed(2)}</td>
                     <td>${usedPercent}%</td>
                     <td>
+                        <button class="map-pad-btn">Map</button>
                         <button class="download-pad-btn">Download</button>
                         <button class="delete-pad-btn">Delete</button>
                     </td>
@@ -67,11 +108,40 @@ async function listPads() {
 
         document.querySelectorAll('.delete-pad-btn').forEach(b => b.addEventListener('click', () => deletePad(b.closest('tr').dataset.padId)));
         document.querySelectorAll('.download-pad-btn').forEach(b => b.addEventListener('click', () => downloadPad(b.closest('tr').dataset.padId)));
+        document.querySelectorAll('.map-pad-btn').forEach(b => b.addEventListener('click', () => togglePadMap(b.closest('tr').dataset.padId)));
     } catch (error) {
        notify('Error loading pads.', 'error');
     }
 }
 
+// Shows or hides a bar below the pad's row: used bytes in red, free ranges in green.
+async function togglePadMap(padId) {
+    const row = document.querySelector(`tr[data-pad-id="${padId}"]`);
+    if (row.nextElementSibling && row.nextElementSibling.classList.contains('pad-map-row')) {
+        row.nextElementSibling.remove();
+        return;
+    }
+    try {
+        const response = await fetch(`/api/pads/${padId}/map`);
+        if (!response.ok) throw new Error(`HTTP error! status: ${response.status}`);
+        const map = await response.json();
+        const percent = bytes => (map.size > 0 ? bytes / map.size * 100 : 0);
+        const gaps = map.free_ranges.map(r =>
+            `<span title="${r.start}..${r.end}" style="position:absolute;left:${percent(r.start)}%;width:${percent(r.end - r.start)}%;height:100%;background:#8c8"></span>`
+        ).join('');
+        row.insertAdjacentHTML('afterend', `
+            <tr class="pad-map-row">
+                <td colspan="4">
+                    <div style="position:relative;height:12px;background:#c66">${gaps}</div>
+                    <small>${map.free_bytes} bytes free in ${map.free_ranges.length} range(s); largest gap ${map.largest_gap} bytes</small>
+                </td>
+            </tr>
+        `);
+    } catch (error) {
+        notify('Error loading pad map.', 'error');
+    }
+}
+
 // --- Pad Management ---
 async function generatePad() {
     const size = parseInt(prompt("Enter pad size in MB:", "1"));
@@ -216,7 +286,8 @@ async function handleEncryptText() {
     const plaintext = new TextEncoder().encode(text);
 
     try {
-        const { ciphertext, metadata } = await encrypt(plaintext);
+        const header = { name: null, size: plaintext.length, mtime: null, mode: null };
+        const { ciphertext, metadata } = await encrypt(await wrapPayload(plaintext, header));
         const payload = { ciphertext_base64: arrToBase64(ciphertext), metadata };
         document.getElementById('decrypt-text-input').value = JSON.stringify(payload, null, 2);
         notify("Text encrypted successfully.");
@@ -232,8 +303,8 @@ async function handleDecryptText() {
     try {
         const { ciphertext_base64, metadata } = JSON.parse(text);
         const ciphertext = base64ToArr(ciphertext_base64);
-        const plaintext = await decrypt(ciphertext, metadata);
-        document.getElementById('encrypt-text-input').value = new TextDecoder().decode(plaintext);
+        const { payload } = await unwrapPayload(await decrypt(ciphertext, metadata));
+        document.getElementById('encrypt-text-input').value = new TextDecoder().decode(payload);
         notify("Text decrypted successfully.");
     } catch (error) {
         notify(`Decryption failed: ${error.message}`, 'error');
@@ -246,7 +317,9 @@ async function handleEncryptFile() {
 
     const plaintext = new Uint8Array(await file.arrayBuffer());
     try {
-        const { ciphertext, metadata } = await encrypt(plaintext);
+        // The original name travels encrypted; browsers expose no permission bits.
+        const header = { name: file.name, size: plaintext.length, mtime: Math.floor(file.lastModified / 1000), mode: null };
+        const { ciphertext, metadata } = await encrypt(await wrapPayload(plaintext, header));
         const encryptedFileBlob = new Blob([ciphertext], { type: 'application/octet-stream' });
         const metadataBlob = new Blob([JSON.stringify(metadata, null, 2)], { type: 'application/json' });
         downloadBlob(encryptedFileBlob, `${file.name}.enc`);
@@ -277,9 +350,10 @@ async function handleDecryptFile() {
             metadata = { pad_id, start, length };
         }
         
-        const plaintext = await decrypt(ciphertext, metadata);
-        const decryptedFileBlob = new Blob([plaintext], { type: 'application/octet-stream' });
-        const originalName = encryptedFile.name.endsWith('.enc') ? encryptedFile.name.slice(0, -4) : `${encryptedFile.name}.dec`;
+        const { header, payload } = await unwrapPayload(await decrypt(ciphertext, metadata));
+        const decryptedFileBlob = new Blob([payload], { type: 'application/octet-stream' });
+        const fallbackName = encryptedFile.name.endsWith('.enc') ? encryptedFile.name.slice(0, -4) : `${encryptedFile.name}.dec`;
+        const originalName = header?.name ? header.name.split(/[\\/]/).pop() : fallbackName;
         downloadBlob(decryptedFileBlob, originalName);
         notify("File decrypted successfully.");
     } catch (error) {